@@ -0,0 +1,410 @@
+//! Machine-readable dumps of the parse tree for `lox ast`.
+//!
+//! Unlike the `Display` impls used by `lox parse`, every node keeps its kind,
+//! its fields by name and, where the parser kept a token for it, its line.
+
+use crate::json::Json;
+use crate::parser::{Arena, Declaration, Expr, ExprId, Object, Program, Statement};
+use crate::token::Token;
+use crate::Format;
+
+/// A parsed program rendered in `format`, as JSON unless it is DOT.
+pub fn dump(program: &Program, format: Format) -> String {
+    let program = node(
+        "Program",
+        None,
+        vec![(
+            "declarations",
+            declaration_list(&program.arena, &program.declarations),
+        )],
+    );
+    match format {
+        Format::Dot => {
+            let mut lines =
+                vec!["digraph ast {".to_string(), "  node [shape=box];".into()];
+            dot_node(&program, &mut lines, &mut 0);
+            lines.push("}".into());
+            lines.join("\n")
+        }
+        _ => program.to_string(),
+    }
+}
+
+/// Writes `value`, a node, and everything under it as DOT statements and
+/// returns its id. The label lists the kind, the scalar fields and the
+/// line; edges to child nodes are labeled with the field holding them.
+fn dot_node(value: &Json, lines: &mut Vec<String>, next_id: &mut usize) -> usize {
+    let id = *next_id;
+    *next_id += 1;
+    let Json::Object(fields) = value else {
+        unreachable!("only nodes are drawn")
+    };
+    let mut label = vec![];
+    let mut line = None;
+    let mut edges = vec![];
+    for (name, field) in fields {
+        match (*name, field) {
+            ("type", Json::String(kind)) => label.insert(0, kind.clone()),
+            ("line", line_number) => line = Some(format!("line {}", line_number)),
+            (_, Json::Null) => {}
+            (name, Json::Object(_)) => edges.push((name.to_string(), field)),
+            (name, Json::Array(elements)) => edges.extend(
+                elements
+                    .iter()
+                    .enumerate()
+                    .map(|(index, element)| (format!("{}[{}]", name, index), element)),
+            ),
+            (name, scalar) => label.push(format!("{} = {}", name, scalar)),
+        }
+    }
+    label.extend(line);
+    let label: Vec<String> = label.iter().map(|part| dot_escape(part)).collect();
+    lines.push(format!("  n{} [label=\"{}\"];", id, label.join("\\n")));
+    for (name, child) in edges {
+        let child_id = dot_node(child, lines, next_id);
+        lines.push(format!(
+            "  n{} -> n{} [label=\"{}\"];",
+            id,
+            child_id,
+            dot_escape(&name)
+        ));
+    }
+    id
+}
+
+fn dot_escape(text: &str) -> String {
+    text.replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+/// A node of kind `kind`, with a `line` field when the line is known.
+fn node(
+    kind: &'static str,
+    line: Option<usize>,
+    fields: Vec<(&'static str, Json)>,
+) -> Json {
+    let mut all = vec![("type", Json::String(kind.into()))];
+    if let Some(line) = line {
+        all.push(("line", Json::Number(line as f64)));
+    }
+    all.extend(fields);
+    Json::Object(all)
+}
+
+fn lexeme(token: &Token) -> Json {
+    Json::String(String::from_utf8_lossy(&token.lexeme).into())
+}
+
+fn optional(value: Option<Json>) -> Json {
+    value.unwrap_or(Json::Null)
+}
+
+fn declaration_list(arena: &Arena, declarations: &[Declaration]) -> Json {
+    Json::Array(
+        declarations
+            .iter()
+            .map(|declaration| self::declaration(arena, declaration))
+            .collect(),
+    )
+}
+
+fn declaration(arena: &Arena, declaration: &Declaration) -> Json {
+    match declaration {
+        Declaration::Var {
+            keyword,
+            target,
+            initializer,
+            constant,
+            doc,
+        } => node(
+            if *constant { "Const" } else { "Var" },
+            Some(keyword.line),
+            vec![
+                ("target", expr(arena, *target)),
+                (
+                    "initializer",
+                    optional(initializer.map(|value| expr(arena, value))),
+                ),
+                ("doc", optional(doc.clone().map(Json::String))),
+            ],
+        ),
+        Declaration::Statement(stmt) => statement(arena, stmt),
+    }
+}
+
+fn statement(arena: &Arena, statement: &Statement) -> Json {
+    match statement {
+        Statement::ExprStmt(value) => {
+            node("Expression", None, vec![("value", expr(arena, *value))])
+        }
+        Statement::PrintStmt(value) => {
+            node("Print", None, vec![("value", expr(arena, *value))])
+        }
+        Statement::EPrintStmt(value) => {
+            node("EPrint", None, vec![("value", expr(arena, *value))])
+        }
+        Statement::IfStmt(if_) => node(
+            "If",
+            Some(if_.keyword.line),
+            vec![
+                ("condition", expr(arena, if_.condition)),
+                ("then", self::statement(arena, &if_.then_branch)),
+                (
+                    "else",
+                    optional(
+                        if_.else_branch
+                            .as_deref()
+                            .map(|branch| self::statement(arena, branch)),
+                    ),
+                ),
+            ],
+        ),
+        Statement::WhileStmt(condition) => {
+            node("While", None, vec![("condition", expr(arena, *condition))])
+        }
+        Statement::Block(body) => {
+            node("Block", None, vec![("body", declaration_list(arena, body))])
+        }
+        Statement::Switch(switch) => node(
+            "Switch",
+            Some(switch.keyword.line),
+            vec![
+                ("value", expr(arena, switch.value)),
+                (
+                    "cases",
+                    Json::Array(
+                        switch
+                            .cases
+                            .iter()
+                            .map(|(values, body)| case(arena, values, body))
+                            .collect(),
+                    ),
+                ),
+                (
+                    "default",
+                    optional(
+                        switch
+                            .default
+                            .as_deref()
+                            .map(|body| declaration_list(arena, body)),
+                    ),
+                ),
+            ],
+        ),
+        Statement::ForIn {
+            keyword,
+            variable,
+            iterable,
+            body,
+        } => node(
+            "ForIn",
+            Some(keyword.line),
+            vec![
+                ("variable", Json::String(variable.to_string())),
+                ("iterable", expr(arena, *iterable)),
+                ("body", self::statement(arena, body)),
+            ],
+        ),
+        Statement::Import { keyword, path } => node(
+            "Import",
+            Some(keyword.line),
+            vec![("path", Json::String(path.clone()))],
+        ),
+        Statement::Try(try_) => node(
+            "Try",
+            None,
+            vec![
+                ("body", declaration_list(arena, &try_.body)),
+                (
+                    "catch",
+                    optional(try_.catch.as_ref().map(|(name, body)| {
+                        node(
+                            "Catch",
+                            None,
+                            vec![
+                                ("name", Json::String(name.to_string())),
+                                ("body", declaration_list(arena, body)),
+                            ],
+                        )
+                    })),
+                ),
+                (
+                    "finally",
+                    optional(
+                        try_.finally
+                            .as_deref()
+                            .map(|body| declaration_list(arena, body)),
+                    ),
+                ),
+            ],
+        ),
+        Statement::Throw { keyword, value } => node(
+            "Throw",
+            Some(keyword.line),
+            vec![("value", expr(arena, *value))],
+        ),
+        Statement::DoWhile { body, condition } => node(
+            "DoWhile",
+            None,
+            vec![
+                ("body", self::statement(arena, body)),
+                ("condition", expr(arena, *condition)),
+            ],
+        ),
+    }
+}
+
+fn case(arena: &Arena, values: &[ExprId], body: &[Declaration]) -> Json {
+    node(
+        "Case",
+        None,
+        vec![
+            (
+                "values",
+                Json::Array(values.iter().map(|&value| expr(arena, value)).collect()),
+            ),
+            ("body", declaration_list(arena, body)),
+        ],
+    )
+}
+
+fn literal(value: &Object) -> Json {
+    match value {
+        Object::Number(number) => Json::Number(*number),
+        Object::String(string) => Json::String(string.to_string()),
+        Object::Boolean(boolean) => Json::Bool(*boolean),
+        Object::Nil => Json::Null,
+        value => Json::String(value.to_string()),
+    }
+}
+
+fn expr(arena: &Arena, id: ExprId) -> Json {
+    let expr = |id: &ExprId| self::expr(arena, *id);
+    match &arena[id] {
+        Expr::Binary {
+            left,
+            operator,
+            right,
+        } => node(
+            "Binary",
+            Some(operator.line),
+            vec![
+                ("operator", lexeme(operator)),
+                ("left", expr(left)),
+                ("right", expr(right)),
+            ],
+        ),
+        Expr::Grouping { expression } => {
+            node("Grouping", None, vec![("expression", expr(expression))])
+        }
+        Expr::Literal { value, .. } => {
+            node("Literal", None, vec![("value", literal(value))])
+        }
+        Expr::Unary { operator, right } => node(
+            "Unary",
+            Some(operator.line),
+            vec![("operator", lexeme(operator)), ("right", expr(right))],
+        ),
+        Expr::Variable {
+            identifier, line, ..
+        } => node(
+            "Variable",
+            Some(*line),
+            vec![("name", Json::String(identifier.to_string()))],
+        ),
+        Expr::Assign {
+            identifier,
+            line,
+            value,
+            ..
+        } => node(
+            "Assign",
+            Some(*line),
+            vec![
+                ("name", Json::String(identifier.to_string())),
+                ("value", expr(value)),
+            ],
+        ),
+        Expr::Conditional {
+            condition,
+            then_branch,
+            else_branch,
+        } => node(
+            "Conditional",
+            None,
+            vec![
+                ("condition", expr(condition)),
+                ("then", expr(then_branch)),
+                ("else", expr(else_branch)),
+            ],
+        ),
+        Expr::Call {
+            callee,
+            paren,
+            arguments,
+        } => node(
+            "Call",
+            Some(paren.line),
+            vec![
+                ("callee", expr(callee)),
+                (
+                    "arguments",
+                    Json::Array(arguments.iter().map(expr).collect()),
+                ),
+            ],
+        ),
+        Expr::List { elements } => node(
+            "List",
+            None,
+            vec![("elements", Json::Array(elements.iter().map(expr).collect()))],
+        ),
+        Expr::Map { brace, entries } => node(
+            "Map",
+            Some(brace.line),
+            vec![(
+                "entries",
+                Json::Array(
+                    entries
+                        .iter()
+                        .map(|(key, value)| {
+                            node(
+                                "Entry",
+                                None,
+                                vec![("key", expr(key)), ("value", expr(value))],
+                            )
+                        })
+                        .collect(),
+                ),
+            )],
+        ),
+        Expr::Index {
+            object,
+            bracket,
+            index,
+        } => node(
+            "Index",
+            Some(bracket.line),
+            vec![("object", expr(object)), ("index", expr(index))],
+        ),
+        Expr::IndexSet {
+            object,
+            bracket,
+            index,
+            operator,
+            value,
+        } => node(
+            "IndexSet",
+            Some(bracket.line),
+            vec![
+                ("operator", lexeme(operator)),
+                ("object", expr(object)),
+                ("index", expr(index)),
+                ("value", expr(value)),
+            ],
+        ),
+        Expr::Spread { ellipsis, value } => {
+            node("Spread", Some(ellipsis.line), vec![("value", expr(value))])
+        }
+    }
+}
@@ -0,0 +1,380 @@
+//! Command-line parsing for the interpreter binary.
+//!
+//! Commands and flags are described by the tables below, which drive both
+//! the parser and `--help`.
+
+/// Exit code for a malformed command line, as in BSD's `sysexits.h`.
+pub const USAGE_EXIT_CODE: i32 = 64;
+
+/// Exit code for a program file that cannot be opened.
+pub const NO_INPUT_EXIT_CODE: i32 = 66;
+
+/// Exit code for a program that cannot be read once opened, stdin included.
+pub const IO_ERROR_EXIT_CODE: i32 = 74;
+
+struct Command {
+    name: &'static str,
+    summary: &'static str,
+    /// Whether the command reads a program; `grammar` does not.
+    takes_file: bool,
+}
+
+const COMMANDS: [Command; 13] = [
+    Command {
+        name: "tokenize",
+        summary: "Print the tokens of the program",
+        takes_file: true,
+    },
+    Command {
+        name: "parse",
+        summary: "Print the parsed program",
+        takes_file: true,
+    },
+    Command {
+        name: "ast",
+        summary: "Dump the parse tree as JSON or DOT",
+        takes_file: true,
+    },
+    Command {
+        name: "check",
+        summary: "Report syntax errors without running; a directory checks each file",
+        takes_file: true,
+    },
+    Command {
+        name: "fmt",
+        summary: "Print the program as canonical source; a directory is rewritten",
+        takes_file: true,
+    },
+    Command {
+        name: "minify",
+        summary: "Print the program with comments and spacing removed",
+        takes_file: true,
+    },
+    Command {
+        name: "lint",
+        summary: "Warn about likely mistakes",
+        takes_file: true,
+    },
+    Command {
+        name: "highlight",
+        summary: "Print the source colored by token",
+        takes_file: true,
+    },
+    Command {
+        name: "evaluate",
+        summary: "Run the program, showing the value of each statement",
+        takes_file: true,
+    },
+    Command {
+        name: "run",
+        summary: "Run the program",
+        takes_file: true,
+    },
+    Command {
+        name: "bench",
+        summary: "Time repeated runs of the program",
+        takes_file: true,
+    },
+    Command {
+        name: "grammar",
+        summary: "Print the grammar in EBNF",
+        takes_file: false,
+    },
+    Command {
+        name: "help",
+        summary: "Print this help",
+        takes_file: false,
+    },
+];
+
+struct Flag {
+    name: &'static str,
+    /// Placeholder for the value the flag takes, if it takes one.
+    value: Option<&'static str>,
+    help: &'static str,
+}
+
+const FLAGS: [Flag; 24] = [
+    Flag {
+        name: "--extensions",
+        value: None,
+        help: "Enable the language extensions",
+    },
+    Flag {
+        name: "--asi",
+        value: None,
+        help: "Let a line break end a statement",
+    },
+    Flag {
+        name: "--propagate-nil",
+        value: None,
+        help: "Make arithmetic on nil evaluate to nil",
+    },
+    Flag {
+        name: "--comparison-warnings",
+        value: None,
+        help: "Warn about comparisons of mixed types",
+    },
+    Flag {
+        name: "--verbose-errors",
+        value: None,
+        help: "Explain runtime errors in more detail",
+    },
+    Flag {
+        name: "-D",
+        value: Some("NAME"),
+        help: "Define NAME for #ifdef",
+    },
+    Flag {
+        name: "--error-format",
+        value: Some("FORMAT"),
+        help: "Print errors as text (default), json lines or rich snippets",
+    },
+    Flag {
+        name: "--stdin-filename",
+        value: Some("PATH"),
+        help: "Treat the program read from - as the file PATH",
+    },
+    Flag {
+        name: "--dump-expanded",
+        value: None,
+        help: "Print the tokens after preprocessing",
+    },
+    Flag {
+        name: "--format",
+        value: Some("FORMAT"),
+        help: "Output format: json or dot for ast, json or csv for tokenize, \
+               ansi or html for highlight",
+    },
+    Flag {
+        name: "--check",
+        value: None,
+        help: "fmt: report unformatted files instead of printing",
+    },
+    Flag {
+        name: "--deny-warnings",
+        value: None,
+        help: "lint: fail when there are warnings",
+    },
+    Flag {
+        name: "--trace",
+        value: None,
+        help: "Print each statement and assignment as it runs",
+    },
+    Flag {
+        name: "--profile",
+        value: None,
+        help: "Print time spent per line and native function",
+    },
+    Flag {
+        name: "--coverage",
+        value: None,
+        help: "Write the lines that ran to lcov.info",
+    },
+    Flag {
+        name: "--max-output-bytes",
+        value: Some("N"),
+        help: "Stop the program's output after N bytes",
+    },
+    Flag {
+        name: "--max-statements",
+        value: Some("N"),
+        help: "Stop the program after N statements and loop iterations",
+    },
+    Flag {
+        name: "--max-depth",
+        value: Some("N"),
+        help: "Allow N levels of nesting at run time (default 2048)",
+    },
+    Flag {
+        name: "--max-loop-iterations",
+        value: Some("N"),
+        help: "Stop the program when a loop runs N times over",
+    },
+    Flag {
+        name: "--timeout",
+        value: Some("MS"),
+        help: "Stop the program after MS milliseconds",
+    },
+    Flag {
+        name: "--iterations",
+        value: Some("N"),
+        help: "bench: measured runs (default 10)",
+    },
+    Flag {
+        name: "--warmup",
+        value: Some("N"),
+        help: "bench: unmeasured runs first (default 1)",
+    },
+    Flag {
+        name: "--help",
+        value: None,
+        help: "Print this help (also -h)",
+    },
+    Flag {
+        name: "--version",
+        value: None,
+        help: "Print the version (also -V)",
+    },
+];
+
+/// A parsed command line.
+#[derive(Debug, PartialEq)]
+pub enum Cli {
+    Help,
+    Version,
+    Run(Invocation),
+}
+
+#[derive(Debug, PartialEq)]
+pub struct Invocation {
+    pub command: String,
+    /// `-` for standard input; `None` for commands that take no file.
+    pub filename: Option<String>,
+    /// Flags in order, with their values; `-DNAME` is `("-D", Some(NAME))`.
+    pub flags: Vec<(String, Option<String>)>,
+    /// Everything after `--`, passed to the program.
+    pub script_args: Vec<String>,
+}
+
+/// Parses the arguments after the program name. `Err` is a usage error.
+pub fn parse(args: impl IntoIterator<Item = String>) -> Result<Cli, String> {
+    let mut positional = vec![];
+    let mut flags = vec![];
+    let mut script_args = vec![];
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--" => script_args = args.by_ref().collect(),
+            "-h" | "--help" => return Ok(Cli::Help),
+            "-V" | "--version" => return Ok(Cli::Version),
+            "-" => positional.push(arg),
+            define if define.starts_with("-D") => {
+                if define.len() == 2 {
+                    return Err("-D needs a name, as in -DDEBUG".into());
+                }
+                flags.push(("-D".to_string(), Some(define[2..].to_string())));
+            }
+            flag if flag.starts_with('-') => {
+                let Some(known) = FLAGS.iter().find(|known| known.name == flag) else {
+                    return Err(format!("unknown flag '{}'", flag));
+                };
+                let value = match known.value {
+                    Some(placeholder) => Some(args.next().ok_or_else(|| {
+                        format!("{} needs a value: {} {}", flag, flag, placeholder)
+                    })?),
+                    None => None,
+                };
+                flags.push((arg, value));
+            }
+            _ => positional.push(arg),
+        }
+    }
+
+    let mut positional = positional.into_iter();
+    let Some(name) = positional.next() else {
+        return Err("missing command".into());
+    };
+    let Some(command) = COMMANDS.iter().find(|command| command.name == name) else {
+        return Err(format!("unknown command '{}'", name));
+    };
+    if command.name == "help" {
+        return Ok(Cli::Help);
+    }
+    let filename = if command.takes_file {
+        let filename = positional
+            .next()
+            .ok_or_else(|| format!("'{}' needs a file name, or - for stdin", name))?;
+        Some(filename)
+    } else {
+        None
+    };
+    if let Some(extra) = positional.next() {
+        return Err(format!(
+            "unexpected argument '{}'; pass program arguments after --",
+            extra
+        ));
+    }
+    Ok(Cli::Run(Invocation {
+        command: name,
+        filename,
+        flags,
+        script_args,
+    }))
+}
+
+/// The one-line synopsis shown with usage errors.
+pub fn usage(program: &str) -> String {
+    format!(
+        "Usage: {} <command> [options] <filename | -> [-- args...]",
+        program
+    )
+}
+
+/// The full `--help` text.
+pub fn help(program: &str) -> String {
+    let mut help = vec![usage(program), String::new(), "Commands:".into()];
+    for command in &COMMANDS {
+        help.push(format!("  {:<10} {}", command.name, command.summary));
+    }
+    help.push(String::new());
+    help.push("Options:".into());
+    for flag in &FLAGS {
+        let name = match flag.value {
+            // Short flags take their value attached, as in `-DNAME`.
+            Some(placeholder) if !flag.name.starts_with("--") => {
+                format!("{}{}", flag.name, placeholder)
+            }
+            Some(placeholder) => format!("{} {}", flag.name, placeholder),
+            None => flag.name.to_string(),
+        };
+        help.push(format!("  {:<26} {}", name, flag.help));
+    }
+    help.join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(line: &str) -> Vec<String> {
+        line.split_whitespace().map(String::from).collect()
+    }
+
+    #[test]
+    fn test_parse_splits_command_file_flags_and_script_args() {
+        assert_eq!(
+            parse(args(
+                "run --format json prog.lox -DDEBUG --extensions -- a b"
+            )),
+            Ok(Cli::Run(Invocation {
+                command: "run".into(),
+                filename: Some("prog.lox".into()),
+                flags: vec![
+                    ("--format".into(), Some("json".into())),
+                    ("-D".into(), Some("DEBUG".into())),
+                    ("--extensions".into(), None),
+                ],
+                script_args: vec!["a".into(), "b".into()],
+            }))
+        );
+        assert_eq!(parse(args("grammar --version")), Ok(Cli::Version));
+        assert_eq!(
+            parse(args("run")),
+            Err("'run' needs a file name, or - for stdin".to_string())
+        );
+        assert_eq!(
+            parse(args("fmt - --stdin-filename src/a.lox")),
+            Ok(Cli::Run(Invocation {
+                command: "fmt".into(),
+                filename: Some("-".into()),
+                flags: vec![("--stdin-filename".into(), Some("src/a.lox".into()))],
+                script_args: vec![],
+            }))
+        );
+        assert_eq!(
+            parse(args("run a.lox --colour")),
+            Err("unknown flag '--colour'".to_string())
+        );
+    }
+}
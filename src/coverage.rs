@@ -0,0 +1,102 @@
+//! `--coverage`: which lines of the file being run executed, written as an
+//! lcov tracefile when the run ends.
+//!
+//! A line is executable when a statement or declaration starts on it.
+
+use std::collections::{BTreeMap, HashMap};
+
+use crate::parser::{Arena, Declaration, Statement};
+
+/// Where `--coverage` writes its report.
+pub const REPORT_PATH: &str = "lcov.info";
+
+/// The executable lines of `declarations`, whose expressions are in
+/// `arena`, each with no hits yet.
+pub fn executable_lines(
+    arena: &Arena,
+    declarations: &[Declaration],
+) -> BTreeMap<usize, usize> {
+    let mut lines = BTreeMap::new();
+    for declaration in declarations {
+        match declaration {
+            Declaration::Statement(statement) => {
+                statement_lines(arena, statement, &mut lines)
+            }
+            declaration => {
+                lines.extend(declaration.line(arena).map(|line| (line, 0)));
+            }
+        }
+    }
+    lines
+}
+
+fn statement_lines(
+    arena: &Arena,
+    statement: &Statement,
+    lines: &mut BTreeMap<usize, usize>,
+) {
+    // A block is not run as a statement of its own, only its contents are.
+    if !matches!(statement, Statement::Block(_)) {
+        lines.extend(statement.line(arena).map(|line| (line, 0)));
+    }
+    match statement {
+        Statement::IfStmt(if_) => {
+            statement_lines(arena, &if_.then_branch, lines);
+            if let Some(else_branch) = &if_.else_branch {
+                statement_lines(arena, else_branch, lines);
+            }
+        }
+        Statement::Block(declarations) => body_lines(arena, declarations, lines),
+        Statement::Switch(switch) => {
+            for (_, declarations) in &switch.cases {
+                body_lines(arena, declarations, lines);
+            }
+            if let Some(declarations) = &switch.default {
+                body_lines(arena, declarations, lines);
+            }
+        }
+        Statement::ForIn { body, .. } | Statement::DoWhile { body, .. } => {
+            statement_lines(arena, body, lines)
+        }
+        Statement::Try(try_) => {
+            body_lines(arena, &try_.body, lines);
+            if let Some((_, declarations)) = &try_.catch {
+                body_lines(arena, declarations, lines);
+            }
+            if let Some(declarations) = &try_.finally {
+                body_lines(arena, declarations, lines);
+            }
+        }
+        _ => {}
+    }
+}
+
+fn body_lines(
+    arena: &Arena,
+    declarations: &[Declaration],
+    lines: &mut BTreeMap<usize, usize>,
+) {
+    lines.append(&mut executable_lines(arena, declarations));
+}
+
+/// An lcov record for `file`: every executable line with its hit count.
+pub fn lcov(
+    file: &str,
+    mut lines: BTreeMap<usize, usize>,
+    hits: &HashMap<usize, usize>,
+) -> String {
+    for (line, count) in hits {
+        *lines.entry(*line).or_default() += count;
+    }
+    let mut report = vec!["TN:".to_string(), format!("SF:{}", file)];
+    report.extend(
+        lines
+            .iter()
+            .map(|(line, count)| format!("DA:{},{}", line, count)),
+    );
+    report.push(format!("LF:{}", lines.len()));
+    let hit = lines.values().filter(|&&count| count > 0).count();
+    report.push(format!("LH:{}", hit));
+    report.push("end_of_record".into());
+    report.join("\n") + "\n"
+}
@@ -1,37 +1,208 @@
 use crate::interpreter::RuntimeError;
+use crate::messages::Code;
 use crate::parser::Object;
+use crate::symbol::Symbol;
 use crate::token::TokenType::VAR;
 use std::collections::HashMap;
 
+/// A variable's value, whether it may be reassigned and its doc comment.
+pub(crate) struct Binding {
+    value: Object,
+    mutable: bool,
+    doc: Option<String>,
+}
+
+/// Where the resolver found a local variable: `depth` scopes out from the
+/// innermost one, at position `index` in it.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Slot {
+    pub depth: usize,
+    pub index: usize,
+}
+
+/// A block's variables in the order they were declared, which is the
+/// order the resolver numbered their slots in.
+pub(crate) type Scope = Vec<(Symbol, Binding)>;
+
+/// Where a variable lives: a local scope and its slot, or the globals.
+enum Location {
+    Local(usize, usize),
+    Global,
+}
+
 pub(crate) struct Environment {
-    _map: HashMap<String, Object>,
-    enclosing: Option<Box<Environment>>,
+    globals: HashMap<Symbol, Binding>,
+    /// Local scopes, innermost last.
+    scopes: Vec<Scope>,
+    /// Arguments given to the script after `--`, read by `args()`.
+    script_args: Vec<String>,
 }
 impl Environment {
     pub fn new() -> Self {
         Environment {
-            _map: HashMap::new(),
-            enclosing: None,
+            globals: HashMap::new(),
+            scopes: vec![],
+            script_args: vec![],
         }
     }
-    pub fn get(&self, identifier: String) -> Result<&Object, RuntimeError> {
-        self._map
-            .get(&identifier)
+
+    pub fn set_script_args(&mut self, args: Vec<String>) {
+        self.script_args = args;
+    }
+
+    pub fn script_args(&self) -> &[String] {
+        &self.script_args
+    }
+
+    /// Enters a new innermost scope, enclosed by the current one.
+    pub fn push_scope(&mut self) {
+        self.scopes.push(vec![]);
+    }
+
+    /// Leaves the innermost scope, dropping the variables defined in it.
+    pub fn pop_scope(&mut self) {
+        self.scopes.pop();
+    }
+
+    /// Removes every local scope, leaving only the globals visible until
+    /// they are put back with [`Environment::restore_scopes`].
+    pub fn take_scopes(&mut self) -> Vec<Scope> {
+        std::mem::take(&mut self.scopes)
+    }
+
+    pub fn restore_scopes(&mut self, scopes: Vec<Scope>) {
+        self.scopes = scopes;
+    }
+
+    /// Finds `identifier` at its resolved `slot`, checking the name there.
+    /// Variables without a slot, such as those of a program that was not
+    /// resolved, are looked up by name from the innermost scope out.
+    fn locate(&self, identifier: Symbol, slot: Option<Slot>) -> Option<Location> {
+        let resolved = slot.and_then(|slot| {
+            let scope = self.scopes.len().checked_sub(slot.depth + 1)?;
+            let (name, _) = self.scopes[scope].get(slot.index)?;
+            (*name == identifier).then_some(Location::Local(scope, slot.index))
+        });
+        resolved
             .or_else(|| {
-                self.enclosing
-                    .as_ref()
-                    .and_then(|e| e.get(identifier.clone()).ok())
+                self.scopes
+                    .iter()
+                    .enumerate()
+                    .rev()
+                    .find_map(|(scope, bindings)| {
+                        bindings
+                            .iter()
+                            .position(|(name, _)| *name == identifier)
+                            .map(|index| Location::Local(scope, index))
+                    })
             })
+            .or_else(|| {
+                self.globals
+                    .contains_key(&identifier)
+                    .then_some(Location::Global)
+            })
+    }
+
+    fn binding(&self, identifier: Symbol, slot: Option<Slot>) -> Option<&Binding> {
+        match self.locate(identifier, slot)? {
+            Location::Local(scope, index) => Some(&self.scopes[scope][index].1),
+            Location::Global => self.globals.get(&identifier),
+        }
+    }
+
+    fn binding_mut(
+        &mut self,
+        identifier: Symbol,
+        slot: Option<Slot>,
+    ) -> Option<&mut Binding> {
+        match self.locate(identifier, slot)? {
+            Location::Local(scope, index) => Some(&mut self.scopes[scope][index].1),
+            Location::Global => self.globals.get_mut(&identifier),
+        }
+    }
+
+    pub fn get(
+        &self,
+        identifier: Symbol,
+        slot: Option<Slot>,
+    ) -> Result<&Object, RuntimeError> {
+        self.binding(identifier, slot)
+            .map(|binding| &binding.value)
             .ok_or_else(|| {
-                RuntimeError::new(format!("Undefined variable {identifier}."), VAR)
+                RuntimeError::new(Code::UndefinedVariable, &[&identifier], VAR)
             })
     }
 
-    pub fn set(&mut self, identifier: String, object: Object) {
-        self._map.insert(identifier.clone(), object.clone());
+    /// Defines a variable in the current scope, replacing any earlier one
+    /// with the same name there.
+    pub fn define(&mut self, identifier: Symbol, object: Object) {
+        self.bind(identifier, object, true)
+    }
+
+    pub fn define_constant(&mut self, identifier: Symbol, object: Object) {
+        self.bind(identifier, object, false)
+    }
+
+    fn bind(&mut self, identifier: Symbol, object: Object, mutable: bool) {
+        let binding = Binding {
+            value: object,
+            mutable,
+            doc: None,
+        };
+        let Some(scope) = self.scopes.last_mut() else {
+            self.globals.insert(identifier, binding);
+            return;
+        };
+        match scope.iter_mut().find(|(name, _)| *name == identifier) {
+            Some((_, existing)) => *existing = binding,
+            None => scope.push((identifier, binding)),
+        }
+    }
+
+    /// Attaches a doc comment to a variable of the current scope.
+    pub fn set_doc(&mut self, identifier: Symbol, doc: String) {
+        let binding = match self.scopes.last_mut() {
+            Some(scope) => scope
+                .iter_mut()
+                .find(|(name, _)| *name == identifier)
+                .map(|(_, binding)| binding),
+            None => self.globals.get_mut(&identifier),
+        };
+        if let Some(binding) = binding {
+            binding.doc = Some(doc);
+        }
+    }
+
+    /// The doc comment of a variable, `None` when it has none.
+    pub fn doc(&self, identifier: Symbol) -> Result<Option<&str>, RuntimeError> {
+        self.get(identifier, None)?;
+        Ok(self
+            .binding(identifier, None)
+            .and_then(|binding| binding.doc.as_deref()))
+    }
 
-        if self.enclosing.is_some() {
-            self.enclosing.as_mut().unwrap().set(identifier, object)
+    /// Assigns the innermost variable with this name; there must be one.
+    pub fn assign(
+        &mut self,
+        identifier: Symbol,
+        slot: Option<Slot>,
+        object: Object,
+    ) -> Result<(), RuntimeError> {
+        match self.binding_mut(identifier, slot) {
+            Some(binding) if !binding.mutable => Err(RuntimeError::new(
+                Code::AssignToConstant,
+                &[&identifier],
+                VAR,
+            )),
+            Some(binding) => {
+                binding.value = object;
+                Ok(())
+            }
+            None => Err(RuntimeError::new(
+                Code::UndefinedVariable,
+                &[&identifier],
+                VAR,
+            )),
         }
     }
 }
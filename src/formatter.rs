@@ -0,0 +1,435 @@
+//! Canonical Lox source for `lox fmt`, printed back from the parse tree.
+//!
+//! Parentheses come from the `Grouping` nodes the parser keeps, so the
+//! output parses to the same tree. Comments other than `///` never reach the
+//! parser; the scanner keeps them, and each is printed before the
+//! declaration that follows it, or at the end of its line if code came
+//! first.
+
+use crate::parser::{Arena, Declaration, Expr, ExprId, Object, Program, Statement};
+use crate::scanner::Comment;
+use crate::token::{Token, TokenType};
+
+const INDENT: &str = "    ";
+
+/// `program` as source with its `comments`: four-space indentation, one
+/// declaration per line and a trailing newline.
+pub fn format(program: &Program, comments: &[Comment]) -> String {
+    let mut printer = Printer::new(&program.arena, comments);
+    printer.declarations(&program.declarations);
+    for comment in std::mem::take(&mut printer.comments) {
+        printer.newline();
+        printer.write(&comment.text);
+    }
+    let mut source = printer.out.trim_matches('\n').to_string();
+    if !source.is_empty() {
+        source.push('\n');
+    }
+    source
+}
+
+/// The first line of `declaration` as source, without its doc comment;
+/// `--trace` shows it as the declaration runs.
+pub fn summary(arena: &Arena, declaration: &Declaration) -> String {
+    let mut printer = Printer::new(arena, &[]);
+    printer.declaration(declaration);
+    first_line(&printer.out)
+}
+
+pub fn statement_summary(arena: &Arena, statement: &Statement) -> String {
+    let mut printer = Printer::new(arena, &[]);
+    printer.statement(statement);
+    first_line(&printer.out)
+}
+
+fn first_line(source: &str) -> String {
+    source
+        .lines()
+        .find(|line| !line.starts_with("///"))
+        .unwrap_or_default()
+        .to_string()
+}
+
+struct Printer<'a, 'c> {
+    arena: &'a Arena,
+    out: String,
+    depth: usize,
+    /// The comments not printed yet, in source order.
+    comments: &'c [Comment],
+    /// How many braces the printed code has open.
+    braces: usize,
+    /// The line the code after the declaration being printed starts on.
+    next_line: usize,
+}
+
+impl<'a, 'c> Printer<'a, 'c> {
+    fn new(arena: &'a Arena, comments: &'c [Comment]) -> Self {
+        Printer {
+            arena,
+            out: String::new(),
+            depth: 0,
+            comments,
+            braces: 0,
+            next_line: usize::MAX,
+        }
+    }
+
+    fn write(&mut self, text: &str) {
+        self.out.push_str(text);
+    }
+
+    fn newline(&mut self) {
+        self.out.push('\n');
+        for _ in 0..self.depth {
+            self.out.push_str(INDENT);
+        }
+    }
+
+    /// Each declaration on a line of its own at the current depth, with
+    /// the comments before it and at the end of its first line.
+    fn declarations(&mut self, declarations: &[Declaration]) {
+        let after = self.next_line;
+        for (index, declaration) in declarations.iter().enumerate() {
+            self.next_line = declarations[index + 1..]
+                .iter()
+                .find_map(|declaration| declaration.line(self.arena))
+                .unwrap_or(after);
+            self.newline();
+            let Some(line) = declaration.line(self.arena) else {
+                self.declaration(declaration);
+                continue;
+            };
+            self.comments_before(line);
+            self.declaration(declaration);
+            while let Some((comment, rest)) = self.comments.split_first() {
+                if !(comment.trailing && comment.line == line) {
+                    break;
+                }
+                self.write(" ");
+                self.write(&comment.text);
+                self.comments = rest;
+            }
+        }
+        self.next_line = after;
+    }
+
+    /// Prints the comments that come before `line`, or start it, each on a
+    /// line of its own.
+    fn comments_before(&mut self, line: usize) {
+        while let Some((comment, rest)) = self.comments.split_first() {
+            if comment.line > line || comment.line == line && comment.trailing {
+                break;
+            }
+            self.write(&comment.text);
+            self.newline();
+            self.comments = rest;
+        }
+    }
+
+    fn declaration(&mut self, declaration: &Declaration) {
+        let (target, initializer, constant, doc) = match declaration {
+            Declaration::Var {
+                target,
+                initializer,
+                constant,
+                doc,
+                ..
+            } => (target, initializer, constant, doc),
+            Declaration::Statement(stmt) => return self.statement(stmt),
+        };
+        for line in doc.iter().flat_map(|doc| doc.split('\n')) {
+            self.write(format!("/// {}", line).trim_end());
+            self.newline();
+        }
+        let keyword = if *constant { "const" } else { "var" };
+        let target = pattern(self.arena, *target);
+        let text = match initializer {
+            Some(value) => {
+                format!("{} {} = {};", keyword, target, expr(self.arena, *value))
+            }
+            None => format!("{} {};", keyword, target),
+        };
+        self.write(&text);
+    }
+
+    fn block(&mut self, body: &[Declaration]) {
+        self.braces += 1;
+        let empty = body.is_empty() && !self.comments_left_inside();
+        self.braces -= 1;
+        if empty {
+            return self.write("{}");
+        }
+        self.write("{");
+        self.open();
+        self.declarations(body);
+        self.close();
+        self.newline();
+        self.write("}");
+    }
+
+    /// Goes one brace deeper.
+    fn open(&mut self) {
+        self.depth += 1;
+        self.braces += 1;
+    }
+
+    /// Prints the comments left before the closing brace, then leaves the
+    /// braces.
+    fn close(&mut self) {
+        while self.comments_left_inside() {
+            self.newline();
+            self.write(&self.comments[0].text);
+            self.comments = &self.comments[1..];
+        }
+        self.braces -= 1;
+        self.depth -= 1;
+    }
+
+    /// Whether the next comment is inside the innermost braces, before
+    /// the code that follows them.
+    fn comments_left_inside(&self) -> bool {
+        self.comments.first().is_some_and(|comment| {
+            comment.braces >= self.braces && comment.line < self.next_line
+        })
+    }
+
+    /// The body of an `if`, `for` or `do`: a block stays on the header's
+    /// line, any other statement goes on the next line, indented.
+    fn body(&mut self, body: &Statement) {
+        if let Statement::Block(declarations) = body {
+            self.write(" ");
+            return self.block(declarations);
+        }
+        self.depth += 1;
+        self.newline();
+        self.statement(body);
+        self.depth -= 1;
+    }
+
+    /// Continues after a `body`: on the same line after a block, otherwise
+    /// on a new one.
+    fn after_body(&mut self, body: &Statement, text: &str) {
+        if matches!(body, Statement::Block(_)) {
+            self.write(" ");
+        } else {
+            self.newline();
+        }
+        self.write(text);
+    }
+
+    fn statement(&mut self, statement: &Statement) {
+        match statement {
+            Statement::ExprStmt(value) => {
+                self.write(&format!("{};", expr(self.arena, *value)))
+            }
+            Statement::PrintStmt(value) => {
+                self.write(&format!("print {};", expr(self.arena, *value)))
+            }
+            Statement::EPrintStmt(value) => {
+                self.write(&format!("eprint {};", expr(self.arena, *value)))
+            }
+            Statement::IfStmt(if_) => {
+                self.write(&format!("if ({})", expr(self.arena, if_.condition)));
+                self.body(&if_.then_branch);
+                if let Some(else_branch) = &if_.else_branch {
+                    self.after_body(&if_.then_branch, "else");
+                    if let Statement::IfStmt(_) = else_branch.as_ref() {
+                        self.write(" ");
+                        self.statement(else_branch);
+                    } else {
+                        self.body(else_branch);
+                    }
+                }
+            }
+            Statement::WhileStmt(condition) => {
+                self.write(&format!("{};", expr(self.arena, *condition)))
+            }
+            Statement::Block(body) => self.block(body),
+            Statement::Switch(switch) => {
+                self.write(&format!("switch ({}) {{", expr(self.arena, switch.value)));
+                self.open();
+                for (values, body) in &switch.cases {
+                    let values = list(self.arena, values);
+                    self.newline();
+                    self.write(&format!("case {}:", values));
+                    self.depth += 1;
+                    self.declarations(body);
+                    self.depth -= 1;
+                }
+                if let Some(body) = &switch.default {
+                    self.newline();
+                    self.write("default:");
+                    self.depth += 1;
+                    self.declarations(body);
+                    self.depth -= 1;
+                }
+                self.close();
+                self.newline();
+                self.write("}");
+            }
+            Statement::ForIn {
+                variable,
+                iterable,
+                body,
+                ..
+            } => {
+                self.write(&format!(
+                    "for ({} in {})",
+                    variable,
+                    expr(self.arena, *iterable)
+                ));
+                self.body(body);
+            }
+            Statement::Import { path, .. } => {
+                self.write(&format!("import \"{}\";", path))
+            }
+            Statement::Try(try_) => {
+                self.write("try ");
+                self.block(&try_.body);
+                if let Some((name, body)) = &try_.catch {
+                    self.write(&format!(" catch ({}) ", name));
+                    self.block(body);
+                }
+                if let Some(body) = &try_.finally {
+                    self.write(" finally ");
+                    self.block(body);
+                }
+            }
+            Statement::Throw { value, .. } => {
+                self.write(&format!("throw {};", expr(self.arena, *value)))
+            }
+            Statement::DoWhile { body, condition } => {
+                self.write("do");
+                self.body(body);
+                self.after_body(
+                    body,
+                    &format!("while ({});", expr(self.arena, *condition)),
+                );
+            }
+        }
+    }
+}
+
+/// The target of a `var`: a name, `[a, b]` or `{a, b}`.
+fn pattern(arena: &Arena, target: ExprId) -> String {
+    match &arena[target] {
+        Expr::List { elements } => format!("[{}]", list(arena, elements)),
+        Expr::Map { entries, .. } => {
+            let names: Vec<String> =
+                entries.iter().map(|&(_, name)| expr(arena, name)).collect();
+            format!("{{{}}}", names.join(", "))
+        }
+        _ => expr(arena, target),
+    }
+}
+
+fn list(arena: &Arena, elements: &[ExprId]) -> String {
+    elements
+        .iter()
+        .map(|&element| expr(arena, element))
+        .collect::<Vec<String>>()
+        .join(", ")
+}
+
+/// Numbers are printed as written, so `1.50` stays `1.50`.
+fn literal(value: &Object, token: Option<&Token>) -> String {
+    if let (Object::Number(_), Some(token)) = (value, token) {
+        return String::from_utf8_lossy(&token.lexeme).into();
+    }
+    match value {
+        // Only raw strings can hold a quote.
+        Object::String(string) if string.contains('"') => {
+            format!("\"\"\"{}\"\"\"", string)
+        }
+        Object::String(string) => format!("\"{}\"", string),
+        Object::Number(number) => number.to_string(),
+        value => value.to_string(),
+    }
+}
+
+fn expr(arena: &Arena, id: ExprId) -> String {
+    let expr = |id: &ExprId| self::expr(arena, *id);
+    match &arena[id] {
+        Expr::Binary {
+            left,
+            operator,
+            right,
+        } if operator.token_type == TokenType::COMMA => {
+            format!("{}, {}", expr(left), expr(right))
+        }
+        Expr::Binary {
+            left,
+            operator,
+            right,
+        } => format!(
+            "{} {} {}",
+            expr(left),
+            operator.token_type.spelling(),
+            expr(right)
+        ),
+        Expr::Grouping { expression } => format!("({})", expr(expression)),
+        Expr::Literal { value, token } => literal(value, token.as_ref()),
+        Expr::Unary { operator, right } => {
+            format!("{}{}", operator.token_type.spelling(), expr(right))
+        }
+        Expr::Variable { identifier, .. } => identifier.to_string(),
+        Expr::Assign {
+            identifier, value, ..
+        } => match &arena[*value] {
+            // `x += e` is kept as `x = x += e`; see `Parser::assignment`.
+            Expr::Binary {
+                left,
+                operator,
+                right,
+            } if operator.token_type.compound_operator().is_some()
+                && matches!(arena[*left], Expr::Variable { identifier: name, .. } if name == *identifier) =>
+            {
+                format!(
+                    "{} {} {}",
+                    identifier,
+                    operator.token_type.spelling(),
+                    expr(right)
+                )
+            }
+            _ => format!("{} = {}", identifier, expr(value)),
+        },
+        Expr::Conditional {
+            condition,
+            then_branch,
+            else_branch,
+        } => format!(
+            "{} ? {} : {}",
+            expr(condition),
+            expr(then_branch),
+            expr(else_branch)
+        ),
+        Expr::Call {
+            callee, arguments, ..
+        } => format!("{}({})", expr(callee), list(arena, arguments)),
+        Expr::List { elements } => format!("[{}]", list(arena, elements)),
+        Expr::Map { entries, .. } => {
+            let entries: Vec<String> = entries
+                .iter()
+                .map(|(key, value)| format!("{}: {}", expr(key), expr(value)))
+                .collect();
+            format!("{{{}}}", entries.join(", "))
+        }
+        Expr::Index { object, index, .. } => {
+            format!("{}[{}]", expr(object), expr(index))
+        }
+        Expr::IndexSet {
+            object,
+            index,
+            operator,
+            value,
+            ..
+        } => format!(
+            "{}[{}] {} {}",
+            expr(object),
+            expr(index),
+            operator.token_type.spelling(),
+            expr(value)
+        ),
+        Expr::Spread { value, .. } => format!("...{}", expr(value)),
+    }
+}
@@ -0,0 +1,350 @@
+//! EBNF dump of the active grammar for `lox grammar`.
+//!
+//! Operator alternatives and precedence levels come from the token table in
+//! `token.rs`, which the parser also consumes, so the printed grammar
+//! cannot drift from what is actually parsed. The statement rules and the
+//! alternatives of `primary` are written by hand; the tests parse an
+//! example of each.
+
+use crate::token::{Precedence, TokenType};
+
+/// A production written out by hand; `extension` rules only exist when
+/// extensions are enabled.
+struct Rule {
+    name: &'static str,
+    body: &'static str,
+    extension: bool,
+}
+
+const STATEMENTS: [Rule; 11] = [
+    Rule {
+        name: "exprStmt",
+        body: "expression \";\"",
+        extension: false,
+    },
+    Rule {
+        name: "printStmt",
+        body: "\"print\" expression \";\"",
+        extension: false,
+    },
+    Rule {
+        name: "eprintStmt",
+        body: "\"eprint\" expression \";\"",
+        extension: true,
+    },
+    Rule {
+        name: "ifStmt",
+        body: "\"if\" \"(\" expression \")\" statement [ \"else\" statement ]",
+        extension: false,
+    },
+    Rule {
+        name: "switchStmt",
+        body: "\"switch\" \"(\" expression \")\" \"{\" \
+               { ( \"case\" assignment { \",\" assignment } | \"default\" ) \":\" { declaration } } \"}\"",
+        extension: true,
+    },
+    Rule {
+        name: "forInStmt",
+        body: "\"for\" \"(\" IDENTIFIER \"in\" expression \")\" statement",
+        extension: false,
+    },
+    Rule {
+        name: "doWhileStmt",
+        body: "\"do\" statement \"while\" \"(\" expression \")\" \";\"",
+        extension: true,
+    },
+    Rule {
+        name: "importStmt",
+        body: "\"import\" STRING \";\"",
+        extension: true,
+    },
+    Rule {
+        name: "tryStmt",
+        body: "\"try\" block [ \"catch\" \"(\" IDENTIFIER \")\" block ] \
+               [ \"finally\" block ]",
+        extension: true,
+    },
+    Rule {
+        name: "throwStmt",
+        body: "\"throw\" expression \";\"",
+        extension: true,
+    },
+    Rule {
+        name: "block",
+        body: "\"{\" { declaration } \"}\"",
+        extension: false,
+    },
+];
+
+/// The alternatives of `primary`, each flagged like [`Rule::extension`].
+const PRIMARY: [(&str, bool); 9] = [
+    ("NUMBER", false),
+    ("STRING", false),
+    ("\"true\"", false),
+    ("\"false\"", false),
+    ("\"nil\"", false),
+    ("IDENTIFIER", false),
+    ("\"(\" expression \")\"", false),
+    ("\"[\" [ arguments ] \"]\"", true),
+    ("\"{\" [ entry { \",\" entry } ] \"}\"", true),
+];
+
+/// The operators satisfying `predicate` as an EBNF alternative.
+fn alternatives(predicate: impl Fn(TokenType) -> bool) -> String {
+    let symbols: Vec<String> = TokenType::all()
+        .filter(|&token_type| predicate(token_type))
+        .map(|operator| format!("\"{}\"", operator.spelling()))
+        .collect();
+    match symbols.as_slice() {
+        [single] => single.clone(),
+        _ => format!("( {} )", symbols.join(" | ")),
+    }
+}
+
+fn at(precedence: Precedence) -> impl Fn(TokenType) -> bool {
+    move |operator| operator.precedence() == Some(precedence)
+}
+
+/// The active grammar in EBNF, one production per line.
+pub fn ebnf(extensions: bool) -> String {
+    let statements: Vec<&Rule> = STATEMENTS
+        .iter()
+        .filter(|rule| extensions || !rule.extension)
+        .collect();
+    let statement_names: Vec<&str> = statements.iter().map(|rule| rule.name).collect();
+    let scanned = |token_type: TokenType| extensions || !token_type.requires_extensions();
+    let levels: Vec<Precedence> = Precedence::BINARY_LEVELS
+        .into_iter()
+        .filter(|&level| {
+            TokenType::all().any(|operator| scanned(operator) && at(level)(operator))
+        })
+        .collect();
+
+    let mut rules: Vec<(String, String)> = vec![
+        ("program".into(), "{ declaration } EOF".into()),
+        (
+            "declaration".into(),
+            if extensions {
+                "varDecl | constDecl | statement".into()
+            } else {
+                "varDecl | statement".into()
+            },
+        ),
+        (
+            "varDecl".into(),
+            "\"var\" ( IDENTIFIER [ \"=\" expression ] | pattern \"=\" expression ) \";\""
+                .into(),
+        ),
+        (
+            "pattern".into(),
+            if extensions {
+                "\"[\" [ names ] \"]\" | \"{\" [ names ] \"}\"".into()
+            } else {
+                "\"{\" [ names ] \"}\"".into()
+            },
+        ),
+        ("names".into(), "IDENTIFIER { \",\" IDENTIFIER }".into()),
+        ("statement".into(), statement_names.join(" | ")),
+    ];
+    if extensions {
+        rules.insert(
+            5,
+            (
+                "constDecl".into(),
+                "\"const\" IDENTIFIER \"=\" expression \";\"".into(),
+            ),
+        );
+    }
+    rules.extend(
+        statements
+            .iter()
+            .map(|rule| (rule.name.to_string(), rule.body.to_string())),
+    );
+    let first_level = levels[0].rule();
+    let (target, subscript, operand) = if extensions {
+        (
+            "( IDENTIFIER | call \"[\" expression \"]\" )",
+            " | \"[\" expression \"]\"",
+            "conditional",
+        )
+    } else {
+        ("IDENTIFIER", "", first_level)
+    };
+    rules.push(("expression".into(), "comma".into()));
+    rules.push((
+        "comma".into(),
+        format!(
+            "assignment {{ {} assignment }}",
+            alternatives(at(Precedence::Comma))
+        ),
+    ));
+    rules.push((
+        "assignment".into(),
+        format!(
+            "{} {} assignment | {}",
+            target,
+            alternatives(
+                |operator| scanned(operator) && operator.is_assignment_operator()
+            ),
+            operand
+        ),
+    ));
+    if extensions {
+        rules.push((
+            "conditional".into(),
+            format!("{} [ \"?\" expression \":\" conditional ]", first_level),
+        ));
+    }
+    for (index, &level) in levels.iter().enumerate() {
+        let operand = levels.get(index + 1).map_or("unary", |next| next.rule());
+        let operators = alternatives(|operator| scanned(operator) && at(level)(operator));
+        rules.push((
+            level.rule().into(),
+            format!("{} {{ {} {} }}", operand, operators, operand),
+        ));
+    }
+    rules.push((
+        "unary".into(),
+        format!(
+            "{} unary | call",
+            alternatives(|operator| scanned(operator) && operator.is_unary_operator())
+        ),
+    ));
+    rules.push((
+        "call".into(),
+        format!("primary {{ \"(\" [ arguments ] \")\"{} }}", subscript),
+    ));
+    if extensions {
+        rules.push(("arguments".into(), "element { \",\" element }".into()));
+        rules.push(("element".into(), "[ \"...\" ] assignment".into()));
+    } else {
+        rules.push(("arguments".into(), "assignment { \",\" assignment }".into()));
+    }
+    let primary: Vec<&str> = PRIMARY
+        .iter()
+        .filter(|(_, extension)| extensions || !extension)
+        .map(|(alternative, _)| *alternative)
+        .collect();
+    rules.push(("primary".into(), primary.join(" | ")));
+    if extensions {
+        rules.push(("entry".into(), "conditional \":\" assignment".into()));
+    }
+
+    let width = rules.iter().map(|(name, _)| name.len()).max().unwrap_or(0);
+    rules
+        .iter()
+        .map(|(name, body)| format!("{:width$} = {} ;", name, body, width = width))
+        .collect::<Vec<String>>()
+        .join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::{Declaration, Parser, Statement};
+    use crate::scanner::Scanner;
+    use crate::Lox;
+
+    /// The declarations `source` parses to; `None` if it has errors.
+    fn parse(source: &str, extensions: bool) -> Option<Vec<Declaration>> {
+        let mut lox = Lox::new();
+        lox.extensions = extensions;
+        let mut scanner = Scanner::new(source.as_bytes(), &lox);
+        let tokens = scanner.scan_tokens();
+        let program = Parser::new(tokens, &lox).parse();
+        (!lox.has_error()).then_some(program.declarations)
+    }
+
+    /// The rule in [`STATEMENTS`] that `statement` was parsed by. There is
+    /// no catch-all arm, so a new kind of statement needs a rule first.
+    fn rule_name(statement: &Statement) -> &'static str {
+        match statement {
+            Statement::ExprStmt(_) => "exprStmt",
+            Statement::PrintStmt(_) => "printStmt",
+            Statement::EPrintStmt(_) => "eprintStmt",
+            Statement::IfStmt(_) => "ifStmt",
+            Statement::WhileStmt(_) => unreachable!("never parsed"),
+            Statement::Block(_) => "block",
+            Statement::Switch(_) => "switchStmt",
+            Statement::ForIn { .. } => "forInStmt",
+            Statement::Import { .. } => "importStmt",
+            Statement::Try(_) => "tryStmt",
+            Statement::Throw { .. } => "throwStmt",
+            Statement::DoWhile { .. } => "doWhileStmt",
+        }
+    }
+
+    #[test]
+    fn test_statement_rules_match_the_parser() {
+        for rule in &STATEMENTS {
+            let example = match rule.name {
+                "exprStmt" => "a = 1;",
+                "printStmt" => "print 1;",
+                "eprintStmt" => "eprint 1;",
+                "ifStmt" => "if (true) print 1; else print 2;",
+                "switchStmt" => "switch (1) { case 1, 2: print 1; default: print 2; }",
+                "forInStmt" => "for (x in \"ab\") print x;",
+                "doWhileStmt" => "do print 1; while (false);",
+                "importStmt" => "import \"module.lox\";",
+                "tryStmt" => {
+                    "try { throw 1; } catch (e) { print e; } finally { print 2; }"
+                }
+                "throwStmt" => "throw 1;",
+                "block" => "{ print 1; }",
+                name => panic!("no example of {}", name),
+            };
+
+            let parsed = parse(example, true).unwrap_or_else(|| panic!("{}", example));
+            let [Declaration::Statement(statement)] = parsed.as_slice() else {
+                panic!("{} is not one statement", example);
+            };
+            assert_eq!(rule_name(statement), rule.name);
+            assert_eq!(
+                parse(example, false).is_some(),
+                !rule.extension,
+                "{}",
+                example
+            );
+        }
+    }
+
+    #[test]
+    fn test_primary_alternatives_match_the_parser() {
+        for (alternative, extension) in PRIMARY {
+            let example = match alternative {
+                "NUMBER" => "1.5",
+                "STRING" => "\"a\"",
+                "\"true\"" => "true",
+                "\"false\"" => "false",
+                "\"nil\"" => "nil",
+                "IDENTIFIER" => "a",
+                "\"(\" expression \")\"" => "(1, 2)",
+                "\"[\" [ arguments ] \"]\"" => "[1, ...[2]]",
+                "\"{\" [ entry { \",\" entry } ] \"}\"" => "{\"a\": 1, \"b\": 2}",
+                alternative => panic!("no example of {}", alternative),
+            };
+
+            let source = format!("print {};", example);
+            assert!(parse(&source, true).is_some(), "{}", source);
+            assert_eq!(parse(&source, false).is_some(), !extension, "{}", source);
+        }
+    }
+
+    #[test]
+    fn test_ebnf_lists_extension_rules_only_when_enabled() {
+        assert!(!ebnf(false).contains("eprintStmt"));
+        assert!(ebnf(true).contains("eprintStmt  = \"eprint\" expression \";\" ;"));
+        assert!(
+            ebnf(false).contains("term        = factor { ( \"-\" | \"+\" ) factor } ;")
+        );
+        assert!(!ebnf(false).contains("shift"));
+        assert!(!ebnf(false).contains("conditional"));
+        assert!(!ebnf(false).contains("coalesce"));
+        assert!(!ebnf(false).contains("element"));
+        assert!(
+            ebnf(false).contains("call        = primary { \"(\" [ arguments ] \")\" } ;")
+        );
+        assert!(ebnf(false).contains("unary       = ( \"-\" | \"!\" ) unary | call ;"));
+        assert!(ebnf(true).contains("bitOr       = bitXor { \"|\" bitXor } ;"));
+    }
+}
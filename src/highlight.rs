@@ -0,0 +1,126 @@
+//! `lox highlight`: the source as written, colored by token, for a terminal
+//! or as HTML.
+//!
+//! Whatever the scanner skips between tokens (whitespace, comments and
+//! characters it rejected) is copied through; all but the whitespace is
+//! styled as a comment.
+
+use crate::token::{Category, Token, TokenType};
+use crate::Format;
+
+/// The kind of text a span is styled as; its name is the HTML class.
+#[derive(Clone, Copy)]
+enum Style {
+    Keyword,
+    String,
+    Number,
+    Identifier,
+    Operator,
+    Punctuation,
+    Comment,
+}
+
+impl Style {
+    fn of(token_type: TokenType) -> Self {
+        match (token_type, token_type.category()) {
+            (TokenType::STRING, _) => Style::String,
+            (TokenType::NUMBER, _) => Style::Number,
+            (_, Category::Keyword | Category::ExtensionKeyword) => Style::Keyword,
+            (_, Category::Identifier) => Style::Identifier,
+            (_, Category::Operator | Category::Assignment) => Style::Operator,
+            (_, Category::Trivia) => Style::Comment,
+            _ => Style::Punctuation,
+        }
+    }
+
+    fn class(self) -> &'static str {
+        match self {
+            Style::Keyword => "keyword",
+            Style::String => "string",
+            Style::Number => "number",
+            Style::Identifier => "identifier",
+            Style::Operator => "operator",
+            Style::Punctuation => "punctuation",
+            Style::Comment => "comment",
+        }
+    }
+
+    /// The SGR color; identifiers and punctuation keep the terminal's own.
+    fn ansi(self) -> Option<&'static str> {
+        match self {
+            Style::Keyword => Some("35"),
+            Style::String => Some("32"),
+            Style::Number => Some("33"),
+            Style::Operator => Some("36"),
+            Style::Comment => Some("90"),
+            Style::Identifier | Style::Punctuation => None,
+        }
+    }
+}
+
+/// `source` with every token styled, in `format`: HTML, or else ANSI escapes.
+pub fn highlight(source: &str, tokens: &[Token], format: Format) -> String {
+    let line_starts: Vec<usize> = std::iter::once(0)
+        .chain(source.match_indices('\n').map(|(index, _)| index + 1))
+        .collect();
+    let mut out = String::new();
+    let mut cursor = 0;
+    for token in tokens {
+        if token.lexeme.is_empty() {
+            continue;
+        }
+        let start = offset(source, &line_starts, token).max(cursor);
+        gap(&source[cursor..start], format, &mut out);
+        cursor = start + token.lexeme.len();
+        let text = String::from_utf8_lossy(&token.lexeme);
+        span(&text, Style::of(token.token_type), format, &mut out);
+    }
+    gap(&source[cursor..], format, &mut out);
+    match format {
+        Format::Html => format!("<pre class=\"lox\">{}</pre>", out),
+        _ => out,
+    }
+}
+
+/// The byte offset of `token` in `source`. A token's line is the one it
+/// ends on, which differs from the line of its column for strings that
+/// span lines.
+fn offset(source: &str, line_starts: &[usize], token: &Token) -> usize {
+    let breaks = token.lexeme.iter().filter(|&&byte| byte == b'\n').count();
+    let line_start = line_starts[token.line - breaks - 1];
+    source[line_start..]
+        .char_indices()
+        .nth(token.column - 1)
+        .map_or(source.len(), |(index, _)| line_start + index)
+}
+
+/// Text between tokens: whitespace as is, anything else as a comment.
+fn gap(text: &str, format: Format, out: &mut String) {
+    let trimmed = text.trim();
+    if trimmed.is_empty() {
+        return out.push_str(text);
+    }
+    let start = text.len() - text.trim_start().len();
+    out.push_str(&text[..start]);
+    span(trimmed, Style::Comment, format, out);
+    out.push_str(&text[start + trimmed.len()..]);
+}
+
+fn span(text: &str, style: Style, format: Format, out: &mut String) {
+    match (format, style.ansi()) {
+        (Format::Html, _) => out.push_str(&format!(
+            "<span class=\"{}\">{}</span>",
+            style.class(),
+            html_escape(text)
+        )),
+        (_, Some(color)) => out.push_str(&format!("\x1b[{}m{}\x1b[0m", color, text)),
+        (_, None) => out.push_str(text),
+    }
+}
+
+fn html_escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
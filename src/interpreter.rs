@@ -1,21 +1,150 @@
-use std::cell::RefCell;
+use std::cell::{Cell, RefCell};
+use std::collections::{HashMap, HashSet};
 use std::fmt::{Display, Formatter};
+use std::fs;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
 use std::rc::Rc;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 use std::vec;
 
-use crate::environment::Environment;
-use crate::parser::{Declaration, Expr, If, Object, Statement};
-use crate::token::{Token, TokenType};
+use crate::environment::{Environment, Slot};
+use crate::formatter;
+use crate::messages::{message, Code};
+use crate::natives;
+use crate::outcome::{Output, SourceFile, Stream};
+use crate::parser::Parser;
+use crate::parser::{
+    Arena, Declaration, Expr, ExprId, If, ListRef, MapKey, MapRef, Object, Program,
+    Statement, Switch, Try,
+};
+use crate::preprocessor::Preprocessor;
+use crate::profile::Profile;
+use crate::resolver;
+use crate::scanner::Scanner;
+use crate::symbol::Symbol;
+use crate::token::{Precedence, Token, TokenType};
+use crate::Lox;
 
 #[derive(Debug)]
 pub struct RuntimeError {
-    message: String,
-    operator: TokenType,
+    /// Boxed, as are the thrown value and file, to keep errors small.
+    message: Box<str>,
+    /// The catalog code of the message; `None` for panics and thrown values.
+    pub(crate) code: Option<Code>,
+    /// The operator that failed; `None` for errors raised by a native
+    /// function or by `panic()`.
+    #[allow(dead_code)]
+    operator: Option<TokenType>,
+    /// Line of the operator that failed, when known.
+    pub(crate) line: Option<usize>,
+    /// Column and byte offsets of the operator, when known.
+    pub(crate) column: Option<usize>,
+    pub(crate) span: Option<Range<usize>>,
+    /// Raised on purpose by `panic()` rather than by a failing operation.
+    pub(crate) is_panic: bool,
+    /// Call sites the error unwound through, innermost first.
+    pub(crate) trace: Vec<String>,
+    /// The value of a `throw` statement; `None` for errors raised by the
+    /// interpreter itself.
+    thrown: Option<Box<Object>>,
+    /// The imported module the error happened in; `None` for the program
+    /// being run.
+    pub(crate) file: Option<Arc<SourceFile>>,
 }
 
 impl RuntimeError {
-    pub fn new(message: String, operator: TokenType) -> Self {
-        RuntimeError { message, operator }
+    pub fn new(code: Code, args: &[&dyn Display], operator: TokenType) -> Self {
+        RuntimeError {
+            message: message(code, args).into(),
+            code: Some(code),
+            operator: Some(operator),
+            line: None,
+            column: None,
+            span: None,
+            is_panic: false,
+            trace: vec![],
+            thrown: None,
+            file: None,
+        }
+    }
+
+    /// An error raised inside a native function, which has no operator of
+    /// its own; the call locates it at its closing parenthesis.
+    pub fn native(code: Code, args: &[&dyn Display]) -> Self {
+        RuntimeError {
+            operator: None,
+            ..RuntimeError::new(code, args, TokenType::RIGHT_PAREN)
+        }
+    }
+
+    pub fn panic(message: String) -> Self {
+        RuntimeError {
+            message: message.into(),
+            code: None,
+            operator: None,
+            line: None,
+            column: None,
+            span: None,
+            is_panic: true,
+            trace: vec![],
+            thrown: None,
+            file: None,
+        }
+    }
+
+    /// Locates the error at `token`.
+    fn at(self, token: &Token) -> Self {
+        RuntimeError {
+            line: Some(token.line),
+            column: Some(token.column),
+            span: Some(token.span.clone()),
+            ..self
+        }
+    }
+
+    /// Gives an error raised away from any token the `line` it happened
+    /// on, that of the variable or statement being run. Errors that already
+    /// have a line, or a trace of where they happened, are left alone.
+    fn or_line(mut self, line: Option<usize>) -> Self {
+        if self.line.is_none() && self.trace.is_empty() {
+            self.line = line;
+        }
+        self
+    }
+
+    /// Panics and exceeded [`Limits`] end the run whatever `catch` clauses
+    /// it is in; a stack overflow unwinds far enough to be handled.
+    fn is_catchable(&self) -> bool {
+        !self.is_panic
+            && !matches!(
+                self.code,
+                Some(
+                    Code::StatementLimitExceeded
+                        | Code::LoopLimitExceeded
+                        | Code::TimeoutExceeded
+                )
+            )
+    }
+
+    /// The value a `catch` clause binds: whatever was thrown, or a map with
+    /// the `message` and `line` of an error raised by the interpreter.
+    fn into_value(self) -> Object {
+        if let Some(value) = self.thrown {
+            return *value;
+        }
+        let line = self
+            .line
+            .map_or(Object::Nil, |line| Object::Number(line as f64));
+        let entries = HashMap::from([
+            (
+                MapKey::String("message".into()),
+                Object::String(self.message.into()),
+            ),
+            (MapKey::String("line".into()), line),
+        ]);
+        Object::Map(Rc::new(RefCell::new(entries)))
     }
 }
 
@@ -25,104 +154,526 @@ impl Display for RuntimeError {
     }
 }
 
+/// The storage slot an index expression designates.
+enum Element {
+    Item(ListRef, usize),
+    /// A missing key reads as `nil`; writing it inserts the entry.
+    Entry(MapRef, MapKey),
+}
+
+impl Element {
+    fn get(&self) -> Object {
+        match self {
+            Element::Item(list, index) => list.borrow()[*index].clone(),
+            Element::Entry(map, key) => {
+                map.borrow().get(key).cloned().unwrap_or(Object::Nil)
+            }
+        }
+    }
+
+    fn set(&self, value: Object) {
+        match self {
+            Element::Item(list, index) => list.borrow_mut()[*index] = value,
+            Element::Entry(map, key) => {
+                map.borrow_mut().insert(key.clone(), value);
+            }
+        }
+    }
+}
+
+/// What a statement writes out. Values are written as the program prints
+/// them; what `evaluate` echoes for an assignment or a declaration is
+/// written as the parse tree shows it.
+enum Emitted {
+    Value(Object),
+    /// An assignment, with the value assigned.
+    Assigned(Symbol, Object),
+    /// The variables a declaration bound.
+    Bound(Vec<Symbol>),
+}
+
+impl Display for Emitted {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Emitted::Value(value) => write!(f, "{}", value),
+            Emitted::Assigned(name, value) => {
+                write!(f, "variable {:?} = {}", name, value.to_literal())
+            }
+            Emitted::Bound(names) if names.len() == 1 => {
+                write!(f, "variable {}", names[0])
+            }
+            Emitted::Bound(names) => {
+                write!(f, "(list")?;
+                for name in names {
+                    write!(f, " variable {}", name)?;
+                }
+                write!(f, ")")
+            }
+        }
+    }
+}
+
+/// Converts a number to the 32-bit integer the bitwise operators work on:
+/// the fraction is dropped and the rest wraps modulo 2^32, as in
+/// JavaScript's ToInt32, with NaN and the infinities giving 0.
+fn to_int32(n: f64) -> i32 {
+    let n = n.trunc().rem_euclid(4294967296.0);
+    if n.is_finite() {
+        n as u32 as i32
+    } else {
+        0
+    }
+}
+
+/// Stack size of the thread programs run on; see [`with_stack`].
+pub const STACK_SIZE: usize = 256 << 20;
+
+/// Stack allowed for one level of nesting: twice what a parenthesized
+/// expression takes to parse in a debug build, the deepest recursion per
+/// level of any command.
+const STACK_PER_LEVEL: usize = 128 << 10;
+
+/// How deeply statements and expressions may nest unless told otherwise:
+/// as deep as fits in [`STACK_SIZE`], so that a deep program fails with an
+/// error before the Rust stack overflows.
+pub const DEFAULT_MAX_DEPTH: usize = STACK_SIZE / STACK_PER_LEVEL;
+
+/// Runs `run` on a new thread with a [`STACK_SIZE`] stack, which the depth
+/// limits are worked out for. A panic in `run` carries on in the caller.
+pub fn with_stack<T: Send + 'static>(run: impl FnOnce() -> T + Send + 'static) -> T {
+    std::thread::Builder::new()
+        .stack_size(STACK_SIZE)
+        .spawn(run)
+        .expect("failed to spawn the interpreter thread")
+        .join()
+        .unwrap_or_else(|panic| std::panic::resume_unwind(panic))
+}
 
-pub(crate) struct Interpreter {
+/// Caps on the work a program may do, for running scripts that cannot be
+/// trusted to stop. Going past one is a runtime error that `catch` does not
+/// handle; `None` means no cap.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Limits {
+    /// Statements, declarations and loop iterations run.
+    pub statements: Option<usize>,
+    /// How deeply statements and expressions may nest.
+    pub depth: usize,
+    /// Iterations of any one loop.
+    pub loop_iterations: Option<usize>,
+    /// Wall-clock time for the whole run, imports included.
+    pub timeout: Option<Duration>,
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Limits {
+            statements: None,
+            depth: DEFAULT_MAX_DEPTH,
+            loop_iterations: None,
+            timeout: None,
+        }
+    }
+}
+
+/// How arithmetic operators treat `nil` operands.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub enum ErrorPolicy {
+    /// Standard Lox: arithmetic on `nil` is a runtime error.
+    #[default]
+    Strict,
+    /// Spreadsheet-like: arithmetic with a `nil` operand evaluates to `nil`.
+    PropagateNil,
+}
+
+pub(crate) struct Interpreter<'l> {
+    lox: &'l Lox,
     environment: Rc<RefCell<Environment>>,
+    policy: ErrorPolicy,
+    comparison_warnings: bool,
+    verbose_errors: bool,
+    /// Where statements write as they run: prints, echoed values and traces.
+    output: RefCell<Output>,
+    /// The value most recently written, when it was a value rather than
+    /// the variables a declaration bound.
+    last_value: RefCell<Option<Object>>,
+    /// Whether expression statements, declarations and untaken `if`s emit
+    /// their value, as `evaluate` shows them; `run` only emits prints.
+    echo: bool,
+    /// `--trace`: emit each statement and variable assignment to stderr.
+    tracing: bool,
+    /// Assignments traced but not yet written, held back until the
+    /// statement making them has been traced.
+    traced: RefCell<Vec<String>>,
+    /// Line of the statement being traced, shown on its assignments.
+    trace_line: Cell<Option<usize>>,
+    /// Statements and declarations run so far.
+    executed: Cell<usize>,
+    /// Statements and expressions being evaluated, innermost included.
+    depth: Cell<usize>,
+    limits: Limits,
+    /// Statements and loop iterations started, for [`Limits::statements`].
+    steps: Cell<usize>,
+    /// When the run times out, with [`Limits::timeout`].
+    deadline: Option<Instant>,
+    /// `--profile`: timings of statements and native calls.
+    profile: Option<RefCell<Profile>>,
+    /// `--coverage`: how many times each line of the file being run
+    /// started a statement.
+    coverage: Option<RefCell<HashMap<usize, usize>>>,
+    /// Modules already imported, which later imports skip.
+    loaded: RefCell<HashSet<PathBuf>>,
+    /// The file being run and the modules currently being imported,
+    /// innermost last.
+    importing: RefCell<Vec<PathBuf>>,
 }
 
-impl Interpreter {
-    pub(crate) fn new() -> Self {
+impl<'l> Interpreter<'l> {
+    pub(crate) fn new(lox: &'l Lox, policy: ErrorPolicy) -> Self {
+        let mut globals = Environment::new();
+        natives::define_globals(&mut globals);
+        globals.set_script_args(lox.script_args.clone());
         Interpreter {
-            environment: Rc::new(RefCell::new(Environment::new())),
+            lox,
+            environment: Rc::new(RefCell::new(globals)),
+            policy,
+            comparison_warnings: false,
+            verbose_errors: false,
+            output: RefCell::new(Output::default()),
+            last_value: RefCell::new(None),
+            echo: true,
+            tracing: false,
+            traced: RefCell::new(vec![]),
+            trace_line: Cell::new(None),
+            profile: None,
+            coverage: None,
+            executed: Cell::new(0),
+            depth: Cell::new(0),
+            limits: Limits::default(),
+            steps: Cell::new(0),
+            deadline: None,
+            loaded: RefCell::new(HashSet::new()),
+            importing: RefCell::new(
+                lox.path
+                    .iter()
+                    .map(|path| fs::canonicalize(path).unwrap_or_else(|_| path.clone()))
+                    .collect(),
+            ),
+        }
+    }
+
+    pub(crate) fn set_comparison_warnings(&mut self, enabled: bool) {
+        self.comparison_warnings = enabled;
+    }
+
+    pub(crate) fn set_verbose_errors(&mut self, enabled: bool) {
+        self.verbose_errors = enabled;
+    }
+
+    /// Directs what the program writes to `output`, after anything it
+    /// already holds.
+    pub(crate) fn set_output(&mut self, output: Output) {
+        self.output = RefCell::new(output);
+    }
+
+    /// The output written so far, including that of a run which failed.
+    pub(crate) fn take_output(&self) -> Output {
+        self.output.take()
+    }
+
+    pub(crate) fn last_value(&self) -> Option<Object> {
+        self.last_value.borrow().clone()
+    }
+
+    /// Sets the limits of the run; a timeout starts counting now.
+    pub(crate) fn set_limits(&mut self, limits: Limits) {
+        self.limits = limits;
+        self.deadline = limits.timeout.map(|timeout| Instant::now() + timeout);
+    }
+
+    pub(crate) fn set_echo(&mut self, enabled: bool) {
+        self.echo = enabled;
+    }
+
+    pub(crate) fn set_tracing(&mut self, enabled: bool) {
+        self.tracing = enabled;
+    }
+
+    pub(crate) fn set_profiling(&mut self, enabled: bool) {
+        self.profile = enabled.then(|| RefCell::new(Profile::default()));
+    }
+
+    pub(crate) fn set_coverage(&mut self, enabled: bool) {
+        self.coverage = enabled.then(|| RefCell::new(HashMap::new()));
+    }
+
+    /// Line hit counts for `--coverage`, if enabled.
+    pub(crate) fn coverage_hits(&self) -> Option<HashMap<usize, usize>> {
+        self.coverage.as_ref().map(|hits| hits.borrow().clone())
+    }
+
+    pub(crate) fn executed(&self) -> usize {
+        self.executed.get()
+    }
+
+    /// The `--profile` summary, if profiling.
+    pub(crate) fn profile_report(&self) -> Option<Vec<String>> {
+        self.profile
+            .as_ref()
+            .map(|profile| profile.borrow().report())
+    }
+
+    /// Counts one statement or loop iteration against
+    /// [`Limits::statements`], and checks the time left.
+    fn step(&self) -> Result<(), RuntimeError> {
+        self.steps.set(self.steps.get() + 1);
+        if let Some(max) = self.limits.statements.filter(|&max| self.steps.get() > max) {
+            return Err(RuntimeError::new(
+                Code::StatementLimitExceeded,
+                &[&max],
+                TokenType::SEMICOLON,
+            ));
+        }
+        if let (Some(deadline), Some(timeout)) = (self.deadline, self.limits.timeout) {
+            if Instant::now() >= deadline {
+                return Err(RuntimeError::new(
+                    Code::TimeoutExceeded,
+                    &[&timeout.as_millis()],
+                    TokenType::SEMICOLON,
+                ));
+            }
         }
+        Ok(())
     }
 
-    pub(crate) fn interpret(
+    /// Starts iteration `iteration` of a loop, counting from 1. Iterations
+    /// are steps too, so that even a loop with an empty body stops.
+    fn loop_iteration(
         &self,
-        stmts: Vec<Declaration>,
-    ) -> Result<Vec<Expr>, RuntimeError> {
-        Ok(stmts
-            .into_iter()
-            .map(|stmt| match stmt {
-                Declaration::Statement(expr) => self.visit_stmt(expr),
-                Declaration::VarDecl(expr) => {
-                    let result = self.visit_var_decl(Box::new(expr))?;
-                    Ok(vec![result])
-                }
-            })
-            .collect::<Result<Vec<Vec<Expr>>, RuntimeError>>()?
-            .into_iter()
-            .flatten()
-            .collect())
+        keyword: TokenType,
+        iteration: usize,
+    ) -> Result<(), RuntimeError> {
+        match self.limits.loop_iterations {
+            Some(max) if iteration > max => {
+                Err(RuntimeError::new(Code::LoopLimitExceeded, &[&max], keyword))
+            }
+            _ => self.step(),
+        }
     }
 
-    fn ensure_literal<'a, 'b>(
-        &'b self,
-        mut expr: Box<Expr<'a>>,
-    ) -> Result<Object, RuntimeError>
-        where
-            'b: 'a,
-    {
-        while !matches!(*expr, Expr::Literal { .. }) {
-            expr = Box::new(self.visit_print_stmt(expr)?);
+    /// Runs `run` as one statement on `line` of the profile, and counts it.
+    fn profiled<T>(&self, line: Option<usize>, run: impl FnOnce() -> T) -> T {
+        self.executed.set(self.executed.get() + 1);
+        // Coverage is of the file being run, not the modules it imports.
+        let in_main_file = self.importing.borrow().len() == self.lox.path.iter().count();
+        if let (Some(coverage), Some(line), true) = (&self.coverage, line, in_main_file) {
+            *coverage.borrow_mut().entry(line).or_default() += 1;
         }
+        let Some(profile) = &self.profile else {
+            return run();
+        };
+        profile.borrow_mut().enter();
+        let result = run();
+        profile.borrow_mut().exit(line);
+        result
+    }
 
-        if let Expr::Literal { value } = *expr {
-            Ok(value)
-        } else {
-            unreachable!() // We ensured it's a Literal in the loop
+    /// Traces a statement about to run, after any assignments still pending
+    /// from the enclosing statement.
+    fn trace_start(&self, line: Option<usize>, source: String) {
+        self.trace_line.set(line);
+        let line = format!("[line {}] {}", self.traced_line(), source);
+        self.write(Stream::Stderr, line);
+    }
+
+    /// Statements built only from literals, like `print 1;`, keep no token
+    /// to take a line from.
+    fn traced_line(&self) -> String {
+        self.trace_line
+            .get()
+            .map_or("?".to_string(), |line| line.to_string())
+    }
+
+    fn trace_assignment(&self, name: Symbol, value: &Object) {
+        if self.tracing {
+            let line = self.traced_line();
+            self.traced
+                .borrow_mut()
+                .push(format!("[line {}]   {} = {}", line, name, value));
         }
     }
 
+    /// Writes the pending `--trace` assignments to stderr, which keeps them
+    /// apart from the output.
+    fn flush_traced(&self) {
+        let mut output = self.output.borrow_mut();
+        // Warnings reported so far go first, so they show beside the
+        // output of the code that caused them.
+        self.lox.flush_diagnostics(&mut output, false);
+        for line in self.traced.take() {
+            output.write(Stream::Stderr, line);
+        }
+    }
+
+    /// Writes a line to the output, after the assignments traced so far.
+    fn write(&self, stream: Stream, line: String) {
+        self.flush_traced();
+        self.output.borrow_mut().write(stream, line);
+    }
+
+    /// Writes what a statement produced: a value or the variables bound.
+    /// Fails once the output has run past its limit, leaving `catch` to
+    /// decide whether the program goes on without it.
+    fn emit(&self, stream: Stream, emitted: Emitted) -> Result<(), RuntimeError> {
+        self.write(stream, emitted.to_string());
+        *self.last_value.borrow_mut() = match emitted {
+            Emitted::Value(value) => Some(value),
+            _ => None,
+        };
+        match self.output.borrow().exceeded_limit() {
+            Some(limit) => Err(RuntimeError {
+                operator: None,
+                ..RuntimeError::new(
+                    Code::OutputLimitExceeded,
+                    &[&limit],
+                    TokenType::PRINT,
+                )
+            }),
+            None => Ok(()),
+        }
+    }
+
+    /// `emitted` as the output of a non-printing statement.
+    fn echo(&self, emitted: Emitted) -> Result<(), RuntimeError> {
+        if self.echo {
+            self.emit(Stream::Stdout, emitted)?;
+        }
+        Ok(())
+    }
+
+    pub(crate) fn interpret(&self, program: &Program) -> Result<(), RuntimeError> {
+        self.visit_block_stmt(&program.arena, &program.declarations)
+    }
+
+    /// Evaluates `expr` one level deeper. The tree is only read, so a loop
+    /// runs the same body and condition on every iteration.
+    fn evaluate(&self, arena: &Arena, expr: ExprId) -> Result<Object, RuntimeError> {
+        self.nested(|| self.visit_expr(arena, &arena[expr]))
+    }
+
     fn visit_unary(
         &self,
+        arena: &Arena,
         operator: &Token,
-        right: Box<Expr>,
+        right: ExprId,
     ) -> Result<Object, RuntimeError> {
-        let right_value = self.ensure_literal(right)?;
-        match operator.token_type {
-            TokenType::BANG => match right_value {
-                Object::Boolean(b) => Ok(Object::Boolean(!b)),
-                Object::Number(_) => Ok(Object::Boolean(false)),
-                Object::Nil => Ok(Object::Boolean(true)),
-                _ => Err(RuntimeError::new(
-                    "Operand must be a boolean or number.".to_string(),
-                    operator.token_type,
-                )),
-            },
-            TokenType::MINUS => match right_value {
-                Object::Number(n) => Ok(Object::Number(-n)),
-                _ => Err(RuntimeError::new(
-                    "Operand must be a number.".to_string(),
-                    operator.token_type,
-                )),
-            },
-            _ => Err(RuntimeError::new(
-                "Invalid unary operator.".to_string(),
-                operator.token_type,
+        let right_value = self.evaluate(arena, right)?;
+        if self.propagates_nil(operator, &[&right_value]) {
+            return Ok(Object::Nil);
+        }
+        match (operator.token_type, &right_value) {
+            (TokenType::BANG, Object::Boolean(b)) => Ok(Object::Boolean(!b)),
+            (TokenType::BANG, Object::Number(_)) => Ok(Object::Boolean(false)),
+            (TokenType::BANG, Object::Nil) => Ok(Object::Boolean(true)),
+            (TokenType::BANG, _) => Err(self.operand_error(
+                Code::OperandMustBeBooleanOrNumber,
+                operator,
+                &[&right_value],
+            )),
+            (TokenType::MINUS, Object::Number(n)) => Ok(Object::Number(-n)),
+            (TokenType::TILDE, Object::Number(n)) => {
+                Ok(Object::Number(!to_int32(*n) as f64))
+            }
+            (TokenType::TILDE, _) => Err(self.operand_error(
+                Code::OperandMustBeNumber,
+                operator,
+                &[&right_value],
+            )),
+            (TokenType::MINUS, _) => Err(self.operand_error(
+                Code::OperandMustBeNumber,
+                operator,
+                &[&right_value],
+            )),
+            _ => Err(self.operand_error(
+                Code::InvalidUnaryOperator,
+                operator,
+                &[&right_value],
             )),
         }
     }
+    /// A chain like `1 + 2 + 3` nests to the left, so its left operands are
+    /// walked down in a loop: a long chain takes no more stack than a short
+    /// one.
     fn visit_binary(
         &self,
+        arena: &Arena,
         operator: &Token,
-        left: Box<Expr>,
-        right: Box<Expr>,
+        left: ExprId,
+        right: ExprId,
     ) -> Result<Object, RuntimeError> {
-        let left_value = self.ensure_literal(left)?;
-        let right_value = self.ensure_literal(right)?;
+        let Expr::Binary { .. } = arena[left] else {
+            let left_value = self.evaluate(arena, left)?;
+            return self.apply_binary(arena, operator, left_value, right);
+        };
+        let mut chain = vec![(operator, right)];
+        let mut leftmost = left;
+        while let Expr::Binary {
+            operator,
+            left,
+            right,
+        } = &arena[leftmost]
+        {
+            chain.push((operator, *right));
+            leftmost = *left;
+        }
+        let mut value = self.evaluate(arena, leftmost)?;
+        for (operator, right) in chain.into_iter().rev() {
+            value = self.apply_binary(arena, operator, value, right)?;
+        }
+        Ok(value)
+    }
 
-        match (left_value, right_value) {
+    /// `operator` applied to an evaluated left operand and to `right`.
+    fn apply_binary(
+        &self,
+        arena: &Arena,
+        operator: &Token,
+        left_value: Object,
+        right: ExprId,
+    ) -> Result<Object, RuntimeError> {
+        if let Some(base) = operator.token_type.compound_operator() {
+            let mut operator = operator.clone();
+            operator.token_type = base;
+            return self.apply_binary(arena, &operator, left_value, right);
+        }
+        if operator.token_type == TokenType::QUESTION_QUESTION {
+            // Short-circuits: `right` is only evaluated when `left` is nil.
+            return match left_value {
+                Object::Nil => self.evaluate(arena, right),
+                value => Ok(value),
+            };
+        }
+        let right_value = self.evaluate(arena, right)?;
+        if operator.token_type == TokenType::COMMA {
+            return Ok(right_value);
+        }
+        if self.propagates_nil(operator, &[&left_value, &right_value]) {
+            return Ok(Object::Nil);
+        }
+        if self.comparison_warnings {
+            self.warn_mixed_comparison(operator, &left_value, &right_value);
+        }
+        let error = |code: Code| {
+            Err(self.operand_error(code, operator, &[&left_value, &right_value]))
+        };
+
+        match (&left_value, &right_value) {
             (Object::Number(left), Object::Number(right)) => match operator.token_type {
                 TokenType::PLUS => Ok(Object::Number(left + right)),
                 TokenType::MINUS => Ok(Object::Number(left - right)),
                 TokenType::STAR => Ok(Object::Number(left * right)),
                 TokenType::SLASH => {
-                    if right == 0.0 {
-                        Err(RuntimeError::new(
-                            "Division by zero.".to_string(),
-                            operator.token_type,
-                        ))
+                    if *right == 0.0 {
+                        error(Code::DivisionByZero)
                     } else {
                         Ok(Object::Number(left / right))
                     }
@@ -133,183 +684,1068 @@ impl Interpreter {
                 TokenType::BANG_EQUAL => Ok(Object::Boolean(left != right)),
                 TokenType::GREATER_EQUAL => Ok(Object::Boolean(left >= right)),
                 TokenType::GREATER => Ok(Object::Boolean(left > right)),
-                _ => Err(RuntimeError::new(
-                    "Invalid binary operator for numbers.".to_string(),
-                    operator.token_type,
+                // Bitwise operators work on the operands converted to 32-bit
+                // integers; shift counts use only their low five bits.
+                TokenType::AMPERSAND => {
+                    Ok(Object::Number((to_int32(*left) & to_int32(*right)) as f64))
+                }
+                TokenType::PIPE => {
+                    Ok(Object::Number((to_int32(*left) | to_int32(*right)) as f64))
+                }
+                TokenType::CARET => {
+                    Ok(Object::Number((to_int32(*left) ^ to_int32(*right)) as f64))
+                }
+                TokenType::LESS_LESS => Ok(Object::Number(
+                    (to_int32(*left) << (to_int32(*right) & 31)) as f64,
+                )),
+                TokenType::GREATER_GREATER => Ok(Object::Number(
+                    (to_int32(*left) >> (to_int32(*right) & 31)) as f64,
                 )),
+                _ => error(Code::InvalidNumberOperator),
             },
             (Object::String(left), Object::String(right)) => match operator.token_type {
-                TokenType::PLUS => Ok(Object::String(left + right.as_str())),
+                TokenType::PLUS => {
+                    Ok(Object::String(format!("{}{}", left, right).into()))
+                }
                 TokenType::EQUAL_EQUAL => Ok(Object::Boolean(left == right)),
                 TokenType::BANG_EQUAL => Ok(Object::Boolean(left != right)),
-                _ => Err(RuntimeError::new(
-                    "Invalid binary operator for strings.".to_string(),
-                    operator.token_type,
-                )),
+                _ => error(Code::InvalidStringOperator),
+            },
+            (Object::List(left), Object::List(right)) => match operator.token_type {
+                TokenType::EQUAL_EQUAL => Ok(Object::Boolean(Rc::ptr_eq(left, right))),
+                TokenType::BANG_EQUAL => Ok(Object::Boolean(!Rc::ptr_eq(left, right))),
+                _ => error(Code::InvalidBinaryOperands),
+            },
+            (Object::Map(left), Object::Map(right)) => match operator.token_type {
+                TokenType::EQUAL_EQUAL => Ok(Object::Boolean(Rc::ptr_eq(left, right))),
+                TokenType::BANG_EQUAL => Ok(Object::Boolean(!Rc::ptr_eq(left, right))),
+                _ => error(Code::InvalidBinaryOperands),
             },
             (_, _) if matches!(operator.token_type, TokenType::EQUAL_EQUAL) => {
                 Ok(Object::Boolean(false))
             }
-            _ => Err(RuntimeError::new(
-                "Invalid operands for binary operator.".to_string(),
+            _ if operator.token_type == TokenType::PLUS => {
+                error(Code::OperandsMustBeTwoNumbersOrTwoStrings)
+            }
+            _ => error(Code::InvalidBinaryOperands),
+        }
+    }
+
+    /// Every unary/binary operand error is built here so that, with verbose
+    /// errors enabled, each message carries the same operand description,
+    /// e.g. `cannot add string "a" and number 3`.
+    fn operand_error(
+        &self,
+        code: Code,
+        operator: &Token,
+        operands: &[&Object],
+    ) -> RuntimeError {
+        let error = RuntimeError::new(code, &[], operator.token_type).at(operator);
+        if !self.verbose_errors {
+            return error;
+        }
+        let detail_code = match (operator.token_type, operands.len()) {
+            (TokenType::MINUS, 1) => Code::CannotNegate,
+            (_, 1) => Code::CannotApplyUnary,
+            (TokenType::PLUS, _) => Code::CannotAdd,
+            (TokenType::MINUS, _) => Code::CannotSubtract,
+            (TokenType::STAR, _) => Code::CannotMultiply,
+            (TokenType::SLASH, _) => Code::CannotDivide,
+            (token_type, _)
+                if token_type.precedence() == Some(Precedence::Comparison) =>
+            {
+                Code::CannotCompare
+            }
+            _ => Code::CannotApplyBinary,
+        };
+        // The operator goes last, for the messages that name it.
+        let operands = operands
+            .iter()
+            .map(|operand| match operand {
+                Object::String(s) => format!("string {:?}", s),
+                Object::Nil => "nil".to_string(),
+                _ => format!("{} {:?}", operand.type_name(), operand),
+            })
+            .chain([operator.token_type.spelling().to_string()])
+            .collect::<Vec<String>>();
+        let args = operands
+            .iter()
+            .map(|operand| operand as &dyn Display)
+            .collect::<Vec<_>>();
+        let detail = message(detail_code, &args);
+        RuntimeError {
+            message: message(Code::OperandDetail, &[&error.message, &detail]).into(),
+            ..error
+        }
+    }
+
+    /// Under [`ErrorPolicy::PropagateNil`], arithmetic operators short-circuit
+    /// to `nil` as soon as any operand is `nil`.
+    fn propagates_nil(&self, operator: &Token, operands: &[&Object]) -> bool {
+        self.policy == ErrorPolicy::PropagateNil
+            && matches!(
                 operator.token_type,
-            )),
+                TokenType::PLUS | TokenType::MINUS | TokenType::STAR | TokenType::SLASH
+            )
+            && operands
+                .iter()
+                .any(|operand| matches!(operand, Object::Nil))
+    }
+
+    fn warn_mixed_comparison(&self, operator: &Token, left: &Object, right: &Object) {
+        if let Some(warning) = Self::mixed_comparison_warning(operator, left, right) {
+            self.lox.warn(operator.line, Code::MixedComparison, warning);
         }
     }
 
-    fn visit_grouping(&self, expr: Box<Expr>) -> Result<Object, RuntimeError> {
-        self.ensure_literal(expr)
+    /// Ordering operands of different types always fails; the warning names
+    /// the operand types so the user can tell which side needs converting.
+    fn mixed_comparison_warning(
+        operator: &Token,
+        left: &Object,
+        right: &Object,
+    ) -> Option<String> {
+        if operator.token_type.precedence() != Some(Precedence::Comparison)
+            || std::mem::discriminant(left) == std::mem::discriminant(right)
+        {
+            return None;
+        }
+        let hint = match (left, right) {
+            (Object::Number(_), Object::String(_)) => {
+                format!(" {}", message(Code::ConvertRightOperand, &[]))
+            }
+            (Object::String(_), Object::Number(_)) => {
+                format!(" {}", message(Code::ConvertLeftOperand, &[]))
+            }
+            _ => String::new(),
+        };
+        let warning = message(
+            Code::MixedComparison,
+            &[
+                &left.type_name(),
+                &right.type_name(),
+                &String::from_utf8_lossy(&operator.lexeme),
+            ],
+        );
+        Some(format!("{}{}", warning, hint))
     }
+
+    fn visit_call(
+        &self,
+        arena: &Arena,
+        callee: ExprId,
+        paren: &Token,
+        arguments: &[ExprId],
+    ) -> Result<Object, RuntimeError> {
+        let callee = self.evaluate(arena, callee)?;
+        let arguments = self.visit_elements(arena, arguments)?;
+
+        let Object::Native(native) = callee else {
+            return Err(
+                RuntimeError::new(Code::NotCallable, &[], paren.token_type).at(paren)
+            );
+        };
+        if arguments.len() != native.arity {
+            return Err(RuntimeError::new(
+                Code::ArityMismatch,
+                &[&native.arity, &arguments.len()],
+                paren.token_type,
+            )
+            .at(paren));
+        }
+        let started = Instant::now();
+        let result = (native.function)(&self.environment.borrow(), &arguments);
+        if let Some(profile) = &self.profile {
+            profile.borrow_mut().native(native.name, started.elapsed());
+        }
+        result.map_err(|error| {
+            let mut error = if error.line.is_none() {
+                error.at(paren)
+            } else {
+                error
+            };
+            error
+                .trace
+                .push(message(Code::TraceInScript, &[&paren.line]));
+            error
+        })
+    }
+
+    /// Evaluates argument or list elements in order, splicing in the
+    /// elements of each `...list`.
+    fn visit_elements(
+        &self,
+        arena: &Arena,
+        elements: &[ExprId],
+    ) -> Result<Vec<Object>, RuntimeError> {
+        let mut values = vec![];
+        for &element in elements {
+            let Expr::Spread { ellipsis, value } = &arena[element] else {
+                values.push(self.evaluate(arena, element)?);
+                continue;
+            };
+            match self.evaluate(arena, *value)? {
+                Object::List(list) => values.extend(list.borrow().iter().cloned()),
+                value => {
+                    return Err(RuntimeError::new(
+                        Code::CannotSpread,
+                        &[&value.type_name()],
+                        ellipsis.token_type,
+                    )
+                    .at(ellipsis))
+                }
+            }
+        }
+        Ok(values)
+    }
+
+    /// Evaluates `object[index]` down to the element it designates.
+    fn visit_index(
+        &self,
+        arena: &Arena,
+        object: ExprId,
+        bracket: &Token,
+        index: ExprId,
+    ) -> Result<Element, RuntimeError> {
+        let object = self.evaluate(arena, object)?;
+        let index = self.evaluate(arena, index)?;
+        let error = |code: Code, args: &[&dyn Display]| {
+            RuntimeError::new(code, args, bracket.token_type).at(bracket)
+        };
+        let list = match object {
+            Object::List(list) => list,
+            Object::Map(map) => {
+                let Some(key) = index.to_key() else {
+                    return Err(error(Code::InvalidMapKey, &[&index.type_name()]));
+                };
+                return Ok(Element::Entry(map, key));
+            }
+            _ => return Err(error(Code::NotIndexable, &[&object.type_name()])),
+        };
+        let i = match index {
+            Object::Number(n) if n.fract() == 0.0 => n,
+            _ => return Err(error(Code::IndexNotInteger, &[&format!("{:?}", index)])),
+        };
+        let len = list.borrow().len();
+        if i < 0.0 || i as usize >= len {
+            return Err(error(
+                Code::IndexOutOfBounds,
+                &[&format!("{:?}", index), &len],
+            ));
+        }
+        Ok(Element::Item(list, i as usize))
+    }
+
+    fn visit_map(
+        &self,
+        arena: &Arena,
+        brace: &Token,
+        entries: &[(ExprId, ExprId)],
+    ) -> Result<Object, RuntimeError> {
+        let mut map = HashMap::new();
+        for &(key, value) in entries {
+            let key = self.evaluate(arena, key)?;
+            let Some(key) = key.to_key() else {
+                return Err(RuntimeError::new(
+                    Code::InvalidMapKey,
+                    &[&key.type_name()],
+                    brace.token_type,
+                )
+                .at(brace));
+            };
+            map.insert(key, self.evaluate(arena, value)?);
+        }
+        Ok(Object::Map(Rc::new(RefCell::new(map))))
+    }
+
     fn visit_assignment(
         &self,
-        identifier: String,
-        value: Box<Expr>,
-    ) -> Result<Expr, RuntimeError> {
-        let obj = self.ensure_literal(value)?;
+        arena: &Arena,
+        identifier: Symbol,
+        line: usize,
+        slot: Option<Slot>,
+        value: ExprId,
+    ) -> Result<Object, RuntimeError> {
+        let obj = self.evaluate(arena, value)?;
         self.environment
             .borrow_mut()
-            .set(identifier.clone(), obj.clone());
-        Ok(Expr::Assign {
-            identifier,
-            value: Box::new(Expr::Literal { value: obj }),
-        })
+            .assign(identifier, slot, obj.clone())
+            .map_err(|error| error.or_line(Some(line)))?;
+        self.trace_assignment(identifier, &obj);
+        Ok(obj)
     }
 
-    fn visit_expr_stmt(&self, expr: Box<Expr>) -> Result<Expr, RuntimeError> {
-        match *expr {
-            Expr::Assign { identifier, value } => {
-                self.visit_assignment(identifier, value)
+    /// What an expression statement shows in `evaluate`: its value, or for
+    /// an assignment the assignment itself with the value assigned.
+    fn visit_expr_stmt(
+        &self,
+        arena: &Arena,
+        expr: ExprId,
+    ) -> Result<Emitted, RuntimeError> {
+        match arena[expr] {
+            Expr::Assign {
+                identifier,
+                line,
+                value,
+                slot,
+            } => {
+                let value =
+                    self.visit_assignment(arena, identifier, line, slot, value)?;
+                Ok(Emitted::Assigned(identifier, value))
             }
-            _ => unreachable!(),
+            // Calls are the only other expressions worth a statement; evaluate
+            // anything else the same way for its side effects.
+            _ => Ok(Emitted::Value(self.evaluate(arena, expr)?)),
         }
     }
 
-    fn visit_print_stmt(&self, expr: Box<Expr>) -> Result<Expr, RuntimeError> {
-        match *expr {
-            Expr::Literal { value } => Ok(Expr::Literal { value }),
-            Expr::Unary { operator, right } => {
-                let value = self.visit_unary(operator, right)?;
-                Ok(Expr::Literal { value })
-            }
+    fn visit_expr(&self, arena: &Arena, expr: &Expr) -> Result<Object, RuntimeError> {
+        match expr {
+            Expr::Literal { value, .. } => Ok(value.clone()),
+            Expr::Unary { operator, right } => self.visit_unary(arena, operator, *right),
             Expr::Binary {
                 operator,
                 left,
                 right,
+            } => self.visit_binary(arena, operator, *left, *right),
+            Expr::Grouping { expression } => self.evaluate(arena, *expression),
+            Expr::Variable {
+                identifier,
+                line,
+                slot,
+            } => match self.environment.borrow().get(*identifier, *slot) {
+                Ok(value) => Ok(value.clone()),
+                Err(error) => Err(error.or_line(Some(*line))),
+            },
+            Expr::Call {
+                callee,
+                paren,
+                arguments,
+            } => self.visit_call(arena, *callee, paren, arguments),
+            Expr::Conditional {
+                condition,
+                then_branch,
+                else_branch,
             } => {
-                let value = self.visit_binary(operator, left, right)?;
-                Ok(Expr::Literal { value })
-            }
-            Expr::Grouping { expression } => {
-                let value = self.visit_grouping(expression)?;
-                Ok(Expr::Literal { value })
-            }
-            Expr::Variable { identifier: value } => {
-                let var_res = self.environment.borrow().get(value)?.clone();
-                Ok(Expr::Literal { value: var_res })
-            }
-            Expr::Assign { identifier, value } => {
-                let assignment = self.visit_assignment(identifier, value)?;
-                match assignment {
-                    Expr::Assign {
-                        identifier: _,
-                        value,
-                    } => Ok(*value),
-                    _ => unreachable!(),
-                }
+                let branch =
+                    if self.visit_condition(arena, *condition, TokenType::QUESTION)? {
+                        then_branch
+                    } else {
+                        else_branch
+                    };
+                self.evaluate(arena, *branch)
+            }
+            Expr::List { elements } => {
+                let elements = self.visit_elements(arena, elements)?;
+                Ok(Object::List(Rc::new(RefCell::new(elements))))
             }
+            Expr::Map { brace, entries } => self.visit_map(arena, brace, entries),
+            Expr::Index {
+                object,
+                bracket,
+                index,
+            } => Ok(self.visit_index(arena, *object, bracket, *index)?.get()),
+            Expr::IndexSet {
+                object,
+                bracket,
+                index,
+                operator,
+                value,
+            } => {
+                let element = self.visit_index(arena, *object, bracket, *index)?;
+                let value = if operator.token_type == TokenType::EQUAL {
+                    self.evaluate(arena, *value)?
+                } else {
+                    self.apply_binary(arena, operator, element.get(), *value)?
+                };
+                element.set(value.clone());
+                Ok(value)
+            }
+            Expr::Spread { ellipsis, .. } => {
+                Err(
+                    RuntimeError::new(Code::SpreadNotAllowed, &[], ellipsis.token_type)
+                        .at(ellipsis),
+                )
+            }
+            Expr::Assign {
+                identifier,
+                line,
+                value,
+                slot,
+            } => self.visit_assignment(arena, *identifier, *line, *slot, *value),
         }
     }
 
     fn visit_block_stmt(
         &self,
-        decls: Vec<Declaration>,
-    ) -> Result<Vec<Expr>, RuntimeError> {
-        let mut results = vec![];
+        arena: &Arena,
+        decls: &[Declaration],
+    ) -> Result<(), RuntimeError> {
         for decl in decls {
+            if self.tracing && !matches!(decl, Declaration::Statement(_)) {
+                self.trace_start(decl.line(arena), formatter::summary(arena, decl));
+            }
             match decl {
-                Declaration::VarDecl(expr) => {
-                    let result = self.visit_var_decl(Box::new(expr))?;
-                    results.push(result);
+                Declaration::Var {
+                    keyword,
+                    target,
+                    initializer,
+                    constant,
+                    doc,
+                } => {
+                    self.step()
+                        .map_err(|error| error.or_line(Some(keyword.line)))?;
+                    let result = self
+                        .profiled(Some(keyword.line), || {
+                            self.visit_var_decl(
+                                arena,
+                                keyword,
+                                *target,
+                                *initializer,
+                                !constant,
+                                doc,
+                            )
+                        })
+                        .map_err(|error| error.or_line(Some(keyword.line)))?;
+                    self.flush_traced();
+                    self.echo(result)
+                        .map_err(|error| error.or_line(Some(keyword.line)))?;
                 }
-                Declaration::Statement(stmt) => {
-                    let stmt_results = self.visit_stmt(stmt)?;
-                    results.extend(stmt_results);
+                Declaration::Statement(stmt) => self.visit_stmt(arena, stmt)?,
+            }
+        }
+        Ok(())
+    }
+
+    /// Runs `run` one level deeper, failing with a stack overflow past
+    /// [`Limits::depth`].
+    fn nested<T>(
+        &self,
+        run: impl FnOnce() -> Result<T, RuntimeError>,
+    ) -> Result<T, RuntimeError> {
+        if self.depth.get() >= self.limits.depth {
+            return Err(RuntimeError::new(
+                Code::StackOverflow,
+                &[],
+                TokenType::LEFT_PAREN,
+            ));
+        }
+        self.depth.set(self.depth.get() + 1);
+        let result = run();
+        self.depth.set(self.depth.get() - 1);
+        result
+    }
+
+    /// Runs `run` in a new innermost scope, which is dropped afterwards even
+    /// when it fails.
+    fn in_scope<T>(&self, run: impl FnOnce() -> T) -> T {
+        self.environment.borrow_mut().push_scope();
+        let result = run();
+        self.environment.borrow_mut().pop_scope();
+        result
+    }
+
+    /// Conditions accept booleans and `nil`; anything else is a runtime error.
+    fn visit_condition(
+        &self,
+        arena: &Arena,
+        condition: ExprId,
+        operator: TokenType,
+    ) -> Result<bool, RuntimeError> {
+        match self.evaluate(arena, condition)? {
+            Object::Boolean(b) => Ok(b),
+            Object::Nil => Ok(false),
+            _ => Err(RuntimeError::new(Code::ConditionNotBoolean, &[], operator)),
+        }
+    }
+
+    fn visit_if_stmt(&self, arena: &Arena, if_: &If) -> Result<(), RuntimeError> {
+        let If {
+            condition,
+            then_branch,
+            else_branch,
+            ..
+        } = if_;
+
+        let branch = if self.visit_condition(arena, *condition, TokenType::IF)? {
+            Some(then_branch)
+        } else {
+            else_branch.as_ref()
+        };
+
+        match branch {
+            None => self.echo(Emitted::Value(Object::Nil)),
+            Some(stmt) => self.visit_stmt(arena, stmt),
+        }
+    }
+
+    /// Runs the first case holding a value `==` to the scrutinee, else the
+    /// default. Case values are evaluated in order only until one matches.
+    fn visit_switch_stmt(
+        &self,
+        arena: &Arena,
+        switch: &Switch,
+    ) -> Result<(), RuntimeError> {
+        let Switch {
+            keyword,
+            value,
+            cases,
+            default,
+        } = switch;
+        let value = self.evaluate(arena, *value)?;
+        let equal =
+            Token::new(TokenType::EQUAL_EQUAL, b"==", "null".into(), keyword.line);
+        for (candidates, body) in cases {
+            for &candidate in candidates {
+                if let Object::Boolean(true) =
+                    self.apply_binary(arena, &equal, value.clone(), candidate)?
+                {
+                    return self.in_scope(|| self.visit_block_stmt(arena, body));
                 }
             }
         }
-        Ok(results)
+        match default {
+            Some(body) => self.in_scope(|| self.visit_block_stmt(arena, body)),
+            None => Ok(()),
+        }
     }
 
-    fn visit_if_stmt(&self, if_: If) -> Result<Vec<Expr>, RuntimeError> {
-        let If { condition, then_branch, else_branch } = if_;
+    /// Runs the module at `path`, relative to the importing file, in the
+    /// global environment so its top-level declarations become visible.
+    /// Each module runs at most once; importing one that is still being
+    /// imported is an error.
+    fn visit_import_stmt(&self, keyword: &Token, path: &str) -> Result<(), RuntimeError> {
+        let error = |code: Code| {
+            RuntimeError::new(code, &[&path], keyword.token_type).at(keyword)
+        };
+        let resolved = match self.importing.borrow().last() {
+            Some(importer) => importer.parent().unwrap_or(Path::new("")).join(path),
+            None => PathBuf::from(path),
+        };
+        let module =
+            fs::canonicalize(&resolved).map_err(|_| error(Code::ModuleNotFound))?;
+        if self.importing.borrow().contains(&module) {
+            return Err(error(Code::CircularImport));
+        }
+        if !self.loaded.borrow_mut().insert(module.clone()) {
+            return Ok(());
+        }
+        let source =
+            fs::read_to_string(&module).map_err(|_| error(Code::ModuleNotFound))?;
+        let file = Arc::new(SourceFile {
+            path: resolved,
+            text: source,
+        });
+        let source = &file.text;
 
-        let is_condition = self.visit_print_stmt(condition)?;
-        let branch = match is_condition {
-            Expr::Literal { value } => match value {
-                Object::Boolean(true) => Ok(Some(then_branch)),
-                Object::Boolean(false) | Object::Nil => Ok(else_branch),
-                _ => Err(RuntimeError {
-                    message: "Expected result of condition to be boolean or nil".into(),
-                    operator: TokenType::IF,
+        let errors = self.lox.error_count();
+        let reported = self.lox.diagnostics.borrow().len();
+        let mut scanner = Scanner::new(source.as_bytes(), self.lox);
+        let tokens = scanner.scan_tokens();
+        let expanded;
+        let tokens = if self.lox.extensions {
+            expanded = Preprocessor::new(tokens, self.lox).expand();
+            &expanded
+        } else {
+            tokens
+        };
+        let parser = Parser::new(tokens, self.lox);
+        let mut program = parser.parse();
+        resolver::resolve(&program.declarations, &mut program.arena, self.lox);
+        for diagnostic in &mut self.lox.diagnostics.borrow_mut()[reported..] {
+            diagnostic.file.get_or_insert_with(|| file.clone());
+        }
+        self.lox
+            .flush_diagnostics(&mut self.output.borrow_mut(), true);
+        if self.lox.error_count() > errors {
+            return Err(error(Code::ModuleHasErrors));
+        }
+
+        self.importing.borrow_mut().push(module);
+        let scopes = self.environment.borrow_mut().take_scopes();
+        let result = self.interpret(&program);
+        self.environment.borrow_mut().restore_scopes(scopes);
+        self.importing.borrow_mut().pop();
+        // Errors from a module it imported in turn already have their file.
+        result.map_err(|mut error| {
+            error.file.get_or_insert(file);
+            error
+        })
+    }
+
+    /// Runs `catch` if `body` raised an error, then `finally` whatever
+    /// happened. Output written before the error is kept. Panics and
+    /// exceeded limits are never caught.
+    fn visit_try_stmt(&self, arena: &Arena, try_: &Try) -> Result<(), RuntimeError> {
+        let Try {
+            body,
+            catch,
+            finally,
+        } = try_;
+        let outcome = self.in_scope(|| self.visit_block_stmt(arena, body));
+        let outcome = match (outcome, catch) {
+            (Err(err), Some((name, handler))) if err.is_catchable() => {
+                self.in_scope(|| {
+                    let value = err.into_value();
+                    self.trace_assignment(*name, &value);
+                    self.environment.borrow_mut().define(*name, value);
+                    self.visit_block_stmt(arena, handler)
                 })
-            },
-            _ => Err(RuntimeError {
-                message: "Expected result of condition to be boolean or nil".into(),
-                operator: TokenType::IF,
-            })
+            }
+            (outcome, _) => outcome,
         };
+        if let Some(finally) = finally {
+            self.in_scope(|| self.visit_block_stmt(arena, finally))?;
+        }
+        outcome
+    }
 
-        match branch? {
-            None => Ok(vec![Expr::Literal { value: Object::Nil }]),
-            Some(stmt) => self.visit_stmt(*stmt)
+    /// Binds `variable` to each element of a list, each key of a map (in
+    /// printing order) or each character of a string. The elements are
+    /// taken when the loop starts, so the body may modify the collection.
+    fn visit_for_in_stmt(
+        &self,
+        arena: &Arena,
+        keyword: &Token,
+        variable: Symbol,
+        iterable: ExprId,
+        body: &Statement,
+    ) -> Result<(), RuntimeError> {
+        let elements = match self.evaluate(arena, iterable)? {
+            Object::List(list) => list.borrow().clone(),
+            Object::Map(map) => MapKey::sorted(&map.borrow())
+                .iter()
+                .map(MapKey::to_object)
+                .collect(),
+            Object::String(s) => s
+                .chars()
+                .map(|c| Object::String(c.to_string().into()))
+                .collect(),
+            value => {
+                return Err(RuntimeError::new(
+                    Code::NotIterable,
+                    &[&value.type_name()],
+                    keyword.token_type,
+                )
+                .at(keyword))
+            }
+        };
+        for (iteration, element) in elements.into_iter().enumerate() {
+            self.loop_iteration(keyword.token_type, iteration + 1)?;
+            // Each iteration binds the variable in a scope of its own.
+            self.in_scope(|| {
+                self.trace_assignment(variable, &element);
+                self.environment.borrow_mut().define(variable, element);
+                self.visit_stmt(arena, body)
+            })?;
+        }
+        Ok(())
+    }
+
+    fn visit_do_while_stmt(
+        &self,
+        arena: &Arena,
+        body: &Statement,
+        condition: ExprId,
+    ) -> Result<(), RuntimeError> {
+        let mut iteration = 0;
+        loop {
+            iteration += 1;
+            self.loop_iteration(TokenType::DO, iteration)?;
+            self.visit_stmt(arena, body)?;
+            if !self.visit_condition(arena, condition, TokenType::WHILE)? {
+                return Ok(());
+            }
         }
     }
 
-    fn visit_stmt(&self, stmt: Statement) -> Result<Vec<Expr>, RuntimeError> {
+    /// Runs a statement, timing it with `--profile` and counting its line
+    /// with `--coverage`.
+    fn visit_stmt(&self, arena: &Arena, stmt: &Statement) -> Result<(), RuntimeError> {
+        if matches!(stmt, Statement::Block(_)) {
+            return self.nested(|| self.trace_stmt(arena, stmt));
+        }
+        let measured = self.profile.is_some() || self.coverage.is_some();
+        let line = measured.then(|| stmt.line(arena)).flatten();
+        self.step()
+            .and_then(|()| {
+                self.profiled(line, || self.nested(|| self.trace_stmt(arena, stmt)))
+            })
+            .map_err(|error| error.or_line(stmt.line(arena)))
+    }
+
+    /// With `--trace`, writes the statement's source to stderr before it
+    /// runs and the assignments it made after.
+    fn trace_stmt(&self, arena: &Arena, stmt: &Statement) -> Result<(), RuntimeError> {
+        if !self.tracing || matches!(stmt, Statement::Block(_)) {
+            return self.execute_stmt(arena, stmt);
+        }
+        let source = formatter::statement_summary(arena, stmt);
+        self.trace_start(stmt.line(arena), source);
+        self.execute_stmt(arena, stmt)?;
+        // Nested statements and output take the assignments made before
+        // them, so this is only left with those made after.
+        self.flush_traced();
+        Ok(())
+    }
+
+    fn execute_stmt(&self, arena: &Arena, stmt: &Statement) -> Result<(), RuntimeError> {
         match stmt {
             Statement::PrintStmt(expr) => {
-                let result = self.visit_print_stmt(Box::new(expr))?;
-                Ok(vec![result])
+                let value = self.evaluate(arena, *expr)?;
+                self.emit(Stream::Stdout, Emitted::Value(value))
+            }
+            Statement::EPrintStmt(expr) => {
+                let value = self.evaluate(arena, *expr)?;
+                self.emit(Stream::Stderr, Emitted::Value(value))
             }
             Statement::ExprStmt(expr) => {
-                let result = self.visit_expr_stmt(Box::new(expr))?;
-                Ok(vec![result])
+                let result = self.visit_expr_stmt(arena, *expr)?;
+                self.echo(result)
+            }
+            Statement::IfStmt(if_) => self.visit_if_stmt(arena, if_),
+            Statement::Block(decls) => {
+                self.in_scope(|| self.visit_block_stmt(arena, decls))
             }
-            Statement::IfStmt(if_) => {
-                let result = self.visit_if_stmt(if_)?;
-                Ok(result)
+            Statement::Switch(switch) => self.visit_switch_stmt(arena, switch),
+            Statement::DoWhile { body, condition } => {
+                self.visit_do_while_stmt(arena, body, *condition)
             }
-            Statement::Block(decls) => self.visit_block_stmt(decls),
-            _ => unreachable!()
+            Statement::ForIn {
+                keyword,
+                variable,
+                iterable,
+                body,
+            } => self.visit_for_in_stmt(arena, keyword, *variable, *iterable, body),
+            Statement::Import { keyword, path } => self.visit_import_stmt(keyword, path),
+            Statement::Try(try_) => self.visit_try_stmt(arena, try_),
+            Statement::Throw { keyword, value } => {
+                let value = self.evaluate(arena, *value)?;
+                Err(RuntimeError {
+                    thrown: Some(Box::new(value.clone())),
+                    ..RuntimeError::new(
+                        Code::UncaughtThrow,
+                        &[&value],
+                        keyword.token_type,
+                    )
+                    .at(keyword)
+                })
+            }
+            _ => unreachable!(),
         }
     }
 
-    fn visit_var_decl(&self, decl: Box<Expr>) -> Result<Expr, RuntimeError> {
-        match *decl {
-            Expr::Unary { operator: _, right } => match *right {
-                Expr::Variable { identifier } => {
-                    self.environment
-                        .borrow_mut()
-                        .set(identifier.clone(), Object::Nil);
-                    Ok(Expr::Variable { identifier })
+    /// Binds the names in `target` to the initializer's value, or to `nil`
+    /// without one, and evaluates to the variables bound.
+    fn visit_var_decl(
+        &self,
+        arena: &Arena,
+        keyword: &Token,
+        target: ExprId,
+        initializer: Option<ExprId>,
+        mutable: bool,
+        doc: &Option<String>,
+    ) -> Result<Emitted, RuntimeError> {
+        let value = match initializer {
+            Some(initializer) => self.evaluate(arena, initializer)?,
+            None => Object::Nil,
+        };
+        let bindings = self.destructure(arena, keyword, target, value)?;
+        let mut environment = self.environment.borrow_mut();
+        let names = bindings
+            .into_iter()
+            .map(|(identifier, value)| {
+                self.trace_assignment(identifier, &value);
+                if mutable {
+                    environment.define(identifier, value);
+                } else {
+                    environment.define_constant(identifier, value);
                 }
-                Expr::Binary {
-                    operator: _,
-                    left,
-                    right,
-                } => {
-                    let value = self.ensure_literal(right)?;
-                    if let Expr::Variable { identifier } = *left {
-                        self.environment
-                            .borrow_mut()
-                            .set(identifier.clone(), value.clone());
-                        return Ok(Expr::Variable { identifier });
-                    }
-                    unreachable!();
+                if let Some(doc) = doc {
+                    environment.set_doc(identifier, doc.clone());
                 }
-                _ => unreachable!(),
-            },
+                identifier
+            })
+            .collect();
+        Ok(Emitted::Bound(names))
+    }
+
+    /// Pairs each variable in `pattern` with its part of `value`. List
+    /// elements and map keys the value lacks bind `nil`.
+    fn destructure(
+        &self,
+        arena: &Arena,
+        keyword: &Token,
+        pattern: ExprId,
+        value: Object,
+    ) -> Result<Vec<(Symbol, Object)>, RuntimeError> {
+        let name = |variable: &ExprId| match arena[*variable] {
+            Expr::Variable { identifier, .. } => identifier,
             _ => unreachable!(),
+        };
+        match (&arena[pattern], &value) {
+            (Expr::Variable { identifier, .. }, _) => Ok(vec![(*identifier, value)]),
+            (Expr::List { elements }, Object::List(list)) => {
+                let list = list.borrow();
+                Ok(elements
+                    .iter()
+                    .enumerate()
+                    .map(|(index, element)| {
+                        (
+                            name(element),
+                            list.get(index).cloned().unwrap_or(Object::Nil),
+                        )
+                    })
+                    .collect())
+            }
+            (Expr::Map { entries, .. }, Object::Map(map)) => {
+                let map = map.borrow();
+                Ok(entries
+                    .iter()
+                    .map(|(_, variable)| {
+                        let identifier = name(variable);
+                        let key = MapKey::String(identifier.text());
+                        (identifier, map.get(&key).cloned().unwrap_or(Object::Nil))
+                    })
+                    .collect())
+            }
+            (pattern, value) => Err(RuntimeError::new(
+                Code::CannotDestructure,
+                &[
+                    &value.type_name(),
+                    &if matches!(pattern, Expr::List { .. }) {
+                        "list"
+                    } else {
+                        "map"
+                    },
+                ],
+                keyword.token_type,
+            )
+            .at(keyword)),
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{stdout_lines, temp_dir};
+    use crate::Lox;
+
+    /// What `evaluate` prints for `source`.
+    fn evaluate(source: &str) -> Vec<String> {
+        Lox::new()
+            .run("evaluate", source)
+            .output
+            .lines()
+            .iter()
+            .map(|(_, line)| line.clone())
+            .collect()
+    }
+
+    #[test]
+    fn test_comma_operator_yields_its_last_operand() {
+        assert_eq!(
+            evaluate("var a = 0;\nprint (a = 1, a + 1);\nprint a;\nprint 1, 2, 3;"),
+            vec!["variable a", "2", "1", "3"]
+        );
+    }
+
+    #[test]
+    fn test_conditional_binds_loosely_and_nests_to_the_right() {
+        let mut lox = Lox::new();
+        lox.extensions = true;
+
+        let outcome = lox.run(
+            "run",
+            "var a;\na = true ? 1 : 2;\nprint a;\n\
+             print false ? 1 : true ? 2 : 3;\n\
+             print nil ?? false ? \"x\" : \"y\";\n\
+             print true ? \"yes\" : undefined;\nprint false ? undefined : \"no\";",
+        );
+        let missing = lox.run("parse", "print true ? 1;");
+
+        let printed = stdout_lines(&outcome);
+        assert_eq!(printed, vec!["1", "2", "y", "yes", "no"]);
+        assert!(outcome.diagnostics.is_empty());
+        assert_eq!(
+            missing.diagnostics[0].code,
+            Some(Code::ExpectColonInConditional)
+        );
+    }
+
+    #[test]
+    fn test_compound_assignment_updates_the_target() {
+        let mut lox = Lox::new();
+        lox.extensions = true;
+
+        let outcome = lox.run(
+            "run",
+            "var n = 10;\nn += 5;\nn -= 3;\nn *= 2;\nn /= 4;\nprint n;\n\
+             var s = \"a\";\ns += \"b\";\nprint s;\n\
+             var l = [1];\nl[0] += 1;\nprint l;\nprint n += 1;",
+        );
+        let invalid = lox.run("parse", "1 += 2;");
+        let plain = Lox::new().run("tokenize", "n += 1;");
+
+        let printed = stdout_lines(&outcome);
+        assert_eq!(printed, vec!["6", "ab", "[2]", "7"]);
+        assert_eq!(invalid.diagnostics.len(), 1);
+        assert_eq!(
+            invalid.diagnostics[0].code,
+            Some(Code::InvalidAssignmentTarget)
+        );
+        assert_eq!(
+            plain.output.lines()[1..3],
+            [
+                (Stream::Stdout, "PLUS + null".to_string()),
+                (Stream::Stdout, "EQUAL = null".to_string())
+            ]
+        );
+    }
+
+    #[test]
+    fn test_compound_assignment_errors_point_at_the_operator() {
+        let mut lox = Lox::new();
+        lox.extensions = true;
+
+        let outcome = lox.run("run", "var s = \"a\";\ns  -= 1;");
+
+        assert_eq!(
+            outcome.diagnostics[0].to_json(None).to_string(),
+            "{\"code\":\"E0308\",\"message\":\"Invalid operands for binary operator.\",\
+             \"file\":null,\"line\":2,\"column\":4,\"span\":{\"start\":16,\"end\":18},\
+             \"severity\":\"error\"}"
+        );
+    }
+
+    #[test]
+    fn test_propagate_nil_only_covers_arithmetic() {
+        let mut lox = Lox::new();
+        lox.error_policy = ErrorPolicy::PropagateNil;
+
+        let outcome = lox.run(
+            "evaluate",
+            "print nil + 1;\nprint -nil;\nprint 2 * (nil - 1);\nprint !nil;\nprint nil < 1;",
+        );
+        let strict = Lox::new().run("evaluate", "print nil + 1;");
+
+        let printed = stdout_lines(&outcome);
+        assert_eq!(printed, vec!["nil", "nil", "nil", "true"]);
+        assert_eq!(
+            outcome.diagnostics[0].to_string(),
+            "Invalid operands for binary operator.\n[line 5]"
+        );
+        assert_eq!(strict.exit_code(), 70);
+    }
+
+    #[test]
+    fn test_verbose_errors_describe_the_operator_and_operands() {
+        let mut lox = Lox::new();
+        lox.extensions = true;
+        lox.verbose_errors = true;
+        let error = |source| lox.run("run", source).diagnostics[0].message.clone();
+
+        assert_eq!(
+            error("print \"a\" + 1;"),
+            "Operands must be two numbers or two strings. \
+             (cannot add string \"a\" and number 1)"
+        );
+        assert_eq!(
+            error("print -\"x\";"),
+            "Operand must be a number. (cannot negate string \"x\")"
+        );
+        assert_eq!(
+            error("print \"a\" < 1;"),
+            "Invalid operands for binary operator. \
+             (cannot compare string \"a\" and number 1)"
+        );
+        assert_eq!(
+            error("print !\"s\";"),
+            "Operand must be a boolean or number. (cannot apply '!' to string \"s\")"
+        );
+        assert_eq!(
+            error("print \"a\" & 1;"),
+            "Invalid operands for binary operator. \
+             (cannot apply '&' to string \"a\" and number 1)"
+        );
+    }
+
+    #[test]
+    fn test_mixed_comparison_warning_names_both_operand_types() {
+        let less = Token::new(TokenType::LESS, b"<", "null".into(), 2);
+        let plus = Token::new(TokenType::PLUS, b"+", "null".into(), 2);
+        let one = Object::Number(1.0);
+        let two = Object::String("2".into());
+
+        assert_eq!(
+            Interpreter::mixed_comparison_warning(&less, &one, &two).as_deref(),
+            Some(
+                "comparing number with string using '<'. Did you forget to \
+                 convert the right operand to a number?"
+            )
+        );
+        assert_eq!(
+            Interpreter::mixed_comparison_warning(&less, &one, &Object::Number(2.0)),
+            None
+        );
+        assert_eq!(
+            Interpreter::mixed_comparison_warning(&plus, &one, &two),
+            None
+        );
+        assert!(
+            !Interpreter::new(&Lox::new(), ErrorPolicy::default()).comparison_warnings
+        );
+    }
+
+    #[test]
+    fn test_import_runs_each_module_once_and_detects_cycles() {
+        let dir = temp_dir("import");
+        fs::create_dir_all(dir.join("lib")).unwrap();
+        fs::write(dir.join("lib/a.lox"), "var shared = 1;\nimport \"b.lox\";").unwrap();
+        fs::write(dir.join("lib/b.lox"), "print \"b\";").unwrap();
+        fs::write(dir.join("lib/cycle.lox"), "import \"cycle.lox\";").unwrap();
+        let mut lox = Lox::new();
+        lox.extensions = true;
+        lox.path = Some(dir.join("main.lox"));
+
+        let outcome = lox.run(
+            "evaluate",
+            "import \"lib/a.lox\";\nimport \"lib/b.lox\";\nprint shared;",
+        );
+        let cycle = lox.run("evaluate", "import \"lib/cycle.lox\";");
+
+        let printed = &stdout_lines(&outcome)[1..];
+        assert_eq!(printed, vec!["b", "1"]);
+        assert_eq!(
+            cycle.diagnostics[0].message,
+            "Circular import of 'cycle.lox'."
+        );
+    }
+
+    #[test]
+    fn test_module_diagnostics_point_into_the_module() {
+        let dir = temp_dir("module");
+        fs::write(dir.join("syntax.lox"), "var a = 1;\nprint (;").unwrap();
+        fs::write(dir.join("runtime.lox"), "\n\nprint -\"a\";").unwrap();
+        let mut lox = Lox::new();
+        lox.extensions = true;
+        lox.path = Some(dir.join("main.lox"));
+
+        let syntax = lox.run("evaluate", "import \"syntax.lox\";");
+        let runtime = lox.run("evaluate", "print 1;\nimport \"runtime.lox\";");
+
+        let in_module = &syntax.diagnostics[0];
+        let file = in_module.file.as_ref().unwrap();
+        assert_eq!(file.path, dir.join("syntax.lox"));
+        assert!(in_module
+            .to_rich(&file.text, None)
+            .contains("2 | print (;\n  |        ^"));
+        let at_import = &syntax.diagnostics[1];
+        assert_eq!(at_import.code, Some(Code::ModuleHasErrors));
+        assert!(at_import.file.is_none());
+        let failed = &runtime.diagnostics[0];
+        assert_eq!(failed.line, Some(3));
+        assert_eq!(failed.file.as_ref().unwrap().path, dir.join("runtime.lox"));
+    }
+}
@@ -0,0 +1,315 @@
+//! `lox lint`: warnings about code that runs but is probably a mistake.
+//!
+//! Variables are tracked by name across the whole file, in source order,
+//! whatever block they are declared in.
+
+use std::collections::HashSet;
+
+use crate::messages::{message, Code};
+use crate::parser::{Arena, Declaration, Expr, ExprId, Object, Statement};
+use crate::symbol::Symbol;
+use crate::token::TokenType;
+
+pub struct Warning {
+    pub line: usize,
+    pub code: Code,
+    pub message: String,
+}
+
+/// Lints a file one top-level declaration at a time, in source order, so
+/// each can be dropped once the parser has handed it over.
+#[derive(Default)]
+pub struct Linter {
+    warnings: Vec<Warning>,
+    /// The line of the last node that had one; literals and most
+    /// statements carry no token of their own.
+    line: usize,
+    /// Each variable with the line of its first declaration.
+    declared: Vec<(Symbol, usize)>,
+    read: HashSet<Symbol>,
+    /// Variables declared without a value and not assigned since.
+    unassigned: HashSet<Symbol>,
+    /// The line of a top-level `throw` that nothing has followed yet.
+    throw_line: Option<usize>,
+}
+
+impl Linter {
+    /// Lints the next top-level declaration, whose expressions are in
+    /// `arena`.
+    pub fn add(&mut self, declaration: &Declaration, arena: &Arena) {
+        if let Some(line) = self.throw_line.take() {
+            self.warn(line, Code::UnreachableAfterThrow, &[]);
+        }
+        self.declaration(arena, declaration);
+        if let Declaration::Statement(Statement::Throw { keyword, .. }) = declaration {
+            self.throw_line = Some(keyword.line);
+        }
+    }
+
+    /// The warnings for the declarations added, ordered by line.
+    pub fn finish(mut self) -> Vec<Warning> {
+        for (name, line) in std::mem::take(&mut self.declared) {
+            // A leading underscore marks a variable as unused on purpose.
+            if !self.read.contains(&name) && !name.text().starts_with('_') {
+                self.warn(line, Code::UnusedVariable, &[&name]);
+            }
+        }
+        self.warnings.sort_by_key(|warning| warning.line);
+        self.warnings
+    }
+
+    fn warn(&mut self, line: usize, code: Code, args: &[&dyn std::fmt::Display]) {
+        self.warnings.push(Warning {
+            line,
+            code,
+            message: message(code, args),
+        });
+    }
+
+    fn declare(&mut self, name: Symbol, assigned: bool) {
+        if !self.declared.iter().any(|&(declared, _)| declared == name) {
+            self.declared.push((name, self.line));
+        }
+        if assigned {
+            self.unassigned.remove(&name);
+        } else {
+            self.unassigned.insert(name);
+        }
+    }
+
+    /// Declarations in order; anything after a `throw` can never run.
+    fn body(&mut self, arena: &Arena, declarations: &[Declaration]) {
+        for (index, declaration) in declarations.iter().enumerate() {
+            self.declaration(arena, declaration);
+            if let Declaration::Statement(Statement::Throw { keyword, .. }) = declaration
+            {
+                if index + 1 < declarations.len() {
+                    self.warn(keyword.line, Code::UnreachableAfterThrow, &[]);
+                }
+            }
+        }
+    }
+
+    fn declaration(&mut self, arena: &Arena, declaration: &Declaration) {
+        let (keyword, target, initializer) = match declaration {
+            Declaration::Var {
+                keyword,
+                target,
+                initializer,
+                ..
+            } => (keyword, target, initializer),
+            Declaration::Statement(stmt) => return self.statement(arena, stmt),
+        };
+        self.line = keyword.line;
+        if let Some(value) = initializer {
+            self.expr(arena, *value);
+            self.line = keyword.line;
+        }
+        for name in pattern_names(arena, *target) {
+            self.declare(name, initializer.is_some());
+        }
+    }
+
+    fn statement(&mut self, arena: &Arena, statement: &Statement) {
+        match statement {
+            Statement::ExprStmt(value)
+            | Statement::PrintStmt(value)
+            | Statement::EPrintStmt(value)
+            | Statement::WhileStmt(value) => self.expr(arena, *value),
+            Statement::IfStmt(if_) => {
+                self.line = if_.keyword.line;
+                self.condition(arena, if_.condition);
+                self.statement(arena, &if_.then_branch);
+                if let Some(else_branch) = &if_.else_branch {
+                    self.statement(arena, else_branch);
+                }
+            }
+            Statement::Block(body) => self.body(arena, body),
+            Statement::Switch(switch) => {
+                self.line = switch.keyword.line;
+                self.expr(arena, switch.value);
+                for (values, body) in &switch.cases {
+                    values.iter().for_each(|&value| self.expr(arena, value));
+                    self.body(arena, body);
+                }
+                if let Some(body) = &switch.default {
+                    self.body(arena, body);
+                }
+            }
+            Statement::ForIn {
+                keyword,
+                variable,
+                iterable,
+                body,
+            } => {
+                self.line = keyword.line;
+                self.expr(arena, *iterable);
+                self.line = keyword.line;
+                self.declare(*variable, true);
+                self.statement(arena, body);
+            }
+            Statement::Import { keyword, .. } => self.line = keyword.line,
+            Statement::Try(try_) => {
+                self.body(arena, &try_.body);
+                if let Some((name, body)) = &try_.catch {
+                    self.declare(*name, true);
+                    self.body(arena, body);
+                }
+                if let Some(body) = &try_.finally {
+                    self.body(arena, body);
+                }
+            }
+            Statement::Throw { keyword, value } => {
+                self.line = keyword.line;
+                self.expr(arena, *value);
+            }
+            Statement::DoWhile { body, condition } => {
+                self.statement(arena, body);
+                self.condition(arena, *condition);
+            }
+        }
+    }
+
+    /// An `if`, `do`-`while` or `?:` condition, which should depend on
+    /// something that can change.
+    fn condition(&mut self, arena: &Arena, condition: ExprId) {
+        self.expr(arena, condition);
+        if let Some(value) = constant_condition(arena, condition) {
+            self.warn(self.line, Code::ConstantCondition, &[&value]);
+        }
+    }
+
+    fn expr(&mut self, arena: &Arena, expr: ExprId) {
+        match &arena[expr] {
+            Expr::Variable {
+                identifier, line, ..
+            } => {
+                self.line = *line;
+                self.read.insert(*identifier);
+                if self.unassigned.remove(identifier) {
+                    self.warn(*line, Code::ReadBeforeAssignment, &[identifier]);
+                }
+            }
+            Expr::Assign {
+                identifier, value, ..
+            } => {
+                if let Expr::Variable {
+                    identifier: name,
+                    line,
+                    ..
+                } = &arena[*value]
+                {
+                    if name == identifier {
+                        self.warn(*line, Code::SelfAssignment, &[identifier]);
+                    }
+                }
+                self.expr(arena, *value);
+                self.unassigned.remove(identifier);
+            }
+            Expr::Binary {
+                left,
+                operator,
+                right,
+            } => {
+                self.line = operator.line;
+                self.expr(arena, *left);
+                self.expr(arena, *right);
+            }
+            Expr::Unary { operator, right } => {
+                self.line = operator.line;
+                self.expr(arena, *right);
+            }
+            Expr::Grouping { expression } => self.expr(arena, *expression),
+            Expr::Literal { .. } => {}
+            Expr::Conditional {
+                condition,
+                then_branch,
+                else_branch,
+            } => {
+                self.condition(arena, *condition);
+                self.expr(arena, *then_branch);
+                self.expr(arena, *else_branch);
+            }
+            Expr::Call {
+                callee,
+                paren,
+                arguments,
+            } => {
+                self.line = paren.line;
+                self.expr(arena, *callee);
+                arguments
+                    .iter()
+                    .for_each(|&argument| self.expr(arena, argument));
+            }
+            Expr::List { elements } => elements
+                .iter()
+                .for_each(|&element| self.expr(arena, element)),
+            Expr::Map { brace, entries } => {
+                self.line = brace.line;
+                for (key, value) in entries {
+                    self.expr(arena, *key);
+                    self.expr(arena, *value);
+                }
+            }
+            Expr::Index {
+                object,
+                bracket,
+                index,
+            } => {
+                self.line = bracket.line;
+                self.expr(arena, *object);
+                self.expr(arena, *index);
+            }
+            Expr::IndexSet {
+                object,
+                bracket,
+                index,
+                value,
+                ..
+            } => {
+                self.line = bracket.line;
+                self.expr(arena, *object);
+                self.expr(arena, *index);
+                self.expr(arena, *value);
+            }
+            Expr::Spread { ellipsis, value } => {
+                self.line = ellipsis.line;
+                self.expr(arena, *value);
+            }
+        }
+    }
+}
+
+/// The names a `var` target binds: a name, or the names in a pattern.
+fn pattern_names(arena: &Arena, target: ExprId) -> Vec<Symbol> {
+    match &arena[target] {
+        Expr::Variable { identifier, .. } => vec![*identifier],
+        Expr::List { elements } => elements
+            .iter()
+            .flat_map(|&name| pattern_names(arena, name))
+            .collect(),
+        Expr::Map { entries, .. } => entries
+            .iter()
+            .flat_map(|&(_, name)| pattern_names(arena, name))
+            .collect(),
+        _ => vec![],
+    }
+}
+
+/// The value a condition always has, when it is built from literals only.
+fn constant_condition(arena: &Arena, condition: ExprId) -> Option<bool> {
+    match &arena[condition] {
+        Expr::Literal {
+            value: Object::Boolean(value),
+            ..
+        } => Some(*value),
+        Expr::Literal {
+            value: Object::Nil, ..
+        } => Some(false),
+        Expr::Grouping { expression } => constant_condition(arena, *expression),
+        Expr::Unary { operator, right } if operator.token_type == TokenType::BANG => {
+            constant_condition(arena, *right).map(|value| !value)
+        }
+        _ => None,
+    }
+}
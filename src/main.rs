@@ -1,119 +1,2133 @@
-use std::cell::RefCell;
+use std::cell::{Cell, RefCell};
 use std::env;
 use std::fs;
+use std::io;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::{Duration, Instant};
 
+use crate::interpreter::{ErrorPolicy, Limits};
+use crate::messages::{message, Code};
+use crate::outcome::{
+    Diagnostic, DiagnosticsSink, FileReport, Output, RunOutcome, Severity, Sink, Stream,
+};
 use crate::token::{Token, TokenType};
 
+mod ast;
+mod cli;
+mod coverage;
 mod environment;
+mod formatter;
+mod grammar;
+mod highlight;
 mod interpreter;
+mod json;
+mod lint;
+mod messages;
+mod minify;
+mod natives;
+mod outcome;
 mod parser;
+mod preprocessor;
+mod profile;
+mod resolver;
 mod scanner;
+mod symbol;
+#[cfg(test)]
+mod testing;
 mod token;
+mod walk;
 
+/// Output formats chosen with `--format`.
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum Format {
+    Json,
+    /// A Graphviz graph, for `dot -Tpng`.
+    Dot,
+    Csv,
+    /// Colored with ANSI escape sequences, for a terminal.
+    Ansi,
+    Html,
+}
+
+/// Diagnostic formats chosen with `--error-format`.
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum ErrorFormat {
+    /// One jlox-style line per diagnostic.
+    Text,
+    /// One JSON object per line, for tools.
+    Json,
+    /// The source line under each diagnostic, with the error underlined.
+    Rich,
+}
+
+impl Format {
+    fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "json" => Some(Format::Json),
+            "dot" => Some(Format::Dot),
+            "csv" => Some(Format::Csv),
+            "ansi" => Some(Format::Ansi),
+            "html" => Some(Format::Html),
+            _ => None,
+        }
+    }
+
+    /// Whether `command` can print in this format.
+    fn applies_to(self, command: &str) -> bool {
+        matches!(
+            (self, command),
+            (Format::Json, "ast" | "tokenize")
+                | (Format::Dot, "ast")
+                | (Format::Csv, "tokenize")
+                | (Format::Ansi | Format::Html, "highlight")
+        )
+    }
+}
+
+#[derive(Clone)]
 struct Lox {
-    has_error: RefCell<bool>,
+    diagnostics: RefCell<Vec<Diagnostic>>,
+    /// How many of `diagnostics` have been passed on to the output.
+    emitted: Cell<usize>,
+    error_policy: ErrorPolicy,
+    comparison_warnings: bool,
+    verbose_errors: bool,
+    extensions: bool,
+    /// Names passed as `-DNAME`, visible to the preprocessor's `#ifdef`.
+    defines: Vec<String>,
+    dump_expanded: bool,
+    /// Automatic semicolon insertion: a line break may end a statement.
+    asi: bool,
+    max_output_bytes: Option<usize>,
+    /// `--max-statements`, `--max-depth`, `--max-loop-iterations` and
+    /// `--timeout`.
+    limits: Limits,
+    /// `--format`; `ast` defaults to JSON and `tokenize` to plain text.
+    format: Option<Format>,
+    /// `fmt --check`: report whether the file is formatted, printing nothing.
+    check: bool,
+    /// `--trace`: print each statement and assignment to stderr as it runs.
+    trace: bool,
+    /// `--profile`: print time spent per line and native function.
+    profile: bool,
+    /// `--coverage`: write an lcov report of the lines that ran.
+    coverage: bool,
+    /// `bench --iterations N`: measured runs.
+    iterations: usize,
+    /// `bench --warmup N`: unmeasured runs before the measured ones.
+    warmup: usize,
+    /// `lint --deny-warnings`: lint warnings fail the run (exit code 1).
+    deny_warnings: bool,
+    /// `--error-format`: how diagnostics are printed.
+    error_format: ErrorFormat,
+    /// The file being run; `import` paths resolve against its directory.
+    path: Option<PathBuf>,
+    /// Arguments after `--`, which the program reads with `args()`.
+    script_args: Vec<String>,
 }
 
 impl Lox {
     fn new() -> Self {
         Lox {
-            has_error: RefCell::new(false),
+            diagnostics: RefCell::new(vec![]),
+            emitted: Cell::new(0),
+            error_policy: ErrorPolicy::default(),
+            comparison_warnings: false,
+            verbose_errors: false,
+            extensions: false,
+            defines: vec![],
+            dump_expanded: false,
+            asi: false,
+            max_output_bytes: None,
+            limits: Limits::default(),
+            format: None,
+            check: false,
+            deny_warnings: false,
+            error_format: ErrorFormat::Text,
+            trace: false,
+            profile: false,
+            coverage: false,
+            iterations: 10,
+            warmup: 1,
+            path: None,
+            script_args: vec![],
         }
     }
+
+    /// Turns on an option by the name shared by its `--name` flag and
+    /// `// lox: name` directive comments. Returns false for unknown names.
+    fn set_option(&mut self, name: &str) -> bool {
+        match name {
+            "propagate-nil" => self.error_policy = ErrorPolicy::PropagateNil,
+            "comparison-warnings" => self.comparison_warnings = true,
+            "verbose-errors" => self.verbose_errors = true,
+            "extensions" => self.extensions = true,
+            "asi" => self.asi = true,
+            _ => return false,
+        }
+        true
+    }
 }
 
 impl Lox {
-    fn report(&self, line: usize, _where: &str, message: String) {
-        *self.has_error.borrow_mut() = true;
-        eprintln!("[line {}] Error: {}{}", line, _where, message);
+    /// Records a syntax error; `column` is 0 when it is not known.
+    fn report(
+        &self,
+        line: usize,
+        column: usize,
+        span: Option<Range<usize>>,
+        _where: &str,
+        code: Code,
+        args: &[&dyn std::fmt::Display],
+    ) {
+        self.diagnostics.borrow_mut().push(Diagnostic {
+            severity: Severity::SyntaxError,
+            code: Some(code),
+            line: Some(line),
+            column: (column > 0).then_some(column),
+            span,
+            message: format!("{}{}", _where, message(code, args)),
+            trace: vec![],
+            file: None,
+        });
+    }
+
+    /// `message` is passed whole, as warnings may add a hint to the
+    /// catalog text for `code`.
+    fn warn(&self, line: usize, code: Code, message: String) {
+        self.diagnostics.borrow_mut().push(Diagnostic {
+            severity: Severity::Warning,
+            code: Some(code),
+            line: Some(line),
+            column: None,
+            span: None,
+            message,
+            trace: vec![],
+            file: None,
+        });
+    }
+
+    /// Passes the diagnostics reported since the last call on to `output`.
+    /// With `sort` they go in source order: scanning, parsing and resolving
+    /// each report in their own pass. Diagnostics without a line come last,
+    /// followed by those in imported modules.
+    fn flush_diagnostics(&self, output: &mut Output, sort: bool) {
+        let mut diagnostics = self.diagnostics.borrow_mut();
+        let pending = &mut diagnostics[self.emitted.get()..];
+        if sort {
+            pending.sort_by_key(|diagnostic| {
+                (
+                    diagnostic.file.is_some(),
+                    diagnostic.line.unwrap_or(usize::MAX),
+                )
+            });
+        }
+        for diagnostic in pending.iter() {
+            output.report(diagnostic);
+        }
+        self.emitted.set(diagnostics.len());
+    }
+
+    fn has_error(&self) -> bool {
+        self.error_count() > 0
+    }
+
+    fn error_count(&self) -> usize {
+        self.diagnostics
+            .borrow()
+            .iter()
+            .filter(|diagnostic| diagnostic.severity == Severity::SyntaxError)
+            .count()
     }
 
-    fn error(&self, token: &Token, message: String) {
+    fn error(&self, token: &Token, code: Code, args: &[&dyn std::fmt::Display]) {
         if token.token_type == TokenType::EOF {
-            self.report(token.line, " at end ", message);
+            self.report(
+                token.line,
+                token.column,
+                Some(token.span.clone()),
+                " at end ",
+                code,
+                args,
+            );
         } else {
-            let lexeme_str = String::from_utf8_lossy(token.lexeme);
+            let lexeme_str = String::from_utf8_lossy(&token.lexeme);
             self.report(
                 token.line,
+                token.column,
+                Some(token.span.clone()),
                 format!(" at '{}' ", lexeme_str).as_str(),
-                message,
+                code,
+                args,
             );
         }
     }
 
-    fn run(&self, command: &str, file_contents: String) {
-        if file_contents.is_empty() {
-            println!("EOF  null");
-            return;
+    /// Runs `command` over the source, first applying any `// lox:`
+    /// directive comments at the top of the file on top of these options.
+    fn run(&self, command: &str, file_contents: &str) -> RunOutcome {
+        self.run_into(command, file_contents, Output::default())
+    }
+
+    /// Like [`Lox::run`], writing to `output` as the run goes.
+    fn run_into(&self, command: &str, file_contents: &str, output: Output) -> RunOutcome {
+        let directives = scanner::directive_comments(file_contents);
+        if directives.is_empty() {
+            return self.run_with_options(command, file_contents, output);
         }
-        match command {
-            "tokenize" => {
-                let mut scanner = scanner::Scanner::new(file_contents.as_bytes(), self);
-                let tokens = scanner.scan_tokens();
+        let mut lox = self.clone();
+        for (line, option) in directives {
+            if !lox.set_option(option) {
+                let code = Code::UnknownDirectiveOption;
+                lox.warn(line, code, message(code, &[&option]));
+            }
+        }
+        lox.run_with_options(command, file_contents, output)
+    }
 
-                for token in tokens {
-                    println!("{}", token);
+    fn run_with_options(
+        &self,
+        command: &str,
+        file_contents: &str,
+        output: Output,
+    ) -> RunOutcome {
+        let started = Instant::now();
+        let mut outcome = RunOutcome {
+            output,
+            ..RunOutcome::default()
+        };
+        let mut scanner = scanner::Scanner::new(file_contents.as_bytes(), self);
+        scanner.scan_tokens();
+        let tokens = scanner.tokens();
+        outcome.metrics.tokens = tokens.len();
+        let expanded;
+        // `fmt` and `highlight` print the source as written, directives
+        // included.
+        let tokens =
+            if self.extensions && !matches!(command, "tokenize" | "fmt" | "highlight") {
+                expanded = preprocessor::Preprocessor::new(tokens, self).expand();
+                if self.dump_expanded {
+                    for token in &expanded {
+                        outcome.output.write(Stream::Stdout, token.to_string());
+                    }
                 }
-                if *self.has_error.borrow() {
-                    std::process::exit(65);
+                &expanded
+            } else {
+                tokens
+            };
+        match command {
+            "tokenize" => match self.format {
+                Some(Format::Json) => {
+                    let tokens = tokens.iter().map(Token::to_json).collect();
+                    outcome
+                        .output
+                        .write(Stream::Stdout, json::Json::Array(tokens).to_string());
+                }
+                Some(Format::Csv) => {
+                    outcome
+                        .output
+                        .write(Stream::Stdout, token::CSV_HEADER.into());
+                    for token in tokens {
+                        outcome.output.write(Stream::Stdout, token.to_csv_row());
+                    }
+                }
+                _ => {
+                    for token in tokens {
+                        outcome.output.write(Stream::Stdout, token.to_string());
+                    }
+                }
+            },
+            "highlight" => {
+                let format = self.format.unwrap_or(Format::Ansi);
+                let highlighted = highlight::highlight(file_contents, tokens, format);
+                let highlighted = highlighted.strip_suffix('\n').unwrap_or(&highlighted);
+                // An empty program highlights to nothing, not a blank line.
+                if !highlighted.is_empty() {
+                    outcome.output.write(Stream::Stdout, highlighted.into());
                 }
             }
             "parse" => {
-                let mut scanner = scanner::Scanner::new(file_contents.as_bytes(), self);
-                let tokens = scanner.scan_tokens();
-
                 let parser = parser::Parser::new(tokens, self);
-                let parsed_stmts = parser.parse();
-                if *self.has_error.borrow() {
-                    std::process::exit(65);
+                let mut printed = vec![];
+                parser.parse_streaming(|stmt, arena| {
+                    printed.push(arena.show(&stmt).to_string())
+                });
+                outcome.metrics.declarations = printed.len();
+                if !self.has_error() {
+                    for stmt in printed {
+                        outcome.output.write(Stream::Stdout, stmt);
+                    }
                 }
-                for stmt in parsed_stmts {
-                    println!("{}", stmt);
+            }
+            "ast" => {
+                let parser = parser::Parser::new(tokens, self);
+                let res = parser.parse();
+                outcome.metrics.declarations = res.declarations.len();
+                if !self.has_error() {
+                    outcome.output.write(
+                        Stream::Stdout,
+                        ast::dump(&res, self.format.unwrap_or(Format::Json)),
+                    );
                 }
             }
-            "evaluate" => {
-                let mut scanner = scanner::Scanner::new(file_contents.as_bytes(), self);
-                let tokens = scanner.scan_tokens();
-
+            "fmt" => {
                 let parser = parser::Parser::new(tokens, self);
                 let res = parser.parse();
-                let interpreter = interpreter::Interpreter::new();
-                match interpreter.interpret(res) {
-                    Ok(exprs) => {
-                        exprs.iter().for_each(|expr| println!("{}", expr));
-                    }
-                    Err(err) => {
-                        println!("{}", err);
-                        std::process::exit(70);
+                outcome.metrics.declarations = res.declarations.len();
+                if !self.has_error() {
+                    self.format(&res, file_contents, scanner.comments(), &mut outcome);
+                }
+            }
+            "minify" => {
+                // Only a program that parses is minified, so a mistake in it
+                // is reported against the source as written.
+                let parser = parser::Parser::new(tokens, self);
+                outcome.metrics.declarations = parser.parse().declarations.len();
+                let minified = minify::minify(tokens);
+                if !self.has_error() && !minified.is_empty() {
+                    outcome.output.write(Stream::Stdout, minified);
+                }
+            }
+            "lint" => {
+                let parser = parser::Parser::new(tokens, self);
+                let mut linter = lint::Linter::default();
+                parser.parse_streaming(|declaration, arena| {
+                    outcome.metrics.declarations += 1;
+                    linter.add(&declaration, arena);
+                });
+                if !self.has_error() {
+                    let severity = if self.deny_warnings {
+                        Severity::ToolError
+                    } else {
+                        Severity::Warning
+                    };
+                    let mut diagnostics = self.diagnostics.borrow_mut();
+                    for warning in linter.finish() {
+                        diagnostics.push(Diagnostic {
+                            severity,
+                            code: Some(warning.code),
+                            line: Some(warning.line),
+                            column: None,
+                            span: None,
+                            message: format!(
+                                "{} [{}]",
+                                warning.message,
+                                warning.code.as_str()
+                            ),
+                            trace: vec![],
+                            file: None,
+                        });
                     }
-                };
-                if *self.has_error.borrow() {
-                    std::process::exit(65);
+                }
+            }
+            "check" => {
+                // Top-level declarations are resolved one at a time: globals
+                // have no scope to carry from one to the next.
+                let parser = parser::Parser::new(tokens, self);
+                parser.parse_streaming(|declaration, arena| {
+                    outcome.metrics.declarations += 1;
+                    resolver::resolve(std::slice::from_ref(&declaration), arena, self);
+                });
+            }
+            "evaluate" | "run" => {
+                let parser = parser::Parser::new(tokens, self);
+                let mut res = parser.parse();
+                outcome.metrics.declarations = res.declarations.len();
+                resolver::resolve(&res.declarations, &mut res.arena, self);
+                self.flush_diagnostics(&mut outcome.output, true);
+                // Unlike `evaluate`, `run` never executes a program that
+                // failed to parse.
+                if command == "evaluate" || !self.has_error() {
+                    self.interpret(&res, command == "evaluate", &mut outcome);
                 }
             }
             _ => eprintln!("Unknown command: {}", command),
         }
+        // A running program has reported its diagnostics as it went.
+        let sort = !matches!(command, "evaluate" | "run");
+        self.flush_diagnostics(&mut outcome.output, sort);
+        outcome.diagnostics = self.diagnostics.take();
+        self.emitted.set(0);
+        outcome.metrics.elapsed = started.elapsed();
+        outcome
+    }
+
+    /// Runs the program `warmup` times and then `iterations` times more,
+    /// discarding its output, and reports the timings of the measured
+    /// runs. The first run that fails is reported instead.
+    fn bench(&self, file_contents: &str, mut output: Output) -> RunOutcome {
+        let mut times = vec![];
+        let mut executed = 0;
+        for iteration in 0..self.warmup + self.iterations.max(1) {
+            let run = self.run("run", file_contents);
+            if run.exit_code() != 0 {
+                for diagnostic in &run.diagnostics {
+                    output.report(diagnostic);
+                }
+                return RunOutcome {
+                    output,
+                    diagnostics: run.diagnostics,
+                    ..RunOutcome::default()
+                };
+            }
+            if iteration >= self.warmup {
+                times.push(run.metrics.elapsed);
+                executed = run.metrics.executed;
+            }
+        }
+        times.sort();
+        let middle = times.len() / 2;
+        let median = if times.len() % 2 == 0 {
+            (times[middle - 1] + times[middle]) / 2
+        } else {
+            times[middle]
+        };
+        let mean = times.iter().sum::<Duration>() / times.len() as u32;
+        let mut outcome = RunOutcome {
+            output,
+            ..RunOutcome::default()
+        };
+        for line in [
+            format!("runs:       {} (after {} warmup)", times.len(), self.warmup),
+            format!("min:        {:?}", times[0]),
+            format!("median:     {:?}", median),
+            format!("mean:       {:?}", mean),
+            format!("statements: {} per run", executed),
+        ] {
+            outcome.output.write(Stream::Stdout, line);
+        }
+        outcome
+    }
+
+    /// Prints `program` as canonical source, or with `--check` reports
+    /// where `source` first differs from it.
+    fn format(
+        &self,
+        program: &parser::Program,
+        source: &str,
+        comments: &[scanner::Comment],
+        outcome: &mut RunOutcome,
+    ) {
+        let formatted = formatter::format(program, comments);
+        if !self.check {
+            let formatted = formatted.trim_end_matches('\n');
+            if !formatted.is_empty() {
+                outcome.output.write(Stream::Stdout, formatted.into());
+            }
+        } else if formatted != source {
+            let line = formatted
+                .lines()
+                .zip(source.lines())
+                .take_while(|(formatted, source)| formatted == source)
+                .count()
+                + 1;
+            let code = Code::NotFormatted;
+            self.diagnostics.borrow_mut().push(Diagnostic {
+                severity: Severity::ToolError,
+                code: Some(code),
+                line: Some(line),
+                column: None,
+                span: None,
+                message: message(code, &[]),
+                trace: vec![],
+                file: None,
+            });
+        }
+    }
+
+    /// Executes a parsed program into `outcome`. With `echo`, statements
+    /// that do not print still show their value, as `evaluate` does.
+    fn interpret(&self, program: &parser::Program, echo: bool, outcome: &mut RunOutcome) {
+        let mut interpreter = interpreter::Interpreter::new(self, self.error_policy);
+        interpreter.set_comparison_warnings(self.comparison_warnings);
+        interpreter.set_verbose_errors(self.verbose_errors);
+        interpreter.set_echo(echo);
+        interpreter.set_tracing(self.trace);
+        interpreter.set_profiling(self.profile);
+        interpreter.set_coverage(self.coverage);
+        interpreter.set_limits(self.limits);
+        let executable = self
+            .coverage
+            .then(|| coverage::executable_lines(&program.arena, &program.declarations));
+        if let Some(limit) = self.max_output_bytes {
+            outcome.output.set_limit(limit);
+        }
+        interpreter.set_output(std::mem::take(&mut outcome.output));
+        let result = interpreter.interpret(program);
+        // Whatever was written before a runtime error is still shown.
+        outcome.output = interpreter.take_output();
+        outcome.last_value = interpreter.last_value();
+        if let Err(err) = result {
+            self.diagnostics.borrow_mut().push(Diagnostic {
+                severity: if err.is_panic {
+                    Severity::Panic
+                } else {
+                    Severity::RuntimeError
+                },
+                code: err.code,
+                line: err.line,
+                column: err.column,
+                message: err.to_string(),
+                span: err.span,
+                trace: err.trace,
+                file: err.file,
+            });
+        }
+        self.flush_diagnostics(&mut outcome.output, false);
+        outcome.metrics.executed = interpreter.executed();
+        for line in interpreter.profile_report().unwrap_or_default() {
+            outcome.output.write(Stream::Stderr, line);
+        }
+        if let (Some(executable), Some(hits)) = (executable, interpreter.coverage_hits())
+        {
+            let file = self.path.as_ref().map(|path| path.display().to_string());
+            outcome.coverage = Some(coverage::lcov(
+                file.as_deref().unwrap_or("-"),
+                executable,
+                &hits,
+            ));
+        }
     }
 }
 
-fn main() {
-    let args: Vec<String> = env::args().collect();
-    if args.len() < 3 {
-        eprintln!("Usage: {} tokenize <filename>", args[0]);
-        return;
+/// The CLI's sink: program output on stdout and stderr, and diagnostics on
+/// stderr in the `--error-format`, each printed as soon as it is produced.
+struct Terminal {
+    error_format: ErrorFormat,
+    /// The program's file, as diagnostics name it.
+    path: Option<String>,
+    source: String,
+}
+
+impl Sink for Terminal {
+    fn write(&mut self, stream: Stream, line: &str) {
+        match stream {
+            Stream::Stdout => println!("{}", line),
+            Stream::Stderr => eprintln!("{}", line),
+        }
+    }
+
+    fn report(&mut self, diagnostic: &Diagnostic) {
+        eprintln!("{}", self.render(diagnostic));
+    }
+}
+
+impl Terminal {
+    fn render(&self, diagnostic: &Diagnostic) -> String {
+        // A diagnostic in an imported module is shown against that module.
+        let (source, file) = match &diagnostic.file {
+            Some(file) => (file.text.as_str(), Some(file.path.display().to_string())),
+            None => (self.source.as_str(), self.path.clone()),
+        };
+        match self.error_format {
+            ErrorFormat::Text => diagnostic.to_string(),
+            ErrorFormat::Json => diagnostic.to_json(file.as_deref()).to_string(),
+            ErrorFormat::Rich => diagnostic.to_rich(source, file.as_deref()),
+        }
+    }
+}
+
+impl Lox {
+    /// Runs `fmt` or `check` on each `.lox` file under `root`, each file
+    /// with a `Lox` of its own on one of several threads. Each file's
+    /// diagnostics are shown together, in path order, as soon as the files
+    /// before it have been. `fmt` rewrites the files it would change,
+    /// unless `--check`. Returns the exit code of the first file that
+    /// failed.
+    fn run_directory(&self, command: &str, root: &Path) -> i32 {
+        let files = match walk::lox_files(root) {
+            Ok(files) => files,
+            Err(error) => {
+                eprintln!("Failed to read directory {}: {}", root.display(), error);
+                return cli::IO_ERROR_EXIT_CODE;
+            }
+        };
+        let workers = thread::available_parallelism()
+            .map_or(1, |workers| workers.get())
+            .clamp(1, files.len().max(1));
+        let sink = DiagnosticsSink::new(io::stderr());
+        thread::scope(|scope| {
+            for worker in 0..workers {
+                let lox = self.clone();
+                let (files, sink) = (&files, &sink);
+                thread::Builder::new()
+                    .stack_size(interpreter::STACK_SIZE)
+                    .spawn_scoped(scope, move || {
+                        for (index, file) in
+                            files.iter().enumerate().skip(worker).step_by(workers)
+                        {
+                            sink.submit(index, lox.run_file(command, file));
+                        }
+                    })
+                    .expect("failed to spawn a worker thread");
+            }
+        });
+        let (failed, exit_code) = sink.finish();
+        if self.error_format != ErrorFormat::Json {
+            eprintln!("{}", message(Code::FilesSummary, &[&files.len(), &failed]));
+        }
+        exit_code
+    }
+
+    /// `fmt` or `check` on one file of a directory.
+    fn run_file(&self, command: &str, path: &Path) -> FileReport {
+        let name = path.display().to_string();
+        let source = match fs::read_to_string(path) {
+            Ok(source) => source,
+            Err(error) => {
+                return FileReport {
+                    lines: vec![format!("Failed to read file {}: {}", name, error)],
+                    exit_code: cli::IO_ERROR_EXIT_CODE,
+                }
+            }
+        };
+        let mut lox = self.clone();
+        lox.path = Some(path.to_path_buf());
+        let outcome = lox.run(command, &source);
+        let mut exit_code = outcome.exit_code();
+        let terminal = Terminal {
+            error_format: self.error_format,
+            path: Some(name.clone()),
+            source,
+        };
+        let mut lines: Vec<String> = outcome
+            .diagnostics
+            .iter()
+            .map(|diagnostic| terminal.render(diagnostic))
+            .collect();
+        let summaries = self.error_format != ErrorFormat::Json;
+        if command == "fmt" && !self.check && exit_code == 0 {
+            // What `fmt` prints for a single file: the whole program, less
+            // its final line break.
+            let mut formatted = outcome
+                .output
+                .lines()
+                .iter()
+                .map(|(_, line)| line.as_str())
+                .collect::<Vec<_>>()
+                .join("\n");
+            if !formatted.is_empty() {
+                formatted.push('\n');
+            }
+            if formatted != terminal.source {
+                match fs::write(path, formatted) {
+                    Ok(()) if summaries => {
+                        lines.push(message(Code::FileFormatted, &[&name]))
+                    }
+                    Ok(()) => {}
+                    Err(error) => {
+                        lines.push(format!("Failed to write file {}: {}", name, error));
+                        exit_code = cli::IO_ERROR_EXIT_CODE;
+                    }
+                }
+            }
+        }
+        if summaries && !outcome.diagnostics.is_empty() {
+            let warnings = outcome
+                .diagnostics
+                .iter()
+                .filter(|diagnostic| diagnostic.severity == Severity::Warning)
+                .count();
+            let errors = outcome.diagnostics.len() - warnings;
+            lines.push(message(Code::FileSummary, &[&name, &errors, &warnings]));
+        }
+        FileReport { lines, exit_code }
+    }
+}
+
+/// Ends the CLI's rendering of a run with the syntax error count.
+fn summarize(lox: &Lox, outcome: &RunOutcome) {
+    let errors = outcome
+        .diagnostics
+        .iter()
+        .filter(|diagnostic| diagnostic.severity == Severity::SyntaxError)
+        .count();
+    if errors > 0 && lox.error_format != ErrorFormat::Json {
+        eprintln!("{}", message(Code::SyntaxErrorCount, &[&errors]));
+    }
+}
+
+impl Lox {
+    /// Applies one flag from the command line; `Err` is a usage error.
+    fn apply_flag(&mut self, flag: &str, value: Option<String>) -> Result<(), String> {
+        let value = value.unwrap_or_default();
+        let count = || {
+            value
+                .parse()
+                .map_err(|_| format!("{} expects a count, not '{}'", flag, value))
+        };
+        match flag {
+            "--dump-expanded" => self.dump_expanded = true,
+            "--check" => self.check = true,
+            "--trace" => self.trace = true,
+            "--profile" => self.profile = true,
+            "--coverage" => self.coverage = true,
+            "--deny-warnings" => self.deny_warnings = true,
+            "--iterations" => self.iterations = count()?,
+            "--warmup" => self.warmup = count()?,
+            "--max-output-bytes" => self.max_output_bytes = Some(count()?),
+            "--max-statements" => self.limits.statements = Some(count()?),
+            "--max-depth" => self.limits.depth = count()?,
+            "--max-loop-iterations" => self.limits.loop_iterations = Some(count()?),
+            "--timeout" => {
+                self.limits.timeout = Some(Duration::from_millis(count()? as u64))
+            }
+            "--format" => {
+                self.format = Some(Format::from_name(&value).ok_or_else(|| {
+                    format!(
+                        "--format expects json, dot, csv, ansi or html, not '{}'",
+                        value
+                    )
+                })?)
+            }
+            "--error-format" => match value.as_str() {
+                "text" => self.error_format = ErrorFormat::Text,
+                "json" => self.error_format = ErrorFormat::Json,
+                "rich" => self.error_format = ErrorFormat::Rich,
+                _ => {
+                    return Err(format!(
+                        "--error-format expects text, json or rich, not '{}'",
+                        value
+                    ))
+                }
+            },
+            "-D" => self.defines.push(value),
+            "--stdin-filename" => self.path = Some(PathBuf::from(value)),
+            option => {
+                let known = option
+                    .strip_prefix("--")
+                    .is_some_and(|option| self.set_option(option));
+                if !known {
+                    return Err(format!("unknown flag '{}'", flag));
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Prints a usage error and exits with [`cli::USAGE_EXIT_CODE`].
+fn usage_error(program: &str, error: &str) -> ! {
+    eprintln!("error: {}", error);
+    eprintln!("{}", cli::usage(program));
+    eprintln!("Run '{} --help' for the commands and options.", program);
+    std::process::exit(cli::USAGE_EXIT_CODE);
+}
+
+/// Reads the program named on the command line, `-` meaning `stdin`. `Err`
+/// holds the exit code for the failure and the message to print.
+fn read_program(filename: &str, stdin: impl io::Read) -> Result<String, (i32, String)> {
+    if filename == "-" {
+        return io::read_to_string(stdin).map_err(|error| {
+            (
+                cli::IO_ERROR_EXIT_CODE,
+                format!("Failed to read standard input: {}", error),
+            )
+        });
     }
+    let file = fs::File::open(filename).map_err(|error| {
+        (
+            cli::NO_INPUT_EXIT_CODE,
+            format!("Failed to open file {}: {}", filename, error),
+        )
+    })?;
+    io::read_to_string(file).map_err(|error| {
+        (
+            cli::IO_ERROR_EXIT_CODE,
+            format!("Failed to read file {}: {}", filename, error),
+        )
+    })
+}
 
-    let command = &args[1];
-    let filename = &args[2];
+fn main() {
+    // The interpreter recurses on the Rust stack; its depth limits are
+    // worked out for this thread's.
+    interpreter::with_stack(run_cli);
+}
 
-    let get_file_contents = |filename: &String| {
-        fs::read_to_string(filename).unwrap_or_else(|_| {
-            eprintln!("Failed to read file {}", filename);
-            String::new()
-        })
+fn run_cli() {
+    let mut args = env::args();
+    let program = args.next().unwrap_or_else(|| "lox".into());
+    let invocation = match cli::parse(args) {
+        Ok(cli::Cli::Help) => return println!("{}", cli::help(&program)),
+        Ok(cli::Cli::Version) => {
+            return println!("lox {}", env!("CARGO_PKG_VERSION"));
+        }
+        Ok(cli::Cli::Run(invocation)) => invocation,
+        Err(error) => usage_error(&program, &error),
+    };
+
+    let mut lox = Lox::new();
+    lox.script_args = invocation.script_args;
+    for (flag, value) in invocation.flags {
+        if let Err(error) = lox.apply_flag(&flag, value) {
+            usage_error(&program, &error);
+        }
+    }
+    let command = invocation.command.as_str();
+    if let Some(format) = lox.format.filter(|format| !format.applies_to(command)) {
+        let format = format!("{:?}", format).to_lowercase();
+        usage_error(&program, &format!("{} has no {} output", command, format));
+    }
+    let Some(filename) = invocation.filename else {
+        // `grammar` is the only command without a file.
+        return println!("{}", grammar::ebnf(lox.extensions));
+    };
+    // `--stdin-filename` has set the path already.
+    if filename != "-" && lox.path.is_some() {
+        usage_error(
+            &program,
+            "--stdin-filename only applies when reading from -",
+        );
+    }
+    if matches!(command, "fmt" | "check") && Path::new(&filename).is_dir() {
+        std::process::exit(lox.run_directory(command, Path::new(&filename)));
+    }
+
+    let file_contents = match read_program(&filename, io::stdin()) {
+        Ok(contents) => contents,
+        Err((code, error)) => {
+            eprintln!("{}", error);
+            std::process::exit(code);
+        }
+    };
+    if filename != "-" {
+        lox.path = Some(PathBuf::from(filename));
+    }
+    let output = Output::with_sink(Box::new(Terminal {
+        error_format: lox.error_format,
+        path: lox.path.as_ref().map(|path| path.display().to_string()),
+        source: file_contents.clone(),
+    }));
+    let outcome = if command == "bench" {
+        lox.bench(&file_contents, output)
+    } else {
+        lox.run_into(command, &file_contents, output)
     };
+    summarize(&lox, &outcome);
+    if let Some(report) = &outcome.coverage {
+        if fs::write(coverage::REPORT_PATH, report).is_err() {
+            eprintln!("Failed to write {}", coverage::REPORT_PATH);
+        }
+    }
+    std::process::exit(outcome.exit_code());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{stdout_lines, temp_dir};
+
+    #[test]
+    fn test_parsed_program_outlives_its_source() {
+        let lox = Lox::new();
+        let parse = |source: String| {
+            let mut scanner = scanner::Scanner::new(source.as_bytes(), &lox);
+            let tokens = scanner.scan_tokens().clone();
+            parser::Parser::new(&tokens, &lox).parse()
+        };
+        let program = parse("var a = 1;\nprint a + 2;".to_string());
+
+        let mut outcome = RunOutcome::default();
+        lox.interpret(&program, false, &mut outcome);
+        assert_eq!(outcome.output.lines(), [(Stream::Stdout, "3".to_string())]);
+    }
+
+    #[test]
+    fn test_program_is_read_from_stdin_for_dash() {
+        let program = read_program("-", "print 1;".as_bytes());
+        let invalid = read_program("-", &[0xff, 0xfe][..]);
+
+        assert_eq!(program, Ok("print 1;".to_string()));
+        assert_eq!(invalid.unwrap_err().0, cli::IO_ERROR_EXIT_CODE);
+    }
+
+    #[test]
+    fn test_missing_program_file_is_an_error() {
+        let missing = read_program("/nonexistent/program.lox", io::empty());
+
+        let (code, error) = missing.unwrap_err();
+        assert_eq!(code, cli::NO_INPUT_EXIT_CODE);
+        assert!(error.starts_with("Failed to open file /nonexistent/program.lox: "));
+    }
+
+    #[test]
+    fn test_eprint_keeps_stream_order() {
+        let mut lox = Lox::new();
+        lox.extensions = true;
+
+        let outcome = lox.run("evaluate", "print 1; eprint 2; { print 3; eprint 4; }");
+
+        let lines: Vec<(Stream, &str)> = outcome
+            .output
+            .lines()
+            .iter()
+            .map(|(stream, line)| (*stream, line.as_str()))
+            .collect();
+        assert_eq!(
+            lines,
+            vec![
+                (Stream::Stdout, "1"),
+                (Stream::Stderr, "2"),
+                (Stream::Stdout, "3"),
+                (Stream::Stderr, "4"),
+            ]
+        );
+    }
+
+    /// Records what reaches it, in order.
+    struct Recorder(std::rc::Rc<RefCell<Vec<String>>>);
+
+    impl Sink for Recorder {
+        fn write(&mut self, _: Stream, line: &str) {
+            self.0.borrow_mut().push(line.to_string());
+        }
+
+        fn report(&mut self, diagnostic: &Diagnostic) {
+            self.0.borrow_mut().push(diagnostic.to_string());
+        }
+    }
+
+    #[test]
+    fn test_sink_receives_output_and_diagnostics_as_they_happen() {
+        let mut lox = Lox::new();
+        lox.trace = true;
+        let seen = std::rc::Rc::new(RefCell::new(vec![]));
+        let output = Output::with_sink(Box::new(Recorder(seen.clone())));
+
+        let outcome = lox.run_into(
+            "run",
+            "// lox: comparison-warnings\n// lox: bogus\nprint 1;\nprint 1 < \"a\";",
+            output,
+        );
+
+        assert!(outcome.output.lines().is_empty());
+        let diagnostics: Vec<String> = outcome
+            .diagnostics
+            .iter()
+            .map(Diagnostic::to_string)
+            .collect();
+        assert_eq!(diagnostics.len(), 3);
+        assert_eq!(
+            *seen.borrow(),
+            [
+                diagnostics[0].clone(),
+                "[line 3] print 1;".to_string(),
+                "1".to_string(),
+                "[line 4] print 1 < \"a\";".to_string(),
+                diagnostics[1].clone(),
+                diagnostics[2].clone(),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_token_columns_count_characters_from_the_line_start() {
+        let lox = Lox::new();
+        let source = "print \"é\";\n  x = 1; /* a\nb */ y";
+        let mut scanner = scanner::Scanner::new(source.as_bytes(), &lox);
+
+        let columns: Vec<(usize, usize)> = scanner
+            .scan_tokens()
+            .iter()
+            .map(|token| (token.line, token.column))
+            .collect();
+        assert_eq!(
+            columns,
+            [
+                (1, 1),
+                (1, 7),
+                (1, 10),
+                (2, 3),
+                (2, 5),
+                (2, 7),
+                (2, 8),
+                (3, 6),
+                (3, 7)
+            ]
+        );
+    }
+
+    #[test]
+    fn test_token_spans_are_byte_ranges() {
+        let lox = Lox::new();
+        let source = "var s = \"日本\";\ns";
+        let mut scanner = scanner::Scanner::new(source.as_bytes(), &lox);
+
+        let spans: Vec<Range<usize>> = scanner
+            .scan_tokens()
+            .iter()
+            .map(|token| token.span.clone())
+            .collect();
+        assert_eq!(spans, [0..3, 4..5, 6..7, 8..16, 16..17, 18..19, 19..19]);
+        assert_eq!(&source[8..16], "\"日本\"");
+    }
+
+    #[test]
+    fn test_parse_error_is_located_by_byte_span() {
+        let outcome = Lox::new().run("parse", "print \"é\" + ;");
+
+        let diagnostic = &outcome.diagnostics[0];
+        assert_eq!(diagnostic.span, Some(13..14));
+        assert_eq!((diagnostic.line, diagnostic.column), (Some(1), Some(13)));
+    }
+
+    #[test]
+    fn test_block_comments_nest_and_must_be_closed() {
+        let nested = Lox::new().run("tokenize", "print /* a /* b */ c */ 1;");
+        let unterminated = Lox::new().run("tokenize", "print 1;\n/* a /* b */\nc");
+
+        let tokens = stdout_lines(&nested);
+        assert_eq!(
+            tokens,
+            [
+                "PRINT print null",
+                "NUMBER 1 1.0",
+                "SEMICOLON ; null",
+                "EOF  null"
+            ]
+        );
+        assert_eq!(unterminated.diagnostics.len(), 1);
+        assert_eq!(
+            unterminated.diagnostics[0].to_string(),
+            "[line 3] Error: Unterminated block comment."
+        );
+    }
+
+    #[test]
+    fn test_eprint_is_an_identifier_without_extensions() {
+        let outcome = Lox::new().run("tokenize", "eprint");
+
+        assert_eq!(
+            outcome.output.lines()[0],
+            (Stream::Stdout, "IDENTIFIER eprint eprint".to_string())
+        );
+    }
+
+    #[test]
+    fn test_bitwise_and_shift_tokens_need_extensions() {
+        let shifts = Lox::new().run("tokenize", "<<=>>=");
+        let ampersand = Lox::new().run("tokenize", "1 & 2");
+
+        let tokens = stdout_lines(&shifts);
+        assert_eq!(
+            tokens,
+            [
+                "LESS < null",
+                "LESS_EQUAL <= null",
+                "GREATER > null",
+                "GREATER_EQUAL >= null",
+                "EOF  null"
+            ]
+        );
+        assert_eq!(ampersand.diagnostics.len(), 1);
+        assert_eq!(
+            ampersand.diagnostics[0].to_string(),
+            "[line 1] Error: Unexpected character: &"
+        );
+    }
 
-    let lox = Lox::new();
-    let file_contents = get_file_contents(filename);
-    lox.run(command.as_str(), file_contents);
+    #[test]
+    fn test_extension_punctuation_is_unexpected_without_extensions() {
+        let outcome = Lox::new().run("tokenize", "a ? b : c[0]");
+
+        let errors: Vec<String> = outcome
+            .diagnostics
+            .iter()
+            .map(|diagnostic| diagnostic.to_string())
+            .collect();
+        assert_eq!(
+            errors,
+            [
+                "[line 1] Error: Unexpected character: ?",
+                "[line 1] Error: Unexpected character: :",
+                "[line 1] Error: Unexpected character: [",
+                "[line 1] Error: Unexpected character: ]"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_panic_is_reported_as_intentional_abort() {
+        let outcome = Lox::new().run("evaluate", "print 1;\npanic(\"boom\");");
+
+        assert!(outcome.has(Severity::Panic));
+        assert!(!outcome.has(Severity::RuntimeError));
+        assert_eq!(outcome.exit_code(), 70);
+        assert_eq!(outcome.diagnostics[0].trace, vec!["[line 2] in script"]);
+        assert_eq!(
+            outcome.diagnostics[0].to_string(),
+            "panic: boom\n[line 2] in script"
+        );
+    }
+
+    #[test]
+    fn test_native_errors_are_located_at_the_call() {
+        let mut lox = Lox::new();
+        lox.extensions = true;
+        let source = "var a = [1];\na[0] = a;\nprint deepClone(a);";
+        let cloned = lox.run("evaluate", source);
+        let panicked = Lox::new().run("evaluate", "print 1;\n  panic(\"boom\");");
+
+        let diagnostic = &cloned.diagnostics[0];
+        assert_eq!(diagnostic.code, Some(Code::CannotCloneCycle));
+        assert_eq!((diagnostic.line, diagnostic.column), (Some(3), Some(18)));
+        assert_eq!(&source[diagnostic.span.clone().unwrap()], ")");
+        let diagnostic = &panicked.diagnostics[0];
+        assert_eq!((diagnostic.line, diagnostic.column), (Some(2), Some(15)));
+        assert!(diagnostic.span.is_some());
+    }
+
+    #[test]
+    fn test_mixed_plus_reports_canonical_error_with_line() {
+        let outcome = Lox::new().run("evaluate", "print 1;\nprint \"a\" + 1;");
+
+        assert_eq!(outcome.exit_code(), 70);
+        assert_eq!(
+            outcome.diagnostics[0].to_string(),
+            "Operands must be two numbers or two strings.\n[line 2]"
+        );
+    }
+
+    #[test]
+    fn test_runtime_errors_without_an_operator_have_a_line() {
+        let line = |source| Lox::new().run("run", source).diagnostics[0].line;
+
+        assert_eq!(line("print 1;\nprint x;"), Some(2));
+        assert_eq!(line("{\n  x = 1;\n}"), Some(2));
+        assert_eq!(line("var f = 1;\n\nf();"), Some(3));
+        assert_eq!(line("print 1;\n\nif (1) print 2;"), Some(3));
+    }
+
+    #[test]
+    fn test_list_index_read_write_and_bounds() {
+        let mut lox = Lox::new();
+        lox.extensions = true;
+
+        let outcome = lox.run(
+            "evaluate",
+            "var a = [1, [2]];\nvar b = a;\nb[0] += 4;\nprint a;\nprint a[1][0];",
+        );
+        let last = |outcome: &RunOutcome| outcome.output.lines().last().cloned();
+        assert_eq!(outcome.exit_code(), 0);
+        assert_eq!(outcome.output.lines()[3].1, "[5, [2]]");
+        assert_eq!(last(&outcome), Some((Stream::Stdout, "2".to_string())));
+
+        let outcome = lox.run("evaluate", "var a = [1];\nprint a[1];");
+        assert_eq!(
+            outcome.diagnostics[0].to_string(),
+            "Index 1 out of bounds for list of length 1.\n[line 2]"
+        );
+    }
+
+    #[test]
+    fn test_map_keys_hash_by_value() {
+        let mut lox = Lox::new();
+        lox.extensions = true;
+
+        let outcome = lox.run(
+            "evaluate",
+            "var m = {\"b\": 1, -0: 2};\nm[0] += 1;\nm[\"c\"] = m[\"b\"];\nprint m;",
+        );
+
+        assert_eq!(
+            outcome.output.lines().last().unwrap().1,
+            "{0: 3, \"b\": 1, \"c\": 1}"
+        );
+    }
+
+    #[test]
+    fn test_clone_and_deep_clone_nested_structures() {
+        let mut lox = Lox::new();
+        lox.extensions = true;
+        let source = "var a = [[1], {\"k\": [2]}];\n\
+                      var s = clone(a);\nvar d = deepClone(a);\n\
+                      a[0][0] = 9;\na[1][\"k\"][0] = 8;\ns[0] = 7;\n\
+                      print a;\nprint s;\nprint d;";
+        let outcome = lox.run("evaluate", source);
+        let printed = &stdout_lines(&outcome)[6..];
+
+        assert_eq!(
+            printed,
+            vec![
+                "[[9], {\"k\": [8]}]",
+                "[7, {\"k\": [8]}]",
+                "[[1], {\"k\": [2]}]",
+            ]
+        );
+
+        let outcome = lox.run("evaluate", "var c = [1];\nc[0] = c;\ndeepClone(c);");
+        assert_eq!(
+            outcome.diagnostics[0].message,
+            "Cannot deep-clone a list that contains itself."
+        );
+    }
+
+    #[test]
+    fn test_output_is_truncated_at_byte_limit() {
+        let mut lox = Lox::new();
+        lox.max_output_bytes = Some(9);
+
+        let outcome = lox.run(
+            "evaluate",
+            "print \"hello\";\nprint \"world\";\npanic(\"not reached\");",
+        );
+
+        let printed = stdout_lines(&outcome);
+        assert_eq!(
+            printed,
+            vec!["hello", "wor", "... [output truncated at 9 bytes]"]
+        );
+        assert_eq!(outcome.diagnostics.len(), 1);
+        assert_eq!(
+            outcome.diagnostics[0].to_string(),
+            "Output exceeded the limit of 9 bytes.\n[line 2]"
+        );
+        assert_eq!(outcome.exit_code(), 70);
+    }
+
+    #[test]
+    fn test_exceeding_the_output_limit_can_be_caught() {
+        let mut lox = Lox::new();
+        lox.extensions = true;
+        lox.max_output_bytes = Some(9);
+
+        let outcome = lox.run(
+            "run",
+            "var caught = false;\ntry {\n  print \"hello\";\n  print \"world\";\n\
+             } catch (e) {\n  caught = e[\"message\"];\n}\nprint caught;",
+        );
+
+        assert_eq!(outcome.output.lines().len(), 3);
+        // The handler ran, so only printing what it caught fails.
+        assert_eq!(outcome.diagnostics.len(), 1);
+        assert_eq!(outcome.diagnostics[0].code, Some(Code::OutputLimitExceeded));
+        assert_eq!(outcome.diagnostics[0].line, Some(8));
+    }
+
+    #[test]
+    fn test_raw_string_keeps_quotes_and_counts_lines() {
+        let outcome = Lox::new().run("evaluate", "print \"\"\"say \"hi\"\nbye\"\"\";");
+        assert_eq!(outcome.output.lines()[0].1, "say \"hi\"\nbye");
+
+        let outcome = Lox::new().run("tokenize", "\"\"\"a\nb\"\"\"\n\"\"\"open\n");
+        assert_eq!(
+            outcome.diagnostics[0].to_string(),
+            "[line 3] Error: Unterminated raw string."
+        );
+    }
+
+    #[test]
+    fn test_directive_comments_set_per_file_options() {
+        let source = "// Demo\n// lox: extensions, strict-math\n\neprint 1;";
+        let outcome = Lox::new().run("evaluate", source);
+
+        assert_eq!(outcome.output.lines()[0], (Stream::Stderr, "1".to_string()));
+        assert_eq!(
+            outcome.diagnostics[0].to_string(),
+            "[line 2] Warning: Unknown option 'strict-math' in 'lox:' directive."
+        );
+    }
+
+    #[test]
+    fn test_switch_runs_first_matching_case_without_fallthrough() {
+        let mut lox = Lox::new();
+        lox.extensions = true;
+        let source = "switch (3) { case 1: print 1; case 2, 3: print 23; case 3: print 3; \
+                      default: print 0; }\nswitch (4) { case 1: print 1; default: print 0; }";
+
+        let outcome = lox.run("evaluate", source);
+
+        let printed = stdout_lines(&outcome);
+        assert_eq!(printed, vec!["23", "0"]);
+    }
+
+    #[test]
+    fn test_do_while_runs_body_before_checking_condition() {
+        let mut lox = Lox::new();
+        lox.extensions = true;
+
+        let outcome = lox.run(
+            "evaluate",
+            "var n = 0;\ndo n += 2; while (n < 5);\ndo print \"once\"; while (false);\nprint n;",
+        );
+
+        let printed = &stdout_lines(&outcome)[4..];
+        assert_eq!(printed, vec!["once", "6"]);
+    }
+
+    #[test]
+    fn test_for_in_iterates_lists_map_keys_and_characters() {
+        let mut lox = Lox::new();
+        lox.extensions = true;
+
+        let outcome = lox.run(
+            "evaluate",
+            "for (x in [1, \"a\"]) print x;\nfor (k in {\"b\": 1, 2: 0}) print k;\n\
+             for (c in \"hi\") print c;",
+        );
+
+        let printed = stdout_lines(&outcome);
+        assert_eq!(printed, vec!["1", "a", "2", "b", "h", "i"]);
+    }
+
+    #[test]
+    fn test_loop_bodies_run_afresh_on_every_iteration() {
+        let mut lox = Lox::new();
+        lox.extensions = true;
+
+        let outcome = lox.run(
+            "run",
+            "var total = 0;\nvar i = 0;\n\
+             do {\n  var step;\n  print step;\n  step = i;\n  total += step;\n  i += 1;\n\
+             } while (i < 3);\nprint total;\n\
+             for (x in [1, 2]) {\n  for (y in [10, 20]) print x * y;\n  x = 0;\n}",
+        );
+
+        let printed = stdout_lines(&outcome);
+        assert_eq!(
+            printed,
+            vec!["nil", "nil", "nil", "3", "10", "20", "20", "40"]
+        );
+    }
+
+    #[test]
+    fn test_try_catches_thrown_values_and_runtime_errors() {
+        let mut lox = Lox::new();
+        lox.extensions = true;
+
+        let outcome = lox.run(
+            "evaluate",
+            "try { print 1 / 0; } catch (e) { print e[\"message\"]; }\n\
+             try { try { throw \"inner\"; } finally { print \"finally\"; } }\n\
+             catch (e) { print e; }",
+        );
+
+        let printed = stdout_lines(&outcome);
+        assert_eq!(printed, vec!["Division by zero.", "finally", "inner"]);
+        assert!(!outcome.has(Severity::RuntimeError));
+    }
+
+    #[test]
+    fn test_stdin_filename_places_the_program() {
+        let dir = temp_dir("stdin");
+        fs::write(dir.join("greeting.lox"), "print \"hi\";").unwrap();
+        let mut lox = Lox::new();
+        lox.extensions = true;
+        let path = dir.join("main.lox").display().to_string();
+        lox.apply_flag("--stdin-filename", Some(path)).unwrap();
+
+        let outcome = lox.run("run", "import \"greeting.lox\";");
+
+        assert_eq!(lox.path, Some(dir.join("main.lox")));
+        assert_eq!(outcome.output.lines(), [(Stream::Stdout, "hi".into())]);
+    }
+
+    #[test]
+    fn test_fmt_and_check_run_on_each_file_of_a_directory() {
+        let dir = temp_dir("dir");
+        fs::create_dir_all(dir.join("src")).unwrap();
+        fs::write(dir.join("src/messy.lox"), "print  1;").unwrap();
+        fs::write(dir.join("broken.lox"), "print 1;\nprint (;").unwrap();
+        let lox = Lox::new();
+
+        let checked = lox.run_file("check", &dir.join("broken.lox"));
+        let formatted = lox.run_file("fmt", &dir.join("src/messy.lox"));
+        let rewritten = fs::read_to_string(dir.join("src/messy.lox")).unwrap();
+        let exit_code = lox.run_directory("check", &dir);
+        fs::remove_file(dir.join("broken.lox")).unwrap();
+        let clean_exit_code = lox.run_directory("check", &dir);
+
+        let broken = dir.join("broken.lox").display().to_string();
+        assert_eq!(checked.exit_code, 65);
+        assert_eq!(checked.lines.len(), 2);
+        assert_eq!(
+            checked.lines[1],
+            format!("{}: 1 error(s), 0 warning(s).", broken)
+        );
+        let messy = dir.join("src/messy.lox").display().to_string();
+        assert_eq!(formatted.exit_code, 0);
+        assert_eq!(formatted.lines, [format!("Formatted {}.", messy)]);
+        assert_eq!(rewritten, "print 1;\n");
+        assert_eq!(exit_code, 65);
+        assert_eq!(clean_exit_code, 0);
+    }
+
+    #[test]
+    fn test_assigning_a_constant_is_a_runtime_error() {
+        let mut lox = Lox::new();
+        lox.extensions = true;
+
+        let outcome =
+            lox.run("evaluate", "const limit = 3;\nvar const = 1;\nlimit += 1;");
+
+        assert_eq!(outcome.diagnostics.len(), 1);
+        assert_eq!(
+            outcome.diagnostics[0].message,
+            "Cannot assign to constant limit."
+        );
+    }
+
+    #[test]
+    fn test_nil_coalescing_short_circuits() {
+        let mut lox = Lox::new();
+        lox.extensions = true;
+
+        let outcome = lox.run(
+            "evaluate",
+            "print nil ?? \"fallback\";\nprint false ?? undefined;\nprint nil ?? nil ?? 3;",
+        );
+        let plain = Lox::new().run("tokenize", "nil ?? 1");
+
+        let printed = stdout_lines(&outcome);
+        assert_eq!(printed, vec!["fallback", "false", "3"]);
+        assert_eq!(plain.diagnostics.len(), 2);
+        assert_eq!(plain.diagnostics[0].message, "Unexpected character: ?");
+    }
+
+    #[test]
+    fn test_var_destructures_lists_and_maps() {
+        let mut lox = Lox::new();
+        lox.extensions = true;
+
+        let outcome = lox.run(
+            "evaluate",
+            "var [a, b, c] = [1, 2];\nvar {x, y} = {\"x\": 5};\nprint a + b;\nprint c;\nprint x;",
+        );
+
+        let printed = &stdout_lines(&outcome)[2..];
+        assert_eq!(printed, vec!["3", "nil", "5"]);
+    }
+
+    #[test]
+    fn test_bitwise_operators_truncate_to_integers() {
+        let mut lox = Lox::new();
+        lox.extensions = true;
+
+        let outcome = lox.run(
+            "evaluate",
+            "print 6.7 & 3 | 8;\nprint 6 ^ 3;\nprint ~5;\nprint 1 + 1 << 2;\nprint -16 >> 2;",
+        );
+
+        let printed = stdout_lines(&outcome);
+        assert_eq!(printed, vec!["10", "5", "-6", "8", "-4"]);
+    }
+
+    #[test]
+    fn test_bitwise_operators_wrap_large_and_negative_operands() {
+        let mut lox = Lox::new();
+        lox.extensions = true;
+
+        let outcome = lox.run(
+            "evaluate",
+            "print 4294967297 | 0;\nprint 2147483648 | 0;\nprint -1 & 4294967295;\n\
+             print ~2147483648;\nprint -3.7 | 0;\nprint 1 << -1;\nprint 256 >> 33;\n\
+             print 1 << 32;",
+        );
+
+        let printed = stdout_lines(&outcome);
+        assert_eq!(
+            printed,
+            vec![
+                "1",
+                "-2147483648",
+                "-1",
+                "2147483647",
+                "-3",
+                "-2147483648",
+                "128",
+                "1"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_non_ascii_characters_scan_whole() {
+        let plain = Lox::new().run("tokenize", "var é = \"café\";");
+        let mut lox = Lox::new();
+        lox.extensions = true;
+        let extended = lox.run("evaluate", "var naïve = \"café\";\nprint naïve;");
+
+        assert_eq!(plain.diagnostics.len(), 1);
+        assert_eq!(plain.diagnostics[0].message, "Unexpected character: é");
+        assert_eq!(extended.output.lines()[1].1, "café");
+    }
+
+    #[test]
+    fn test_spread_splices_lists_into_calls_and_literals() {
+        let mut lox = Lox::new();
+        lox.extensions = true;
+
+        let outcome = lox.run(
+            "evaluate",
+            "var rest = [2, 3];\nprint [1, ...rest, 4];\nprint deepEquals(...[rest, [2, 3]]);",
+        );
+        let error = lox.run("evaluate", "print [...1];");
+        let plain = Lox::new().run("tokenize", "a...b");
+
+        let printed = &stdout_lines(&outcome)[1..];
+        assert_eq!(printed, vec!["[1, 2, 3, 4]", "true"]);
+        assert_eq!(
+            error.diagnostics[0].message,
+            "Can only spread lists, not number."
+        );
+        let tokens = stdout_lines(&plain);
+        assert_eq!(
+            tokens,
+            [
+                "IDENTIFIER a a",
+                "DOT . null",
+                "DOT . null",
+                "DOT . null",
+                "IDENTIFIER b b",
+                "EOF  null"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_doc_comments_attach_to_the_next_declaration() {
+        let mut lox = Lox::new();
+        lox.extensions = true;
+
+        let outcome = lox.run(
+            "evaluate",
+            "/// Number of retries.\n/// Zero disables them.\nvar retries = 3;\n\
+             // Not a doc comment.\nvar other = 1;\nretries = 4;\n\
+             print doc(\"retries\");\nprint doc(\"other\");",
+        );
+
+        let printed = &stdout_lines(&outcome)[3..];
+        assert_eq!(
+            printed,
+            vec!["Number of retries.\nZero disables them.", "nil"]
+        );
+    }
+
+    #[test]
+    fn test_run_emits_only_printed_output() {
+        let outcome = Lox::new().run(
+            "run",
+            "var a = 1;\na = a + 1;\nif (false) print 0;\nprint a;",
+        );
+        let invalid = Lox::new().run("run", "var [a];");
+
+        assert_eq!(
+            outcome.output.lines(),
+            vec![(Stream::Stdout, "2".to_string())]
+        );
+        assert_eq!(invalid.exit_code(), 65);
+        assert!(invalid.output.lines().is_empty());
+    }
+
+    #[test]
+    fn test_blocks_scope_their_variables() {
+        let outcome = Lox::new().run(
+            "run",
+            "var a = 1;\nvar b = 1;\n{ var a = 2; b = a; print a; }\nprint a;\nprint b;",
+        );
+        let leaked = Lox::new().run("run", "{ var c = 1; }\nprint c;");
+
+        assert_eq!(
+            outcome.output.lines(),
+            vec![
+                (Stream::Stdout, "2".to_string()),
+                (Stream::Stdout, "1".to_string()),
+                (Stream::Stdout, "2".to_string()),
+            ]
+        );
+        assert_eq!(leaked.exit_code(), 70);
+        assert_eq!(leaked.diagnostics[0].message, "Undefined variable 'c'.");
+    }
+
+    #[test]
+    fn test_assigning_an_undeclared_variable_is_an_error() {
+        let outcome = Lox::new().run("run", "{ var a; a = 1; }\nb = 2;");
+
+        assert_eq!(outcome.exit_code(), 70);
+        assert_eq!(outcome.diagnostics[0].message, "Undefined variable 'b'.");
+    }
+
+    #[test]
+    fn test_redeclaring_a_local_is_a_static_error() {
+        let global = Lox::new().run("run", "var a = 1;\nvar a = 2;\nprint a;");
+        let local = Lox::new().run(
+            "check",
+            "{\n  var a = 1;\n  { var a = 2; }\n  var a = 3;\n}",
+        );
+
+        assert_eq!(global.exit_code(), 0);
+        assert_eq!(local.exit_code(), 65);
+        assert_eq!(local.diagnostics.len(), 1);
+        assert_eq!(local.diagnostics[0].line, Some(4));
+        assert_eq!(
+            local.diagnostics[0].code,
+            Some(Code::VariableAlreadyDeclared)
+        );
+    }
+
+    #[test]
+    fn test_numbers_are_doubles_printed_like_jlox() {
+        let mut lox = Lox::new();
+        lox.extensions = true;
+
+        let outcome = lox.run(
+            "run",
+            "print 0.1 + 0.2;\nprint 12345678901;\nprint 7 / 2;\nprint [1, 2.5];",
+        );
+        let parsed = lox.run("parse", "1 + 2.5;");
+
+        let printed = stdout_lines(&outcome);
+        assert_eq!(
+            printed,
+            vec!["0.30000000000000004", "12345678901", "3.5", "[1, 2.5]"]
+        );
+        assert_eq!(parsed.output.lines()[0].1, "(+ 1.0 2.5);");
+    }
+
+    #[test]
+    fn test_output_before_a_runtime_error_is_kept() {
+        let outcome = Lox::new().run("run", "print \"before\";\nprint -\"a\";\nprint 3;");
+
+        assert_eq!(outcome.exit_code(), 70);
+        assert_eq!(outcome.output.lines(), [(Stream::Stdout, "before".into())]);
+    }
+
+    #[test]
+    fn test_deep_nesting_is_an_error_not_a_crash() {
+        let run = || {
+            let mut lox = Lox::new();
+            lox.extensions = true;
+            let sum =
+                Lox::new().run("run", &format!("print {};", vec!["1"; 5000].join(" + ")));
+            let parens = format!("{}1{}", "(".repeat(100), ")".repeat(100));
+            lox.limits.depth = 50;
+            let caught = lox.run(
+                "run",
+                &format!(
+                    "try {{ print {}; }} catch (e) {{ print e[\"message\"]; }}",
+                    parens
+                ),
+            );
+            let nested = Lox::new().run(
+                "check",
+                &format!("print {}true;", "!".repeat(interpreter::DEFAULT_MAX_DEPTH)),
+            );
+            let chain = vec!["1"; 20000].join(" + ");
+            let long = Lox::new().run("check", &format!("print {};", chain));
+            (
+                sum.output.lines().to_vec(),
+                caught.output.lines().to_vec(),
+                nested.diagnostics,
+                long.diagnostics,
+            )
+        };
+        let (sum, caught, nested, long) = interpreter::with_stack(run);
+
+        assert_eq!(sum, [(Stream::Stdout, "5000".into())]);
+        assert_eq!(caught, [(Stream::Stdout, "Stack overflow.".into())]);
+        assert_eq!(nested.len(), 1);
+        assert_eq!(nested[0].code, Some(Code::NestedTooDeeply));
+        assert_eq!(long.len(), 1);
+        assert_eq!(long[0].code, Some(Code::NestedTooDeeply));
+    }
+
+    #[test]
+    fn test_limits_stop_runaway_loops_past_catch() {
+        let run = |limits: Limits| {
+            let mut lox = Lox::new();
+            lox.extensions = true;
+            lox.limits = limits;
+            let outcome = lox.run(
+                "run",
+                "try { do {} while (true); } catch (e) { print \"caught\"; }",
+            );
+            assert!(outcome.output.lines().is_empty());
+            outcome.diagnostics[0].code
+        };
+
+        let statements = run(Limits {
+            statements: Some(100),
+            ..Limits::default()
+        });
+        let iterations = run(Limits {
+            loop_iterations: Some(5),
+            ..Limits::default()
+        });
+        let timeout = run(Limits {
+            timeout: Some(Duration::from_millis(10)),
+            ..Limits::default()
+        });
+
+        assert_eq!(statements, Some(Code::StatementLimitExceeded));
+        assert_eq!(iterations, Some(Code::LoopLimitExceeded));
+        assert_eq!(timeout, Some(Code::TimeoutExceeded));
+    }
+
+    #[test]
+    fn test_check_reports_syntax_errors_without_running() {
+        let valid = Lox::new().run("check", "print 1;\nprint undefined;");
+        let invalid = Lox::new().run("check", "print 1;\nvar [a];");
+
+        assert_eq!(valid.exit_code(), 0);
+        assert!(valid.output.lines().is_empty());
+        assert_eq!(invalid.exit_code(), 65);
+        assert!(invalid.output.lines().is_empty());
+    }
+
+    #[test]
+    fn test_every_syntax_error_is_reported_in_line_order() {
+        let outcome = Lox::new().run("check", "print 1 +;\nprint );\nprint \"open");
+
+        let lines: Vec<Option<usize>> = outcome
+            .diagnostics
+            .iter()
+            .map(|diagnostic| diagnostic.line)
+            .collect();
+        assert_eq!(lines[..3], [Some(1), Some(2), Some(3)]);
+        assert!(lines.is_sorted());
+        // The scanner's error comes before the parser's on the same line.
+        assert_eq!(outcome.diagnostics[2].code, Some(Code::UnterminatedString));
+    }
+
+    #[test]
+    fn test_ast_dumps_json_with_lines() {
+        let outcome = Lox::new().run("ast", "var a = 1;\nprint -a;");
+
+        assert_eq!(
+            outcome.output.lines(),
+            vec![(
+                Stream::Stdout,
+                "{\"type\":\"Program\",\"declarations\":[\
+                 {\"type\":\"Var\",\"line\":1,\"target\":{\"type\":\"Variable\",\"line\":1,\"name\":\"a\"},\
+                 \"initializer\":{\"type\":\"Literal\",\"value\":1},\"doc\":null},\
+                 {\"type\":\"Print\",\"value\":{\"type\":\"Unary\",\"line\":2,\
+                 \"operator\":\"-\",\"right\":{\"type\":\"Variable\",\"line\":2,\"name\":\"a\"}}}]}"
+                    .to_string()
+            )]
+        );
+    }
+
+    #[test]
+    fn test_ast_dumps_dot_graphs() {
+        let mut lox = Lox::new();
+        lox.format = Some(Format::Dot);
+        let outcome = lox.run("ast", "print 1 + \"a\";");
+        let graph = &outcome.output.lines()[0].1;
+
+        assert!(graph.starts_with("digraph ast {\n  node [shape=box];\n"));
+        assert!(
+            graph.contains("  n2 [label=\"Binary\\noperator = \\\"+\\\"\\nline 1\"];")
+        );
+        assert!(graph.contains("  n1 -> n2 [label=\"value\"];"));
+        assert!(graph.ends_with("\n}"));
+    }
+
+    #[test]
+    fn test_diagnostics_render_as_json_lines() {
+        let outcome = Lox::new().run("run", "var x = 1\n  print x;");
+        let runtime = Lox::new().run("run", "print -\"a\";");
+
+        assert_eq!(
+            outcome.diagnostics[0].to_json(Some("a.lox")).to_string(),
+            "{\"code\":\"E0201\",\"message\":\"at 'print' Error: missing semicolon at \
+             end\",\"file\":\"a.lox\",\"line\":2,\"column\":3,\"span\":{\"start\":12,\
+             \"end\":17},\"severity\":\"error\"}"
+        );
+        assert_eq!(
+            runtime.diagnostics[0].to_json(None).to_string(),
+            "{\"code\":\"E0303\",\"message\":\"Operand must be a number.\",\"file\":null,\
+             \"line\":1,\"column\":7,\"span\":{\"start\":6,\"end\":7},\
+             \"severity\":\"error\"}"
+        );
+    }
+
+    #[test]
+    fn test_rich_diagnostics_underline_the_source() {
+        let source = "var s = \"a\";\n\tprint -s;";
+        let outcome = Lox::new().run("run", source);
+
+        assert_eq!(
+            outcome.diagnostics[0].to_rich(source, Some("a.lox")),
+            "error[E0303]: Operand must be a number.\n --> a.lox:2:8\n  |\n2 | \tprint \
+             -s;\n  | \t      ^"
+        );
+        let outcome = Lox::new().run("run", "var x = 1\nprint x;");
+        let rich = outcome.diagnostics[0].to_rich("var x = 1\nprint x;", None);
+        assert!(rich.ends_with(
+            "2 | print x;\n  | ^^^^^\n  = help: end the statement \
+             with ';', or pass --asi to end it at the line break"
+        ));
+    }
+
+    #[test]
+    fn test_minify_keeps_only_needed_spaces() {
+        let source = "// c\nvar a = 1; /* x */\nif (a >= 1) {\n  print a - -1;\n}\n";
+        let outcome = Lox::new().run("minify", source);
+
+        let minified = &outcome.output.lines()[0].1;
+        assert_eq!(minified, "var a=1;if(a>=1){print a--1;}");
+        assert_eq!(
+            Lox::new().run("run", minified).output.lines(),
+            Lox::new().run("run", source).output.lines()
+        );
+    }
+
+    #[test]
+    fn test_highlight_keeps_source_layout() {
+        let mut lox = Lox::new();
+        lox.format = Some(Format::Html);
+        let html = lox.run("highlight", "print \"\"\"a\n<b\"\"\";  // c");
+
+        assert_eq!(
+            html.output.lines()[0].1,
+            "<pre class=\"lox\"><span class=\"keyword\">print</span> <span \
+             class=\"string\">&quot;&quot;&quot;a\n&lt;b&quot;&quot;&quot;</span><span \
+             class=\"punctuation\">;</span>  <span class=\"comment\">// c</span></pre>"
+        );
+    }
+
+    #[test]
+    fn test_tokenize_formats_include_columns() {
+        let mut lox = Lox::new();
+        lox.format = Some(Format::Csv);
+        let csv = lox.run("tokenize", "var s = \"a,b\";\n  1.50");
+        lox.format = Some(Format::Json);
+        let json = lox.run("tokenize", "(");
+
+        let rows: Vec<&str> = csv
+            .output
+            .lines()
+            .iter()
+            .map(|(_, row)| row.as_str())
+            .collect();
+        assert_eq!(
+            rows,
+            vec![
+                "type,lexeme,literal,line,column",
+                "VAR,var,,1,1",
+                "IDENTIFIER,s,s,1,5",
+                "EQUAL,=,,1,7",
+                "STRING,\"\"\"a,b\"\"\",\"a,b\",1,9",
+                "SEMICOLON,;,,1,14",
+                "NUMBER,1.50,1.5,2,3",
+                "EOF,,,2,7",
+            ]
+        );
+        assert_eq!(
+            json.output.lines()[0].1,
+            "[{\"type\":\"LEFT_PAREN\",\"lexeme\":\"(\",\"literal\":null,\"line\":1,\"column\":1},\
+             {\"type\":\"EOF\",\"lexeme\":\"\",\"literal\":null,\"line\":1,\"column\":2}]"
+        );
+    }
+
+    #[test]
+    fn test_empty_program_is_handled_by_each_command() {
+        let printed = |command: &str, format: Option<Format>| {
+            let mut lox = Lox::new();
+            lox.format = format;
+            let outcome = lox.run(command, "");
+            assert_eq!(outcome.exit_code(), 0, "{}", command);
+            outcome
+                .output
+                .lines()
+                .iter()
+                .map(|(_, line)| line.clone())
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(printed("tokenize", None), ["EOF  null"]);
+        assert_eq!(
+            printed("tokenize", Some(Format::Json)),
+            [r#"[{"type":"EOF","lexeme":"","literal":null,"line":1,"column":1}]"#]
+        );
+        assert_eq!(
+            printed("ast", None),
+            [r#"{"type":"Program","declarations":[]}"#]
+        );
+        for command in [
+            "parse",
+            "fmt",
+            "minify",
+            "highlight",
+            "lint",
+            "check",
+            "run",
+        ] {
+            assert!(printed(command, None).is_empty(), "{}", command);
+        }
+    }
+
+    #[test]
+    fn test_fmt_prints_canonical_source() {
+        let source = "var  x=1;if(x>0){print x;}else print(x+1)*2;\nx=x+1;";
+        let formatted = Lox::new().run("fmt", source);
+        let mut lox = Lox::new();
+        lox.check = true;
+        let unformatted = lox.run("fmt", source);
+        let canonical = lox.run("fmt", &format!("{}\n", formatted.output.lines()[0].1));
+
+        assert_eq!(
+            formatted.output.lines()[0].1,
+            "var x = 1;\nif (x > 0) {\n    print x;\n} else\n    print (x + 1) * 2;\nx = x + 1;"
+        );
+        assert_eq!(unformatted.exit_code(), 1);
+        assert_eq!(unformatted.diagnostics[0].line, Some(1));
+        assert!(canonical.output.lines().is_empty());
+        assert_eq!(canonical.exit_code(), 0);
+    }
+
+    #[test]
+    fn test_fmt_keeps_comments_and_number_spelling() {
+        let source =
+            "// header\nvar  x=1.50; // price\nif(x>0){\n  /* note */\n  print x;\n\
+                      // last in block\n}\n{}\nprint 2.0;\n// end";
+        let formatted = Lox::new().run("fmt", source);
+        let mut lox = Lox::new();
+        lox.check = true;
+        let canonical = lox.run("fmt", &format!("{}\n", formatted.output.lines()[0].1));
+
+        assert_eq!(
+            formatted.output.lines()[0].1,
+            "// header\nvar x = 1.50; // price\nif (x > 0) {\n    /* note */\n    print x;\n    \
+             // last in block\n}\n{}\nprint 2.0;\n// end"
+        );
+        assert_eq!(canonical.exit_code(), 0);
+    }
+
+    #[test]
+    fn test_lint_reports_suspicious_code() {
+        let source =
+            "var unused = 1;\nvar late;\nprint late;\nif (!false) late = late;\n\
+                      { throw 1; print 2; }";
+        let mut lox = Lox::new();
+        lox.extensions = true;
+        let outcome = lox.run("lint", source);
+        lox.deny_warnings = true;
+        let denied = lox.run("lint", source);
+
+        let warnings: Vec<String> = outcome
+            .diagnostics
+            .iter()
+            .map(|diagnostic| diagnostic.to_string())
+            .collect();
+        assert_eq!(
+            warnings,
+            vec![
+                "[line 1] Warning: Variable 'unused' is never read. [W0405]",
+                "[line 3] Warning: Variable 'late' is read before it is assigned. [W0406]",
+                "[line 4] Warning: Condition is always true. [W0407]",
+                "[line 4] Warning: Variable 'late' is assigned to itself. [W0408]",
+                "[line 5] Warning: Code after 'throw' is unreachable. [W0409]",
+            ]
+        );
+        assert_eq!(outcome.exit_code(), 0);
+        assert_eq!(denied.exit_code(), 1);
+    }
+
+    #[test]
+    fn test_trace_emits_statements_and_assignments_to_stderr() {
+        let mut lox = Lox::new();
+        lox.trace = true;
+        let outcome = lox.run(
+            "run",
+            "var a = 1;\nif (a == 1) {\n  a = a + 1;\n}\nprint \"done\";",
+        );
+
+        assert_eq!(
+            outcome.output.lines(),
+            vec![
+                (Stream::Stderr, "[line 1] var a = 1;".to_string()),
+                (Stream::Stderr, "[line 1]   a = 1".to_string()),
+                (Stream::Stderr, "[line 2] if (a == 1) {".to_string()),
+                (Stream::Stderr, "[line 3] a = a + 1;".to_string()),
+                (Stream::Stderr, "[line 3]   a = 2".to_string()),
+                (Stream::Stderr, "[line 5] print \"done\";".to_string()),
+                (Stream::Stdout, "done".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_coverage_counts_lines_that_ran() {
+        let mut lox = Lox::new();
+        lox.coverage = true;
+        let outcome = lox.run(
+            "run",
+            "var a = \"ab\";\nfor (x in a)\n  print x;\nif (a == nil)\n  print a;\n\
+             print \"done\";",
+        );
+
+        assert_eq!(
+            outcome.coverage.unwrap(),
+            "TN:\nSF:-\nDA:1,1\nDA:2,1\nDA:3,2\nDA:4,1\nDA:5,0\nDA:6,1\nLF:6\nLH:5\n\
+             end_of_record\n"
+        );
+    }
+
+    #[test]
+    fn test_profile_counts_statements_and_native_calls() {
+        let mut lox = Lox::new();
+        lox.profile = true;
+        lox.extensions = true;
+        let outcome = lox.run("run", "var a = [1, 2];\nfor (x in a)\n  print clone(x);");
+
+        let calls: Vec<(&str, &str)> = outcome.output.lines()[2..]
+            .iter()
+            .filter(|(stream, _)| *stream == Stream::Stderr)
+            .filter_map(|(_, row)| {
+                let mut columns = row.split_whitespace();
+                Some((columns.next()?, columns.next()?))
+            })
+            .collect();
+        let mut lines = calls[2..5].to_vec();
+        lines.sort();
+        assert_eq!(lines, vec![("1", "1"), ("2", "1"), ("3", "2")]);
+        assert_eq!(
+            calls[5..],
+            [("Profile", "by"), ("function", "calls"), ("clone", "2")]
+        );
+    }
+
+    #[test]
+    fn test_bench_reports_timings_without_program_output() {
+        let mut lox = Lox::new();
+        lox.iterations = 3;
+        let outcome =
+            lox.bench("var a = 1;\nfor (x in \"ab\") print x;", Output::default());
+        let failed = lox.bench("print nil + 1;", Output::default());
+
+        let labels: Vec<&str> = outcome
+            .output
+            .lines()
+            .iter()
+            .map(|(_, line)| line.split(':').next().unwrap_or_default())
+            .collect();
+        assert_eq!(labels, vec!["runs", "min", "median", "mean", "statements"]);
+        assert_eq!(
+            outcome.output.lines()[0].1,
+            "runs:       3 (after 1 warmup)"
+        );
+        assert_eq!(outcome.output.lines()[4].1, "statements: 4 per run");
+        assert_eq!(failed.exit_code(), 70);
+        assert!(failed.output.lines().is_empty());
+    }
+
+    #[test]
+    fn test_args_returns_the_script_arguments() {
+        let mut lox = Lox::new();
+        lox.script_args = vec!["a".into(), "b c".into()];
+        lox.extensions = true;
+        let outcome = lox.run("run", "print args();\nprint args()[1];");
+
+        assert_eq!(
+            outcome.output.lines(),
+            vec![
+                (Stream::Stdout, "[\"a\", \"b c\"]".to_string()),
+                (Stream::Stdout, "b c".to_string())
+            ]
+        );
+    }
 }
@@ -0,0 +1,488 @@
+//! Catalog of every user-facing diagnostic message.
+//!
+//! Messages are looked up by [`Code`] and interpolated with positional
+//! `{0}`, `{1}`, ... placeholders. The built-in catalog is English; an
+//! embedder can install a translated one with [`set_catalog`], and any code
+//! the translation does not cover falls back to English.
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fmt::Display;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Code {
+    // Scanner
+    UnterminatedString,
+    UnexpectedCharacter,
+    UnterminatedBlockComment,
+    UnterminatedRawString,
+
+    // Parser
+    MissingSemicolon,
+    ExpectRightBraceAfterBlock,
+    ExpectLeftParenAfterIf,
+    ExpectRightParenAfterIf,
+    InvalidAssignmentTarget,
+    UnmatchedParentheses,
+    ExpectExpression,
+    ExpectColonInConditional,
+    ExpectRightParenAfterArguments,
+    ExpectRightBracketAfterElements,
+    ExpectRightBracketAfterIndex,
+    ExpectColonAfterMapKey,
+    ExpectRightBraceAfterMapEntries,
+    ExpectLeftParenAfterSwitch,
+    ExpectRightParenAfterSwitch,
+    ExpectLeftBraceBeforeCases,
+    ExpectColonAfterCase,
+    ExpectCaseOrDefault,
+    ExpectRightBraceAfterCases,
+    DuplicateDefault,
+    ExpectWhileAfterDoBody,
+    ExpectLeftParenAfterWhile,
+    ExpectRightParenAfterCondition,
+    ExpectLeftParenAfterFor,
+    ExpectForIn,
+    ExpectRightParenAfterForIn,
+    ExpectLeftBraceAfter,
+    ExpectLeftParenAfterCatch,
+    ExpectCatchVariable,
+    ExpectRightParenAfterCatch,
+    ExpectCatchOrFinally,
+    ExpectConstInitializer,
+    ExpectBindingName,
+    ExpectClosingPattern,
+    ExpectPatternInitializer,
+    NestedTooDeeply,
+    ExpectVariableName,
+
+    // Runtime
+    UndefinedVariable,
+    OperandMustBeBooleanOrNumber,
+    OperandMustBeNumber,
+    InvalidUnaryOperator,
+    DivisionByZero,
+    InvalidNumberOperator,
+    InvalidStringOperator,
+    InvalidBinaryOperands,
+    ConditionNotBoolean,
+    NotCallable,
+    ArityMismatch,
+    OperandsMustBeTwoNumbersOrTwoStrings,
+    NotIndexable,
+    IndexNotInteger,
+    IndexOutOfBounds,
+    InvalidMapKey,
+    CannotCloneCycle,
+    OutputLimitExceeded,
+    OutputTruncated,
+    NotIterable,
+    UncaughtThrow,
+    ModuleNotFound,
+    CircularImport,
+    ModuleHasErrors,
+    AssignToConstant,
+    CannotDestructure,
+    SpreadNotAllowed,
+    CannotSpread,
+    DocExpectsName,
+    StackOverflow,
+    StatementLimitExceeded,
+    LoopLimitExceeded,
+    TimeoutExceeded,
+    TraceInScript,
+    OperandDetail,
+    CannotNegate,
+    CannotAdd,
+    CannotSubtract,
+    CannotMultiply,
+    CannotDivide,
+    CannotCompare,
+    CannotApplyUnary,
+    CannotApplyBinary,
+
+    // Preprocessor
+    ExpectDirectiveName,
+    ExpectMacroName,
+    UnknownDirective,
+    UnmatchedDirective,
+    UnterminatedConditional,
+
+    // Tools
+    NotFormatted,
+    SyntaxErrorCount,
+    FileSummary,
+    FileFormatted,
+    FilesSummary,
+
+    // Resolver
+    VariableAlreadyDeclared,
+
+    // Warnings
+    MixedComparison,
+    ConvertRightOperand,
+    ConvertLeftOperand,
+    UnknownDirectiveOption,
+    UnusedVariable,
+    ReadBeforeAssignment,
+    ConstantCondition,
+    SelfAssignment,
+    UnreachableAfterThrow,
+
+    // Help notes
+    HelpMissingSemicolon,
+    HelpUndefinedVariable,
+    HelpStackOverflow,
+    HelpStatementLimit,
+    HelpLoopLimit,
+    HelpTimeout,
+}
+
+impl Code {
+    /// Stable identifier of the message, independent of its translation.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Code::UnterminatedString => "E0101",
+            Code::UnexpectedCharacter => "E0102",
+            Code::UnterminatedBlockComment => "E0103",
+            Code::UnterminatedRawString => "E0104",
+            Code::MissingSemicolon => "E0201",
+            Code::ExpectRightBraceAfterBlock => "E0202",
+            Code::ExpectLeftParenAfterIf => "E0203",
+            Code::ExpectRightParenAfterIf => "E0204",
+            Code::InvalidAssignmentTarget => "E0205",
+            Code::UnmatchedParentheses => "E0206",
+            Code::ExpectExpression => "E0207",
+            Code::ExpectColonInConditional => "E0208",
+            Code::ExpectRightParenAfterArguments => "E0209",
+            Code::ExpectRightBracketAfterElements => "E0210",
+            Code::ExpectRightBracketAfterIndex => "E0211",
+            Code::ExpectColonAfterMapKey => "E0212",
+            Code::ExpectRightBraceAfterMapEntries => "E0213",
+            Code::ExpectLeftParenAfterSwitch => "E0214",
+            Code::ExpectRightParenAfterSwitch => "E0215",
+            Code::ExpectLeftBraceBeforeCases => "E0216",
+            Code::ExpectColonAfterCase => "E0217",
+            Code::ExpectCaseOrDefault => "E0218",
+            Code::ExpectRightBraceAfterCases => "E0219",
+            Code::DuplicateDefault => "E0220",
+            Code::ExpectWhileAfterDoBody => "E0221",
+            Code::ExpectLeftParenAfterWhile => "E0222",
+            Code::ExpectRightParenAfterCondition => "E0223",
+            Code::ExpectLeftParenAfterFor => "E0224",
+            Code::ExpectForIn => "E0225",
+            Code::ExpectRightParenAfterForIn => "E0226",
+            Code::ExpectLeftBraceAfter => "E0227",
+            Code::ExpectLeftParenAfterCatch => "E0228",
+            Code::ExpectCatchVariable => "E0229",
+            Code::ExpectRightParenAfterCatch => "E0230",
+            Code::ExpectCatchOrFinally => "E0231",
+            Code::ExpectConstInitializer => "E0232",
+            Code::ExpectBindingName => "E0233",
+            Code::ExpectClosingPattern => "E0234",
+            Code::ExpectPatternInitializer => "E0235",
+            Code::NestedTooDeeply => "E0236",
+            Code::ExpectVariableName => "E0237",
+            Code::UndefinedVariable => "E0301",
+            Code::OperandMustBeBooleanOrNumber => "E0302",
+            Code::OperandMustBeNumber => "E0303",
+            Code::InvalidUnaryOperator => "E0304",
+            Code::DivisionByZero => "E0305",
+            Code::InvalidNumberOperator => "E0306",
+            Code::InvalidStringOperator => "E0307",
+            Code::InvalidBinaryOperands => "E0308",
+            Code::ConditionNotBoolean => "E0309",
+            Code::NotCallable => "E0310",
+            Code::ArityMismatch => "E0311",
+            Code::OperandsMustBeTwoNumbersOrTwoStrings => "E0312",
+            Code::NotIndexable => "E0313",
+            Code::IndexNotInteger => "E0314",
+            Code::IndexOutOfBounds => "E0315",
+            Code::InvalidMapKey => "E0316",
+            Code::CannotCloneCycle => "E0317",
+            Code::OutputLimitExceeded => "E0318",
+            Code::OutputTruncated => "E0319",
+            Code::NotIterable => "E0320",
+            Code::UncaughtThrow => "E0321",
+            Code::ModuleNotFound => "E0322",
+            Code::CircularImport => "E0323",
+            Code::ModuleHasErrors => "E0324",
+            Code::AssignToConstant => "E0325",
+            Code::CannotDestructure => "E0326",
+            Code::SpreadNotAllowed => "E0327",
+            Code::CannotSpread => "E0328",
+            Code::DocExpectsName => "E0329",
+            Code::StackOverflow => "E0330",
+            Code::StatementLimitExceeded => "E0331",
+            Code::LoopLimitExceeded => "E0332",
+            Code::TimeoutExceeded => "E0333",
+            Code::TraceInScript => "E0380",
+            Code::OperandDetail => "E0390",
+            Code::CannotNegate => "E0391",
+            Code::CannotAdd => "E0392",
+            Code::CannotSubtract => "E0393",
+            Code::CannotMultiply => "E0394",
+            Code::CannotDivide => "E0395",
+            Code::CannotCompare => "E0396",
+            Code::CannotApplyUnary => "E0397",
+            Code::CannotApplyBinary => "E0398",
+            Code::ExpectDirectiveName => "E0501",
+            Code::ExpectMacroName => "E0502",
+            Code::UnknownDirective => "E0503",
+            Code::UnmatchedDirective => "E0504",
+            Code::UnterminatedConditional => "E0505",
+            Code::NotFormatted => "E0602",
+            Code::SyntaxErrorCount => "E0603",
+            Code::FileSummary => "E0604",
+            Code::FileFormatted => "E0605",
+            Code::FilesSummary => "E0606",
+            Code::VariableAlreadyDeclared => "E0701",
+            Code::MixedComparison => "W0401",
+            Code::ConvertRightOperand => "W0402",
+            Code::ConvertLeftOperand => "W0403",
+            Code::UnknownDirectiveOption => "W0404",
+            Code::UnusedVariable => "W0405",
+            Code::ReadBeforeAssignment => "W0406",
+            Code::ConstantCondition => "W0407",
+            Code::SelfAssignment => "W0408",
+            Code::UnreachableAfterThrow => "W0409",
+            Code::HelpMissingSemicolon => "H0201",
+            Code::HelpUndefinedVariable => "H0301",
+            Code::HelpStackOverflow => "H0330",
+            Code::HelpStatementLimit => "H0331",
+            Code::HelpLoopLimit => "H0332",
+            Code::HelpTimeout => "H0333",
+        }
+    }
+
+    /// The help note printed under a diagnostic with this code, if any.
+    pub fn help(&self) -> Option<Code> {
+        match self {
+            Code::MissingSemicolon => Some(Code::HelpMissingSemicolon),
+            Code::UndefinedVariable => Some(Code::HelpUndefinedVariable),
+            Code::StackOverflow => Some(Code::HelpStackOverflow),
+            Code::StatementLimitExceeded => Some(Code::HelpStatementLimit),
+            Code::LoopLimitExceeded => Some(Code::HelpLoopLimit),
+            Code::TimeoutExceeded => Some(Code::HelpTimeout),
+            _ => None,
+        }
+    }
+
+    fn english(&self) -> &'static str {
+        match self {
+            Code::UnterminatedString => "Unterminated string.",
+            Code::UnexpectedCharacter => "Unexpected character: {0}",
+            Code::UnterminatedBlockComment => "Unterminated block comment.",
+            Code::UnterminatedRawString => "Unterminated raw string.",
+            Code::MissingSemicolon => "Error: missing semicolon at end",
+            Code::ExpectRightBraceAfterBlock => "Expect '}' after block.",
+            Code::ExpectLeftParenAfterIf => "Expect '(' after 'if'.",
+            Code::ExpectRightParenAfterIf => "Expect ')' after if condition.",
+            Code::InvalidAssignmentTarget => "Invalid assignment target.",
+            Code::UnmatchedParentheses => "Error: Unmatched parentheses.",
+            Code::ExpectExpression => "Unexpected error",
+            Code::ExpectColonInConditional => {
+                "Expect ':' after then branch of conditional expression."
+            }
+            Code::ExpectRightParenAfterArguments => "Expect ')' after arguments.",
+            Code::ExpectRightBracketAfterElements => "Expect ']' after list elements.",
+            Code::ExpectRightBracketAfterIndex => "Expect ']' after index.",
+            Code::ExpectColonAfterMapKey => "Expect ':' after map key.",
+            Code::ExpectRightBraceAfterMapEntries => "Expect '}' after map entries.",
+            Code::ExpectLeftParenAfterSwitch => "Expect '(' after 'switch'.",
+            Code::ExpectRightParenAfterSwitch => "Expect ')' after switch value.",
+            Code::ExpectLeftBraceBeforeCases => "Expect '{' before switch cases.",
+            Code::ExpectColonAfterCase => "Expect ':' after case.",
+            Code::ExpectCaseOrDefault => "Expect 'case' or 'default' in switch.",
+            Code::ExpectRightBraceAfterCases => "Expect '}' after switch cases.",
+            Code::DuplicateDefault => "A switch can only have one 'default'.",
+            Code::ExpectWhileAfterDoBody => "Expect 'while' after do body.",
+            Code::ExpectLeftParenAfterWhile => "Expect '(' after 'while'.",
+            Code::ExpectRightParenAfterCondition => "Expect ')' after condition.",
+            Code::ExpectLeftParenAfterFor => "Expect '(' after 'for'.",
+            Code::ExpectForIn => "Expect 'name in collection' after 'for ('.",
+            Code::ExpectRightParenAfterForIn => "Expect ')' after for-in collection.",
+            Code::ExpectLeftBraceAfter => "Expect '{' after '{0}'.",
+            Code::ExpectLeftParenAfterCatch => "Expect '(' after 'catch'.",
+            Code::ExpectCatchVariable => "Expect error variable name.",
+            Code::ExpectRightParenAfterCatch => "Expect ')' after catch variable.",
+            Code::ExpectCatchOrFinally => "Expect 'catch' or 'finally' after try block.",
+            Code::ExpectConstInitializer => "Expect '=' after constant name.",
+            Code::ExpectBindingName => "Expect variable name in destructuring pattern.",
+            Code::ExpectClosingPattern => "Expect '{0}' after destructuring pattern.",
+            Code::ExpectPatternInitializer => "Expect '=' after destructuring pattern.",
+            Code::NestedTooDeeply => "Too much nesting.",
+            Code::ExpectVariableName => "Expect variable name.",
+            Code::UndefinedVariable => "Undefined variable '{0}'.",
+            Code::OperandMustBeBooleanOrNumber => "Operand must be a boolean or number.",
+            Code::OperandMustBeNumber => "Operand must be a number.",
+            Code::InvalidUnaryOperator => "Invalid unary operator.",
+            Code::DivisionByZero => "Division by zero.",
+            Code::InvalidNumberOperator => "Invalid binary operator for numbers.",
+            Code::InvalidStringOperator => "Invalid binary operator for strings.",
+            Code::InvalidBinaryOperands => "Invalid operands for binary operator.",
+            Code::ConditionNotBoolean => {
+                "Expected result of condition to be boolean or nil"
+            }
+            Code::NotCallable => "Can only call functions and classes.",
+            Code::ArityMismatch => "Expected {0} arguments but got {1}.",
+            Code::OperandsMustBeTwoNumbersOrTwoStrings => {
+                "Operands must be two numbers or two strings."
+            }
+            Code::NotIndexable => "Only lists and maps can be indexed, not {0}.",
+            Code::IndexNotInteger => "List index must be an integer, got {0}.",
+            Code::IndexOutOfBounds => "Index {0} out of bounds for list of length {1}.",
+            Code::InvalidMapKey => "Map keys must be strings or numbers, not {0}.",
+            Code::CannotCloneCycle => "Cannot deep-clone a {0} that contains itself.",
+            Code::OutputLimitExceeded => "Output exceeded the limit of {0} bytes.",
+            Code::OutputTruncated => "... [output truncated at {0} bytes]",
+            Code::NotIterable => {
+                "Can only iterate over lists, maps and strings, not {0}."
+            }
+            Code::UncaughtThrow => "Uncaught exception: {0}",
+            Code::ModuleNotFound => "Cannot read module '{0}'.",
+            Code::CircularImport => "Circular import of '{0}'.",
+            Code::ModuleHasErrors => "Module '{0}' has syntax errors.",
+            Code::AssignToConstant => "Cannot assign to constant {0}.",
+            Code::CannotDestructure => "Cannot destructure {0} with a {1} pattern.",
+            Code::SpreadNotAllowed => {
+                "Can only spread inside argument lists and list literals."
+            }
+            Code::CannotSpread => "Can only spread lists, not {0}.",
+            Code::DocExpectsName => "doc() expects a variable name, not {0}.",
+            Code::StackOverflow => "Stack overflow.",
+            Code::StatementLimitExceeded => "Ran more than {0} statements.",
+            Code::LoopLimitExceeded => "Loop ran more than {0} iterations.",
+            Code::TimeoutExceeded => "Timed out after {0} ms.",
+            Code::TraceInScript => "[line {0}] in script",
+            Code::OperandDetail => "{0} ({1})",
+            Code::CannotNegate => "cannot negate {0}",
+            Code::CannotAdd => "cannot add {0} and {1}",
+            Code::CannotSubtract => "cannot subtract {1} from {0}",
+            Code::CannotMultiply => "cannot multiply {0} and {1}",
+            Code::CannotDivide => "cannot divide {0} by {1}",
+            Code::CannotCompare => "cannot compare {0} and {1}",
+            Code::CannotApplyUnary => "cannot apply '{1}' to {0}",
+            Code::CannotApplyBinary => "cannot apply '{2}' to {0} and {1}",
+            Code::ExpectDirectiveName => "Expect directive name after '#'.",
+            Code::ExpectMacroName => "Expect macro name after '#{0}'.",
+            Code::UnknownDirective => "Unknown directive '#{0}'.",
+            Code::UnmatchedDirective => "'#{0}' without matching '#ifdef'.",
+            Code::UnterminatedConditional => "Unterminated '#{0}'; expect '#endif'.",
+            Code::NotFormatted => "Not formatted; 'lox fmt' prints the canonical form.",
+            Code::SyntaxErrorCount => "{0} syntax error(s).",
+            Code::FileSummary => "{0}: {1} error(s), {2} warning(s).",
+            Code::FileFormatted => "Formatted {0}.",
+            Code::FilesSummary => "{0} file(s), {1} failed.",
+            Code::VariableAlreadyDeclared => {
+                "Already a variable with this name in this scope."
+            }
+            Code::MixedComparison => "comparing {0} with {1} using '{2}'.",
+            Code::ConvertRightOperand => {
+                "Did you forget to convert the right operand to a number?"
+            }
+            Code::ConvertLeftOperand => {
+                "Did you forget to convert the left operand to a number?"
+            }
+            Code::UnknownDirectiveOption => "Unknown option '{0}' in 'lox:' directive.",
+            Code::UnusedVariable => "Variable '{0}' is never read.",
+            Code::ReadBeforeAssignment => "Variable '{0}' is read before it is assigned.",
+            Code::ConstantCondition => "Condition is always {0}.",
+            Code::SelfAssignment => "Variable '{0}' is assigned to itself.",
+            Code::UnreachableAfterThrow => "Code after 'throw' is unreachable.",
+            Code::HelpMissingSemicolon => {
+                "end the statement with ';', or pass --asi to end it at the line break"
+            }
+            Code::HelpUndefinedVariable => "declare it with 'var' before using it",
+            Code::HelpStackOverflow => "pass --max-depth to allow deeper nesting",
+            Code::HelpStatementLimit => "pass a larger --max-statements to run longer",
+            Code::HelpLoopLimit => "pass a larger --max-loop-iterations to loop longer",
+            Code::HelpTimeout => "pass a larger --timeout to run longer",
+        }
+    }
+}
+
+/// Source of translated message templates.
+pub trait Catalog {
+    /// Template for `code`, or `None` to fall back to the English text.
+    fn template(&self, code: Code) -> Option<&str>;
+}
+
+impl Catalog for HashMap<Code, String> {
+    fn template(&self, code: Code) -> Option<&str> {
+        self.get(&code).map(String::as_str)
+    }
+}
+
+thread_local! {
+    static CATALOG: RefCell<Option<Box<dyn Catalog>>> = const { RefCell::new(None) };
+}
+
+/// Installs `catalog` for every message rendered on the current thread.
+///
+/// The interpreter is single-threaded, so the catalog is kept per thread
+/// rather than behind a global lock.
+#[allow(dead_code)]
+pub fn set_catalog(catalog: Box<dyn Catalog>) {
+    CATALOG.with(|current| *current.borrow_mut() = Some(catalog));
+}
+
+/// Renders `code` from the active catalog, substituting `{n}` with `args[n]`.
+pub fn message(code: Code, args: &[&dyn Display]) -> String {
+    CATALOG.with(|current| {
+        let current = current.borrow();
+        let template = current
+            .as_ref()
+            .and_then(|catalog| catalog.template(code))
+            .unwrap_or_else(|| code.english());
+        interpolate(template, args)
+    })
+}
+
+fn interpolate(template: &str, args: &[&dyn Display]) -> String {
+    let mut result = String::with_capacity(template.len());
+    let mut rest = template;
+    while let Some(open) = rest.find('{') {
+        result.push_str(&rest[..open]);
+        let after = &rest[open + 1..];
+        match after.find('}').and_then(|close| {
+            let index = after[..close].parse::<usize>().ok()?;
+            Some((close, args.get(index)?))
+        }) {
+            Some((close, arg)) => {
+                result.push_str(&arg.to_string());
+                rest = &after[close + 1..];
+            }
+            None => {
+                result.push('{');
+                rest = after;
+            }
+        }
+    }
+    result.push_str(rest);
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_translated_catalog_falls_back_to_english() {
+        let mut french = HashMap::new();
+        french.insert(
+            Code::UndefinedVariable,
+            "Variable {0} non définie.".to_string(),
+        );
+        set_catalog(Box::new(french));
+
+        assert_eq!(
+            message(Code::UndefinedVariable, &[&"x"]),
+            "Variable x non définie."
+        );
+        assert_eq!(
+            message(Code::ExpectRightBraceAfterBlock, &[]),
+            "Expect '}' after block."
+        );
+    }
+}
@@ -0,0 +1,48 @@
+//! `lox minify`: the smallest source that scans to the same tokens.
+//!
+//! Comments and whitespace are dropped; a space is kept only where two
+//! tokens would otherwise run together. `///` doc comments are kept, since
+//! `doc()` reads them, and so are line breaks in ASI mode.
+
+use crate::token::{Token, TokenType};
+
+pub fn minify(tokens: &[Token]) -> String {
+    let mut out = String::new();
+    for token in tokens {
+        match token.token_type {
+            TokenType::EOF => {}
+            TokenType::NEWLINE => {
+                if !out.is_empty() && !out.ends_with('\n') {
+                    out.push('\n');
+                }
+            }
+            _ => {
+                let text = String::from_utf8_lossy(&token.lexeme);
+                if needs_space(&out, &text) {
+                    out.push(' ');
+                }
+                out.push_str(&text);
+                // A doc comment runs to the end of its line.
+                if token.token_type == TokenType::DOC_COMMENT {
+                    out.push('\n');
+                }
+            }
+        }
+    }
+    out.trim_end_matches('\n').to_string()
+}
+
+/// Whether `next` written right after `out` would scan differently.
+fn needs_space(out: &str, next: &str) -> bool {
+    let (Some(last), Some(first)) = (out.chars().last(), next.chars().next()) else {
+        return false;
+    };
+    let word = |ch: char| ch.is_alphanumeric() || ch == '_';
+    if word(last) && word(first) {
+        return true;
+    }
+    // `/` `/` would start a comment and `"` `"` a raw string.
+    let pair = format!("{}{}", last, first);
+    matches!(pair.as_str(), "//" | "/*" | "\"\"")
+        || TokenType::all().any(|token_type| token_type.spelling().starts_with(&pair))
+}
@@ -0,0 +1,134 @@
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use crate::environment::Environment;
+use crate::interpreter::RuntimeError;
+use crate::messages::Code;
+use crate::parser::{NativeFunction, Object};
+use crate::symbol::Symbol;
+
+static NATIVES: [NativeFunction; 6] = [
+    NativeFunction {
+        name: "panic",
+        arity: 1,
+        function: panic,
+    },
+    NativeFunction {
+        name: "deepEquals",
+        arity: 2,
+        function: deep_equals,
+    },
+    NativeFunction {
+        name: "clone",
+        arity: 1,
+        function: clone,
+    },
+    NativeFunction {
+        name: "deepClone",
+        arity: 1,
+        function: deep_clone,
+    },
+    NativeFunction {
+        name: "doc",
+        arity: 1,
+        function: doc,
+    },
+    NativeFunction {
+        name: "args",
+        arity: 0,
+        function: args,
+    },
+];
+
+/// Defines every native function in the global environment.
+pub fn define_globals(environment: &mut Environment) {
+    for native in &NATIVES {
+        environment.define(Symbol::intern(native.name), Object::Native(native));
+    }
+}
+
+/// `panic(message)` aborts the script; unlike ordinary runtime errors it is
+/// reported as an intentional abort.
+fn panic(_: &Environment, arguments: &[Object]) -> Result<Object, RuntimeError> {
+    Err(RuntimeError::panic(arguments[0].to_string()))
+}
+
+/// `deepEquals(a, b)` compares lists and maps by contents; see
+/// [`Object::deep_equals`].
+fn deep_equals(_: &Environment, arguments: &[Object]) -> Result<Object, RuntimeError> {
+    Ok(Object::Boolean(arguments[0].deep_equals(&arguments[1])))
+}
+
+/// `clone(value)` copies a list or map one level deep; its elements are
+/// still shared with the original. Other values are returned as they are.
+fn clone(_: &Environment, arguments: &[Object]) -> Result<Object, RuntimeError> {
+    Ok(match &arguments[0] {
+        Object::List(list) => Object::List(Rc::new(RefCell::new(list.borrow().clone()))),
+        Object::Map(map) => Object::Map(Rc::new(RefCell::new(map.borrow().clone()))),
+        value => value.clone(),
+    })
+}
+
+/// `deepClone(value)` copies lists and maps all the way down. A structure
+/// that contains itself has no finite copy and is a runtime error.
+fn deep_clone(_: &Environment, arguments: &[Object]) -> Result<Object, RuntimeError> {
+    copy_nested(&arguments[0], &mut vec![])
+}
+
+/// `doc(name)` is the `///` comment documenting the variable `name`, or nil
+/// when it has none.
+fn doc(environment: &Environment, arguments: &[Object]) -> Result<Object, RuntimeError> {
+    let Object::String(name) = &arguments[0] else {
+        return Err(RuntimeError::native(
+            Code::DocExpectsName,
+            &[&arguments[0].type_name()],
+        ));
+    };
+    Ok(environment
+        .doc(Symbol::intern(name))?
+        .map_or(Object::Nil, |doc| Object::String(doc.into())))
+}
+
+/// `args()` is a list of the strings given after `--` on the command line.
+fn args(environment: &Environment, _: &[Object]) -> Result<Object, RuntimeError> {
+    Ok(Object::List(Rc::new(RefCell::new(
+        environment
+            .script_args()
+            .iter()
+            .map(|arg| Object::String(arg.as_str().into()))
+            .collect(),
+    ))))
+}
+
+/// `seen` holds the containers being copied further up.
+fn copy_nested(value: &Object, seen: &mut Vec<usize>) -> Result<Object, RuntimeError> {
+    let address = match value {
+        Object::List(list) => Rc::as_ptr(list) as usize,
+        Object::Map(map) => Rc::as_ptr(map) as usize,
+        _ => return Ok(value.clone()),
+    };
+    if seen.contains(&address) {
+        return Err(RuntimeError::native(
+            Code::CannotCloneCycle,
+            &[&value.type_name()],
+        ));
+    }
+    seen.push(address);
+    let copy = match value {
+        Object::List(list) => Object::List(Rc::new(RefCell::new(
+            list.borrow()
+                .iter()
+                .map(|element| copy_nested(element, seen))
+                .collect::<Result<_, _>>()?,
+        ))),
+        Object::Map(map) => Object::Map(Rc::new(RefCell::new(
+            map.borrow()
+                .iter()
+                .map(|(key, element)| Ok((key.clone(), copy_nested(element, seen)?)))
+                .collect::<Result<_, RuntimeError>>()?,
+        ))),
+        _ => unreachable!(),
+    };
+    seen.pop();
+    Ok(copy)
+}
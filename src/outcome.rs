@@ -0,0 +1,463 @@
+use std::collections::BTreeMap;
+use std::fmt::{Display, Formatter};
+use std::io::Write;
+use std::ops::Range;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex, PoisonError};
+use std::time::Duration;
+
+use crate::json::Json;
+use crate::messages::{message, Code};
+use crate::parser::Object;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Severity {
+    /// Scan or parse error; the program is rejected (exit code 65).
+    SyntaxError,
+    /// Error raised while evaluating (exit code 70).
+    RuntimeError,
+    /// Intentional abort through `panic()` (exit code 70).
+    Panic,
+    /// A tool command such as `fmt --check` rejected the file (exit code 1).
+    ToolError,
+    Warning,
+}
+
+#[derive(Debug, Clone)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    /// The catalog code of the message; `None` for panics and thrown values.
+    pub code: Option<Code>,
+    pub line: Option<usize>,
+    /// 1-based; known for syntax errors and for runtime errors raised at
+    /// a token.
+    pub column: Option<usize>,
+    /// Byte offsets of the offending text in the source, known where the
+    /// column is.
+    pub span: Option<Range<usize>>,
+    pub message: String,
+    /// Stack trace for runtime errors, innermost frame first.
+    pub trace: Vec<String>,
+    /// The imported file the diagnostic points into; `None` for the
+    /// program being run.
+    pub file: Option<Arc<SourceFile>>,
+}
+
+/// An imported file, kept with its text so that diagnostics in it are
+/// shown against it rather than against the file that imported it.
+#[derive(Debug, PartialEq)]
+pub struct SourceFile {
+    /// The path as the `import` resolved it.
+    pub path: PathBuf,
+    pub text: String,
+}
+
+impl Diagnostic {
+    /// One line of `--error-format json`; unknown fields are `null`.
+    pub fn to_json(&self, file: Option<&str>) -> Json {
+        let severity = match self.severity {
+            Severity::Warning => "warning",
+            _ => "error",
+        };
+        let number =
+            |value: Option<usize>| value.map_or(Json::Null, |n| Json::Number(n as f64));
+        Json::Object(vec![
+            (
+                "code",
+                self.code
+                    .map_or(Json::Null, |code| Json::String(code.as_str().into())),
+            ),
+            ("message", Json::String(self.message.trim_start().into())),
+            (
+                "file",
+                file.map_or(Json::Null, |file| Json::String(file.into())),
+            ),
+            ("line", number(self.line)),
+            ("column", number(self.column)),
+            (
+                "span",
+                self.span.as_ref().map_or(Json::Null, |span| {
+                    Json::Object(vec![
+                        ("start", Json::Number(span.start as f64)),
+                        ("end", Json::Number(span.end as f64)),
+                    ])
+                }),
+            ),
+            ("severity", Json::String(severity.into())),
+        ])
+    }
+
+    /// The diagnostic as `--error-format rich` prints it: a headline with
+    /// the code, the source line with the offending text underlined, then
+    /// the help note for the code, if any, and the stack trace.
+    pub fn to_rich(&self, source: &str, file: Option<&str>) -> String {
+        let severity = match self.severity {
+            Severity::Warning => "warning",
+            Severity::Panic => "panic",
+            _ => "error",
+        };
+        let mut rich = match self.code {
+            Some(code) => format!("{}[{}]: ", severity, code.as_str()),
+            None => format!("{}: ", severity),
+        };
+        rich.push_str(self.message.trim_start());
+
+        let width = self.line.map_or(0, |line| line.to_string().len());
+        let text = self
+            .line
+            .and_then(|line| source.split('\n').nth(line.checked_sub(1)?));
+        if let (Some(line), Some(text)) = (self.line, text) {
+            let text = text.trim_end_matches('\r');
+            let file = file.unwrap_or("<stdin>");
+            match self.column {
+                Some(column) => {
+                    rich +=
+                        &format!("\n{:w$}--> {}:{}:{}", "", file, line, column, w = width)
+                }
+                None => rich += &format!("\n{:w$}--> {}:{}", "", file, line, w = width),
+            }
+            rich += &format!("\n{:w$} |\n{} | {}", "", line, text, w = width);
+            if let Some(column) = self.column {
+                // Tabs are kept so the carets line up under them.
+                let indent: String = text
+                    .chars()
+                    .take(column - 1)
+                    .map(|c| if c == '\t' { '\t' } else { ' ' })
+                    .collect();
+                let underlined = self
+                    .span
+                    .as_ref()
+                    .and_then(|span| source.get(span.clone()))
+                    .map_or(0, |spanned| {
+                        spanned.split('\n').next().unwrap_or("").chars().count()
+                    });
+                let carets = "^".repeat(underlined.max(1));
+                rich += &format!("\n{:w$} | {}{}", "", indent, carets, w = width);
+            }
+        }
+        if let Some(help) = self.code.and_then(|code| code.help()) {
+            rich += &format!("\n{:w$} = help: {}", "", message(help, &[]), w = width);
+        }
+        for frame in &self.trace {
+            rich += &format!("\n{:w$} = note: {}", "", frame, w = width);
+        }
+        rich
+    }
+}
+
+impl Display for Diagnostic {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let label = match self.severity {
+            Severity::SyntaxError | Severity::ToolError => "Error: ",
+            Severity::RuntimeError => "",
+            Severity::Warning => "Warning: ",
+            Severity::Panic => "panic: ",
+        };
+        match (self.severity, self.line) {
+            // A stack trace starts with the line the error happened on.
+            (_, Some(_)) if !self.trace.is_empty() => {
+                write!(f, "{}{}", label, self.message)?
+            }
+            // Runtime errors follow jlox: the message, then the line below it.
+            (Severity::RuntimeError, Some(line)) => {
+                write!(f, "{}\n[line {}]", self.message, line)?
+            }
+            (_, Some(line)) => write!(f, "[line {}] {}{}", line, label, self.message)?,
+            (_, None) => write!(f, "{}{}", label, self.message)?,
+        }
+        for frame in &self.trace {
+            write!(f, "\n{}", frame)?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Stream {
+    Stdout,
+    Stderr,
+}
+
+/// Where a run's output and diagnostics go as they are produced, such as
+/// the terminal.
+pub trait Sink {
+    fn write(&mut self, stream: Stream, line: &str);
+    fn report(&mut self, diagnostic: &Diagnostic);
+}
+
+impl std::fmt::Debug for dyn Sink {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str("Sink")
+    }
+}
+
+/// Program output in the order it was produced, each line tagged with its
+/// stream so stdout and stderr stay interleaved correctly. Without a sink
+/// the lines are kept for the caller; with one they are passed on as they
+/// are written.
+#[derive(Debug, Default)]
+pub struct Output {
+    lines: Vec<(Stream, String)>,
+    sink: Option<Box<dyn Sink>>,
+    /// Cap on the bytes written, newlines included.
+    limit: Option<usize>,
+    bytes: usize,
+    truncated: bool,
+}
+
+impl Output {
+    pub fn with_sink(sink: Box<dyn Sink>) -> Self {
+        Output {
+            sink: Some(sink),
+            ..Output::default()
+        }
+    }
+
+    pub fn set_limit(&mut self, limit: usize) {
+        self.limit = Some(limit);
+    }
+
+    /// Once the limit is reached, the line that crosses it is cut short and
+    /// followed by a marker; everything written after that is dropped.
+    pub fn write(&mut self, stream: Stream, mut line: String) {
+        if self.truncated {
+            return;
+        }
+        self.bytes += line.len() + 1;
+        if let Some(limit) = self.limit.filter(|&limit| self.bytes > limit) {
+            let mut keep = (line.len() + 1).saturating_sub(self.bytes - limit);
+            while !line.is_char_boundary(keep) {
+                keep -= 1;
+            }
+            line.truncate(keep);
+            if !line.is_empty() {
+                self.push(stream, line);
+            }
+            let marker = message(Code::OutputTruncated, &[&limit]);
+            self.push(stream, marker);
+            self.truncated = true;
+            return;
+        }
+        self.push(stream, line);
+    }
+
+    fn push(&mut self, stream: Stream, line: String) {
+        match &mut self.sink {
+            Some(sink) => sink.write(stream, &line),
+            None => self.lines.push((stream, line)),
+        }
+    }
+
+    /// Passes `diagnostic` on to the sink. Without one, diagnostics reach
+    /// the caller through [`RunOutcome::diagnostics`] instead.
+    pub fn report(&mut self, diagnostic: &Diagnostic) {
+        if let Some(sink) = &mut self.sink {
+            sink.report(diagnostic);
+        }
+    }
+
+    /// The limit the output was cut short at, once that has happened.
+    pub fn exceeded_limit(&self) -> Option<usize> {
+        self.limit.filter(|_| self.truncated)
+    }
+
+    /// The lines kept, when there is no sink.
+    #[allow(dead_code)]
+    pub fn lines(&self) -> &[(Stream, String)] {
+        &self.lines
+    }
+}
+
+/// What a command made of one of several files, rendered for the terminal.
+#[derive(Debug, PartialEq)]
+pub struct FileReport {
+    pub lines: Vec<String>,
+    pub exit_code: i32,
+}
+
+/// Shows the reports of files processed on several threads, in file order
+/// and each one whole: a report is held until those of the files before it
+/// have been written. Shared by reference between the threads.
+pub struct DiagnosticsSink<W> {
+    state: Mutex<Reports<W>>,
+}
+
+struct Reports<W> {
+    out: W,
+    /// Index of the file whose report is written next.
+    next: usize,
+    held: BTreeMap<usize, FileReport>,
+    failed: usize,
+    exit_code: i32,
+}
+
+impl<W: Write> DiagnosticsSink<W> {
+    pub fn new(out: W) -> Self {
+        DiagnosticsSink {
+            state: Mutex::new(Reports {
+                out,
+                next: 0,
+                held: BTreeMap::new(),
+                failed: 0,
+                exit_code: 0,
+            }),
+        }
+    }
+
+    /// Hands over the report of the file at `index` in the order files are
+    /// shown in, writing it and any held reports that were waiting on it.
+    pub fn submit(&self, index: usize, report: FileReport) {
+        // A thread that panicked while writing left nothing half-updated
+        // that matters more than showing the other reports.
+        let mut guard = self.state.lock().unwrap_or_else(PoisonError::into_inner);
+        let state = &mut *guard;
+        state.held.insert(index, report);
+        while let Some(report) = state.held.remove(&state.next) {
+            for line in &report.lines {
+                // Nothing better can be done with stderr gone.
+                let _ = writeln!(state.out, "{}", line);
+            }
+            if report.exit_code != 0 {
+                state.failed += 1;
+                if state.exit_code == 0 {
+                    state.exit_code = report.exit_code;
+                }
+            }
+            state.next += 1;
+        }
+    }
+
+    /// How many files failed, and the exit code of the first that did.
+    pub fn finish(self) -> (usize, i32) {
+        let state = self
+            .state
+            .into_inner()
+            .unwrap_or_else(PoisonError::into_inner);
+        (state.failed, state.exit_code)
+    }
+}
+
+#[derive(Debug, Default, Clone)]
+pub struct Metrics {
+    pub tokens: usize,
+    pub declarations: usize,
+    /// Statements and declarations executed, loop iterations included.
+    pub executed: usize,
+    pub elapsed: Duration,
+}
+
+/// Everything a single `Lox::run` produced, left for the caller to render.
+#[derive(Debug, Default)]
+pub struct RunOutcome {
+    pub output: Output,
+    pub last_value: Option<Object>,
+    pub diagnostics: Vec<Diagnostic>,
+    pub metrics: Metrics,
+    /// The lcov report, with `--coverage`.
+    pub coverage: Option<String>,
+}
+
+impl RunOutcome {
+    pub fn has(&self, severity: Severity) -> bool {
+        self.diagnostics
+            .iter()
+            .any(|diagnostic| diagnostic.severity == severity)
+    }
+
+    /// Runtime errors take precedence over syntax errors, matching the order
+    /// in which the interpreter used to exit.
+    pub fn exit_code(&self) -> i32 {
+        if self.has(Severity::RuntimeError) || self.has(Severity::Panic) {
+            70
+        } else if self.has(Severity::SyntaxError) {
+            65
+        } else if self.has(Severity::ToolError) {
+            1
+        } else {
+            0
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+
+    use super::*;
+    use crate::Lox;
+
+    fn diagnostic(severity: Severity) -> Diagnostic {
+        Diagnostic {
+            severity,
+            code: None,
+            line: None,
+            column: None,
+            span: None,
+            message: String::new(),
+            trace: vec![],
+            file: None,
+        }
+    }
+
+    #[test]
+    fn test_exit_code_follows_the_worst_severity() {
+        let exit_code = |severities: &[Severity]| {
+            RunOutcome {
+                diagnostics: severities.iter().copied().map(diagnostic).collect(),
+                ..RunOutcome::default()
+            }
+            .exit_code()
+        };
+
+        assert_eq!(exit_code(&[]), 0);
+        assert_eq!(exit_code(&[Severity::Warning]), 0);
+        assert_eq!(exit_code(&[Severity::ToolError]), 1);
+        assert_eq!(exit_code(&[Severity::ToolError, Severity::SyntaxError]), 65);
+        assert_eq!(
+            exit_code(&[Severity::SyntaxError, Severity::RuntimeError]),
+            70
+        );
+        assert_eq!(exit_code(&[Severity::ToolError, Severity::Panic]), 70);
+    }
+
+    #[test]
+    fn test_run_outcome_keeps_the_last_value_and_counts() {
+        let outcome = Lox::new().run(
+            "evaluate",
+            "var a = 1;\nfor (c in \"ab\") a = a + 1;\na * 2;",
+        );
+
+        assert_eq!(
+            outcome.last_value.as_ref().map(Object::to_string),
+            Some("6".into())
+        );
+        assert_eq!(outcome.metrics.tokens, 22);
+        assert_eq!(outcome.metrics.declarations, 3);
+        // The declaration, the loop, its body twice and the last statement.
+        assert_eq!(outcome.metrics.executed, 5);
+        assert_eq!(outcome.exit_code(), 0);
+    }
+
+    #[test]
+    fn test_diagnostics_sink_writes_reports_in_file_order() {
+        let mut written = vec![];
+        let sink = DiagnosticsSink::new(&mut written);
+        let report = |file: usize| FileReport {
+            lines: vec![format!("{} a", file), format!("{} b", file)],
+            exit_code: [0, 65, 0, 1][file],
+        };
+
+        thread::scope(|scope| {
+            for file in (0..4).rev() {
+                let sink = &sink;
+                scope.spawn(move || sink.submit(file, report(file)));
+            }
+        });
+
+        assert_eq!(sink.finish(), (2, 65));
+        assert_eq!(
+            String::from_utf8(written).unwrap(),
+            "0 a\n0 b\n1 a\n1 b\n2 a\n2 b\n3 a\n3 b\n"
+        );
+    }
+}
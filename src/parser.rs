@@ -1,61 +1,300 @@
-use std::cell::RefCell;
+use std::cell::{Cell, RefCell};
+use std::collections::HashMap;
 use std::fmt::{Debug, Display, Formatter};
+use std::rc::Rc;
 
-use crate::parser::Expr::{Assign, Binary, Grouping, Literal, Unary, Variable};
-use crate::token::TokenType::{BANG, BANG_EQUAL, ELSE, EOF, EQUAL, EQUAL_EQUAL, FALSE, GREATER, GREATER_EQUAL, IDENTIFIER, IF, LEFT_BRACE, LEFT_PAREN, LESS, LESS_EQUAL, MINUS, NIL, NUMBER, PLUS, PRINT, RIGHT_BRACE, RIGHT_PAREN, SEMICOLON, SLASH, STAR, STRING, TRUE, VAR};
-use crate::token::{Token, TokenType};
+use crate::environment::{Environment, Slot};
+use crate::interpreter::{RuntimeError, DEFAULT_MAX_DEPTH};
+use crate::messages::{message, Code};
+use crate::parser::Expr::{
+    Assign, Binary, Call, Conditional, Grouping, Index, IndexSet, List, Literal, Map,
+    Spread, Unary, Variable,
+};
+use crate::symbol::Symbol;
+use crate::token::TokenType::{
+    CASE, CATCH, COLON, COMMA, DEFAULT, DO, DOC_COMMENT, ELLIPSIS, ELSE, EOF, EPRINT,
+    EQUAL, FALSE, FINALLY, FOR, IDENTIFIER, IF, LEFT_BRACE, LEFT_BRACKET, LEFT_PAREN,
+    NEWLINE, NIL, NUMBER, PRINT, QUESTION, RIGHT_BRACE, RIGHT_BRACKET, RIGHT_PAREN,
+    SEMICOLON, STRING, SWITCH, THROW, TRUE, TRY, VAR, WHILE,
+};
+use crate::token::{Precedence, SoftKeyword, Token, TokenType};
 use crate::Lox;
 
-pub enum Declaration<'a> {
-    VarDecl(Expr<'a>),
-    Statement(Statement<'a>),
+/// Where an expression is stored in its [`Arena`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ExprId(u32);
+
+/// The expressions of a parse. Each refers to its operands by [`ExprId`]
+/// instead of owning them, so a tree takes a few allocations however many
+/// nodes it has, and every pass over it can name a node by a copyable id.
+#[derive(Clone, Default)]
+pub struct Arena {
+    exprs: Vec<Expr>,
 }
 
-impl<'a> Display for Declaration<'a> {
-    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+impl Arena {
+    pub fn alloc(&mut self, expr: Expr) -> ExprId {
+        let id = u32::try_from(self.exprs.len()).expect("too many expressions");
+        self.exprs.push(expr);
+        ExprId(id)
+    }
+
+    /// `node` as `lox parse` prints it, its expressions looked up here.
+    pub fn show<'a, T: ?Sized>(&'a self, node: &'a T) -> Show<'a, T> {
+        Show { arena: self, node }
+    }
+}
+
+impl std::ops::Index<ExprId> for Arena {
+    type Output = Expr;
+
+    fn index(&self, id: ExprId) -> &Expr {
+        &self.exprs[id.0 as usize]
+    }
+}
+
+impl std::ops::IndexMut<ExprId> for Arena {
+    fn index_mut(&mut self, id: ExprId) -> &mut Expr {
+        &mut self.exprs[id.0 as usize]
+    }
+}
+
+/// A parsed file: its top-level declarations and the arena holding their
+/// expressions.
+#[derive(Clone)]
+pub struct Program {
+    pub arena: Arena,
+    pub declarations: Vec<Declaration>,
+}
+
+/// A node of the parse tree with the arena its expressions are in, which
+/// displays it as `lox parse` does.
+pub struct Show<'a, T: ?Sized> {
+    arena: &'a Arena,
+    node: &'a T,
+}
+
+impl<'a, T: ?Sized> Show<'a, T> {
+    /// `node`, another part of the same tree.
+    fn show<U: ?Sized>(&self, node: &'a U) -> Show<'a, U> {
+        self.arena.show(node)
+    }
+}
+
+#[derive(Clone)]
+pub enum Declaration {
+    /// `var target = initializer;`, or `const` when `constant`, and the `///`
+    /// doc comment preceding it, if any.
+    Var {
+        /// The `var` or `const` keyword.
+        keyword: Token,
+        /// A name, or a list or map pattern to unpack the initializer into.
+        target: ExprId,
+        initializer: Option<ExprId>,
+        constant: bool,
+        doc: Option<String>,
+    },
+    Statement(Statement),
+}
+
+impl Declaration {
+    /// The first source line the declaration has a token for, if any.
+    pub fn line(&self, arena: &Arena) -> Option<usize> {
         match self {
-            Declaration::VarDecl(expr) => write!(f, "{};", expr),
-            Declaration::Statement(expr) => write!(f, "{}", expr),
+            Declaration::Var { keyword, .. } => Some(keyword.line),
+            Declaration::Statement(stmt) => stmt.line(arena),
+        }
+    }
+}
+
+impl Display for Show<'_, Declaration> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self.node {
+            Declaration::Var {
+                keyword,
+                target,
+                initializer,
+                ..
+            } => {
+                let keyword = String::from_utf8_lossy(&keyword.lexeme);
+                let target = self.show(target);
+                match initializer {
+                    Some(initializer) => {
+                        let initializer = self.show(initializer);
+                        write!(f, "({} (= {} {}));", keyword, target, initializer)
+                    }
+                    None => write!(f, "({} {});", keyword, target),
+                }
+            }
+            Declaration::Statement(expr) => write!(f, "{}", self.show(expr)),
         }
     }
 }
 
-pub struct If<'a> {
-    pub condition: Box<Expr<'a>>,
-    pub then_branch: Box<Statement<'a>>,
-    pub else_branch: Option<Box<Statement<'a>>>,
+impl Display for Show<'_, [Declaration]> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        for decl in self.node {
+            write!(f, " {}", self.show(decl))?;
+        }
+        Ok(())
+    }
 }
 
-impl<'a> Display for If<'a> {
+#[derive(Clone)]
+pub struct If {
+    pub keyword: Token,
+    pub condition: ExprId,
+    pub then_branch: Box<Statement>,
+    pub else_branch: Option<Box<Statement>>,
+}
+
+impl Display for Show<'_, If> {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
-        writeln!(f, "if ({})", self.condition)?;
-        writeln!(f, "then {}", self.then_branch)?;
-        if let Some(else_stmt) = self.else_branch.as_ref() {
-            writeln!(f, "else {}", else_stmt)?;
+        writeln!(f, "if ({})", self.show(&self.node.condition))?;
+        writeln!(f, "then {}", self.show(&*self.node.then_branch))?;
+        if let Some(else_stmt) = self.node.else_branch.as_deref() {
+            writeln!(f, "else {}", self.show(else_stmt))?;
         }
         Ok(())
     }
 }
 
-pub enum Statement<'a> {
-    ExprStmt(Expr<'a>),
-    PrintStmt(Expr<'a>),
-    IfStmt(If<'a>),
-    WhileStmt(Expr<'a>),
-    Block(Vec<Declaration<'a>>),
+/// `switch (value) { case a, b: ... default: ... }`. Cases do not fall
+/// through; the first one with a value equal to the scrutinee runs.
+#[derive(Clone)]
+pub struct Switch {
+    pub keyword: Token,
+    pub value: ExprId,
+    pub cases: Vec<(Vec<ExprId>, Vec<Declaration>)>,
+    pub default: Option<Vec<Declaration>>,
+}
+
+impl Display for Show<'_, Switch> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "switch ({})", self.show(&self.node.value))?;
+        for (values, body) in &self.node.cases {
+            write!(f, " case")?;
+            for value in values {
+                write!(f, " {}", self.show(value))?;
+            }
+            write!(f, ":{}", self.show(&body[..]))?;
+        }
+        if let Some(body) = &self.node.default {
+            write!(f, " default:{}", self.show(&body[..]))?;
+        }
+        Ok(())
+    }
 }
 
+/// `try { ... } catch (name) { ... } finally { ... }`, with at least one of
+/// the `catch` and `finally` clauses.
+#[derive(Clone)]
+pub struct Try {
+    pub body: Vec<Declaration>,
+    pub catch: Option<(Symbol, Vec<Declaration>)>,
+    pub finally: Option<Vec<Declaration>>,
+}
 
-impl<'a> Display for Statement<'a> {
+impl Display for Show<'_, Try> {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "try{}", self.show(&self.node.body[..]))?;
+        if let Some((name, body)) = &self.node.catch {
+            write!(f, " catch ({}){}", name, self.show(&body[..]))?;
+        }
+        if let Some(body) = &self.node.finally {
+            write!(f, " finally{}", self.show(&body[..]))?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Clone)]
+pub enum Statement {
+    ExprStmt(ExprId),
+    PrintStmt(ExprId),
+    EPrintStmt(ExprId),
+    IfStmt(If),
+    #[allow(dead_code)]
+    WhileStmt(ExprId),
+    Block(Vec<Declaration>),
+    Switch(Switch),
+    /// `for (variable in iterable) body`.
+    ForIn {
+        keyword: Token,
+        variable: Symbol,
+        iterable: ExprId,
+        body: Box<Statement>,
+    },
+    /// `import "path";` runs another file once, in the global scope.
+    Import {
+        keyword: Token,
+        path: String,
+    },
+    Try(Try),
+    Throw {
+        keyword: Token,
+        value: ExprId,
+    },
+    /// `do body while (condition);` runs `body` at least once.
+    DoWhile {
+        body: Box<Statement>,
+        condition: ExprId,
+    },
+}
+
+impl Statement {
+    /// The first source line the statement has a token for; an empty block
+    /// has none.
+    pub fn line(&self, arena: &Arena) -> Option<usize> {
+        let first_line = |decls: &[Declaration]| decls.iter().find_map(|d| d.line(arena));
         match self {
-            Statement::ExprStmt(expr) => write!(f, "{};", expr),
-            Statement::PrintStmt(expr) => write!(f, "print {};", expr),
-            Statement::IfStmt(expr) => write!(f, "{}", expr),
-            Statement::WhileStmt(expr) => write!(f, "{}", expr),
+            Statement::ExprStmt(expr)
+            | Statement::PrintStmt(expr)
+            | Statement::EPrintStmt(expr)
+            | Statement::WhileStmt(expr) => arena[*expr].line(arena),
+            Statement::IfStmt(if_) => Some(if_.keyword.line),
+            Statement::Block(decls) => first_line(decls),
+            Statement::Switch(switch) => Some(switch.keyword.line),
+            Statement::ForIn { keyword, .. }
+            | Statement::Import { keyword, .. }
+            | Statement::Throw { keyword, .. } => Some(keyword.line),
+            Statement::Try(try_) => first_line(&try_.body),
+            Statement::DoWhile { body, .. } => body.line(arena),
+        }
+    }
+}
+
+impl Display for Show<'_, Statement> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self.node {
+            Statement::ExprStmt(expr) => write!(f, "{};", self.show(expr)),
+            Statement::PrintStmt(expr) => write!(f, "print {};", self.show(expr)),
+            Statement::EPrintStmt(expr) => write!(f, "eprint {};", self.show(expr)),
+            Statement::IfStmt(expr) => write!(f, "{}", self.show(expr)),
+            Statement::Switch(switch) => write!(f, "{}", self.show(switch)),
+            Statement::ForIn {
+                variable,
+                iterable,
+                body,
+                ..
+            } => write!(
+                f,
+                "for ({} in {}) {}",
+                variable,
+                self.show(iterable),
+                self.show(&**body)
+            ),
+            Statement::Import { path, .. } => write!(f, "import \"{}\";", path),
+            Statement::Try(try_) => write!(f, "{}", self.show(try_)),
+            Statement::Throw { value, .. } => write!(f, "throw {};", self.show(value)),
+            Statement::DoWhile { body, condition } => {
+                let (body, condition) = (self.show(&**body), self.show(condition));
+                write!(f, "do {} while ({});", body, condition)
+            }
+            Statement::WhileStmt(expr) => write!(f, "{}", self.show(expr)),
             Statement::Block(exprs) => {
                 for expr in exprs {
-                    write!(f, " {{ {} }}", expr)?;
+                    write!(f, " {{ {} }}", self.show(expr))?;
                 }
                 Ok(())
             }
@@ -63,34 +302,149 @@ impl<'a> Display for Statement<'a> {
     }
 }
 
-pub enum Expr<'a> {
+#[derive(Clone)]
+pub enum Expr {
     Binary {
-        left: Box<Expr<'a>>,
-        operator: &'a Token<'a>,
-        right: Box<Expr<'a>>,
+        left: ExprId,
+        operator: Token,
+        right: ExprId,
     },
     Grouping {
-        expression: Box<Expr<'a>>,
+        expression: ExprId,
     },
+    /// `token` is the literal as written; `None` for values made up by the
+    /// parser or the interpreter.
     Literal {
         value: Object,
+        token: Option<Token>,
     },
     Unary {
-        operator: &'a Token<'a>,
-        right: Box<Expr<'a>>,
+        operator: Token,
+        right: ExprId,
     },
+    /// `slot` is where the resolver found the variable, or `None` for a
+    /// global.
     Variable {
-        identifier: String,
+        identifier: Symbol,
+        line: usize,
+        slot: Option<Slot>,
     },
     Assign {
-        identifier: String,
-        value: Box<Expr<'a>>,
+        identifier: Symbol,
+        /// Line of the assigned name.
+        line: usize,
+        value: ExprId,
+        slot: Option<Slot>,
+    },
+    Conditional {
+        condition: ExprId,
+        then_branch: ExprId,
+        else_branch: ExprId,
+    },
+    Call {
+        callee: ExprId,
+        paren: Token,
+        arguments: Vec<ExprId>,
+    },
+    List {
+        elements: Vec<ExprId>,
+    },
+    Map {
+        brace: Token,
+        entries: Vec<(ExprId, ExprId)>,
+    },
+    Index {
+        object: ExprId,
+        bracket: Token,
+        index: ExprId,
+    },
+    /// `object[index] = value`; `operator` is `=` or a compound assignment.
+    IndexSet {
+        object: ExprId,
+        bracket: Token,
+        index: ExprId,
+        operator: Token,
+        value: ExprId,
+    },
+    /// `...value` inside an argument list or list literal, splicing in the
+    /// elements of a list.
+    Spread {
+        ellipsis: Token,
+        value: ExprId,
     },
 }
 
-impl<'a> Display for Expr<'a> {
-    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+impl Expr {
+    /// The first source line the expression has a token for; literals
+    /// made up by the parser or the interpreter have none.
+    pub fn line(&self, arena: &Arena) -> Option<usize> {
+        let line = |id: &ExprId| arena[*id].line(arena);
+        match self {
+            Binary { left, operator, .. } => line(left).or(Some(operator.line)),
+            Grouping { expression } => line(expression),
+            Literal { token, .. } => token.as_ref().map(|token| token.line),
+            Unary { operator, .. } => Some(operator.line),
+            Variable { line, .. } => Some(*line),
+            Assign { line, .. } => Some(*line),
+            Conditional { condition, .. } => line(condition),
+            Call { callee, paren, .. } => line(callee).or(Some(paren.line)),
+            List { elements } => elements.iter().find_map(line),
+            Map { brace, .. } => Some(brace.line),
+            Index {
+                object, bracket, ..
+            }
+            | IndexSet {
+                object, bracket, ..
+            } => line(object).or(Some(bracket.line)),
+            Spread { ellipsis, .. } => Some(ellipsis.line),
+        }
+    }
+
+    /// The operands of the expression, in source order.
+    pub fn children(&self) -> Vec<ExprId> {
         match self {
+            Literal { .. } | Variable { .. } => vec![],
+            Grouping { expression: value }
+            | Unary { right: value, .. }
+            | Assign { value, .. }
+            | Spread { value, .. } => vec![*value],
+            Binary { left, right, .. } => vec![*left, *right],
+            Conditional {
+                condition,
+                then_branch,
+                else_branch,
+            } => vec![*condition, *then_branch, *else_branch],
+            Call {
+                callee, arguments, ..
+            } => [*callee]
+                .into_iter()
+                .chain(arguments.iter().copied())
+                .collect(),
+            List { elements } => elements.clone(),
+            Map { entries, .. } => entries
+                .iter()
+                .flat_map(|&(key, value)| [key, value])
+                .collect(),
+            Index { object, index, .. } => vec![*object, *index],
+            IndexSet {
+                object,
+                index,
+                value,
+                ..
+            } => vec![*object, *index, *value],
+        }
+    }
+}
+
+impl Display for Show<'_, ExprId> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.show(&self.arena[*self.node]))
+    }
+}
+
+impl Display for Show<'_, Expr> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self.node {
             Binary {
                 left,
                 operator,
@@ -99,72 +453,355 @@ impl<'a> Display for Expr<'a> {
                 write!(
                     f,
                     "({} {} {})",
-                    String::from_utf8_lossy(operator.lexeme),
-                    left,
-                    right
+                    String::from_utf8_lossy(&operator.lexeme),
+                    self.show(left),
+                    self.show(right)
                 )
             }
             Grouping { expression } => {
-                write!(f, "(group {})", expression)
-            }
-            Literal { value } => {
-                write!(f, "{}", value)
+                write!(f, "(group {})", self.show(expression))
             }
+            Literal { value, .. } => write!(f, "{}", value.to_literal()),
             Unary { operator, right } => {
                 write!(
                     f,
                     "({} {})",
-                    String::from_utf8_lossy(operator.lexeme),
-                    right
+                    String::from_utf8_lossy(&operator.lexeme),
+                    self.show(right)
                 )
             }
-            Variable { identifier: value } => write!(f, "variable {}", value),
-            Assign { identifier, value } => {
-                write!(f, "variable {:?} = {}", identifier, value)
+            Variable {
+                identifier: value, ..
+            } => write!(f, "variable {}", value),
+            Assign {
+                identifier, value, ..
+            } => {
+                write!(f, "variable {:?} = {}", identifier, self.show(value))
+            }
+            Conditional {
+                condition,
+                then_branch,
+                else_branch,
+            } => write!(
+                f,
+                "(?: {} {} {})",
+                self.show(condition),
+                self.show(then_branch),
+                self.show(else_branch)
+            ),
+            Call {
+                callee, arguments, ..
+            } => {
+                write!(f, "(call {}", self.show(callee))?;
+                for argument in arguments {
+                    write!(f, " {}", self.show(argument))?;
+                }
+                write!(f, ")")
+            }
+            List { elements } => {
+                write!(f, "(list")?;
+                for element in elements {
+                    write!(f, " {}", self.show(element))?;
+                }
+                write!(f, ")")
             }
+            Map { entries, .. } => {
+                write!(f, "(map")?;
+                for (key, value) in entries {
+                    write!(f, " {} {}", self.show(key), self.show(value))?;
+                }
+                write!(f, ")")
+            }
+            Index { object, index, .. } => {
+                write!(f, "(index {} {})", self.show(object), self.show(index))
+            }
+            Spread { value, .. } => write!(f, "(... {})", self.show(value)),
+            IndexSet {
+                object,
+                index,
+                operator,
+                value,
+                ..
+            } => write!(
+                f,
+                "(index{} {} {} {})",
+                String::from_utf8_lossy(&operator.lexeme),
+                self.show(object),
+                self.show(index),
+                self.show(value)
+            ),
         }
     }
 }
 
+#[derive(Clone, Copy)]
+pub struct NativeFunction {
+    pub name: &'static str,
+    pub arity: usize,
+    pub function: fn(&Environment, &[Object]) -> Result<Object, RuntimeError>,
+}
+
+/// Shared storage behind a list value.
+pub type ListRef = Rc<RefCell<Vec<Object>>>;
+
+/// Shared storage behind a map value.
+pub type MapRef = Rc<RefCell<HashMap<MapKey, Object>>>;
+
+/// A hashable map key. Only strings and numbers can be keys; a number is
+/// hashed by its bit pattern, with `-0` folded into `0` so that keys that
+/// compare equal also hash equal.
+#[derive(Clone, PartialEq, Eq, Hash)]
+pub enum MapKey {
+    Number(u64),
+    String(Rc<str>),
+}
+
+impl MapKey {
+    /// The keys of `map` with numbers first, then strings, so that printing
+    /// and iterating a map are deterministic.
+    pub fn sorted(map: &HashMap<MapKey, Object>) -> Vec<MapKey> {
+        let mut keys: Vec<MapKey> = map.keys().cloned().collect();
+        keys.sort_by(|a, b| match (a, b) {
+            (MapKey::Number(a), MapKey::Number(b)) => {
+                f64::from_bits(*a).total_cmp(&f64::from_bits(*b))
+            }
+            (MapKey::String(a), MapKey::String(b)) => a.cmp(b),
+            (MapKey::Number(_), _) => std::cmp::Ordering::Less,
+            _ => std::cmp::Ordering::Greater,
+        });
+        keys
+    }
+
+    pub fn to_object(&self) -> Object {
+        match self {
+            MapKey::Number(bits) => Object::Number(f64::from_bits(*bits)),
+            MapKey::String(s) => Object::String(s.clone()),
+        }
+    }
+}
+
+/// A runtime value. Every variant is at most two words of payload, and
+/// strings share their text, so copying a value never allocates.
 #[derive(Clone)]
 pub enum Object {
-    Number(f32),
-    String(String),
+    Number(f64),
+    String(Rc<str>),
     Boolean(bool),
     Nil,
+    Native(&'static NativeFunction),
+    /// Lists are shared by reference, so every alias sees element writes.
+    List(ListRef),
+    /// Maps are shared by reference like lists.
+    Map(MapRef),
+}
+
+impl Object {
+    /// The value as the parse tree shows a literal: numbers as `tokenize`
+    /// does, integral ones with a trailing `.0`.
+    pub fn to_literal(&self) -> String {
+        match self {
+            Object::Number(n) if n.fract() == 0.0 => format!("{:.1}", n),
+            value => value.to_string(),
+        }
+    }
+
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            Object::Number(_) => "number",
+            Object::String(_) => "string",
+            Object::Boolean(_) => "boolean",
+            Object::Nil => "nil",
+            Object::Native(_) => "function",
+            Object::List(_) => "list",
+            Object::Map(_) => "map",
+        }
+    }
+
+    /// The key this value indexes a map with, if it can be one.
+    pub fn to_key(&self) -> Option<MapKey> {
+        match self {
+            Object::Number(n) if *n == 0.0 => Some(MapKey::Number(0.0f64.to_bits())),
+            Object::Number(n) => Some(MapKey::Number(n.to_bits())),
+            Object::String(s) => Some(MapKey::String(s.clone())),
+            _ => None,
+        }
+    }
+
+    /// Structural equality: lists and maps compare by contents rather than
+    /// by reference, unlike `==`. Cyclic structures compare equal when they
+    /// have the same shape.
+    pub fn deep_equals(&self, other: &Object) -> bool {
+        self.deep_equals_seen(other, &mut vec![])
+    }
+
+    /// `seen` holds the container pairs already being compared further up;
+    /// meeting one again means the cycle matched so far.
+    fn deep_equals_seen(&self, other: &Object, seen: &mut Vec<(usize, usize)>) -> bool {
+        let pair = match (self, other) {
+            (Object::List(a), Object::List(b)) => {
+                (Rc::as_ptr(a) as usize, Rc::as_ptr(b) as usize)
+            }
+            (Object::Map(a), Object::Map(b)) => {
+                (Rc::as_ptr(a) as usize, Rc::as_ptr(b) as usize)
+            }
+            (Object::Number(a), Object::Number(b)) => return a == b,
+            (Object::String(a), Object::String(b)) => return a == b,
+            (Object::Boolean(a), Object::Boolean(b)) => return a == b,
+            (Object::Nil, Object::Nil) => return true,
+            (Object::Native(a), Object::Native(b)) => return a.name == b.name,
+            _ => return false,
+        };
+        if pair.0 == pair.1 || seen.contains(&pair) {
+            return true;
+        }
+        seen.push(pair);
+        let equal = match (self, other) {
+            (Object::List(a), Object::List(b)) => {
+                let (a, b) = (a.borrow(), b.borrow());
+                a.len() == b.len()
+                    && a.iter()
+                        .zip(b.iter())
+                        .all(|(a, b)| a.deep_equals_seen(b, seen))
+            }
+            (Object::Map(a), Object::Map(b)) => {
+                let (a, b) = (a.borrow(), b.borrow());
+                a.len() == b.len()
+                    && a.iter().all(|(key, a)| {
+                        b.get(key).is_some_and(|b| a.deep_equals_seen(b, seen))
+                    })
+            }
+            _ => unreachable!(),
+        };
+        seen.pop();
+        equal
+    }
+
+    /// How the value prints inside a collection: strings are quoted.
+    fn fmt_element(
+        &self,
+        f: &mut Formatter<'_>,
+        seen: &mut Vec<usize>,
+    ) -> std::fmt::Result {
+        match self {
+            Object::String(s) => write!(f, "{:?}", s),
+            _ => self.fmt_nested(f, seen),
+        }
+    }
+
+    /// `seen` holds the containers being printed further up; a container
+    /// that contains itself prints as `[...]` or `{...}` the second time.
+    fn fmt_nested(
+        &self,
+        f: &mut Formatter<'_>,
+        seen: &mut Vec<usize>,
+    ) -> std::fmt::Result {
+        let address = match self {
+            Object::List(list) => Rc::as_ptr(list) as usize,
+            Object::Map(map) => Rc::as_ptr(map) as usize,
+            _ => return write!(f, "{}", self),
+        };
+        if seen.contains(&address) {
+            return match self {
+                Object::List(_) => write!(f, "[...]"),
+                _ => write!(f, "{{...}}"),
+            };
+        }
+        seen.push(address);
+        match self {
+            Object::List(elements) => {
+                write!(f, "[")?;
+                for (i, element) in elements.borrow().iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    element.fmt_element(f, seen)?;
+                }
+                write!(f, "]")?;
+            }
+            Object::Map(entries) => {
+                let entries = entries.borrow();
+                write!(f, "{{")?;
+                for (i, key) in MapKey::sorted(&entries).iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    key.to_object().fmt_element(f, seen)?;
+                    write!(f, ": ")?;
+                    entries[key].fmt_element(f, seen)?;
+                }
+                write!(f, "}}")?;
+            }
+            _ => unreachable!(),
+        }
+        seen.pop();
+        Ok(())
+    }
 }
 
 impl Display for Object {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
         match self {
             Object::Nil => write!(f, "nil"),
-            Object::Number(n) => {
-                if n.fract() == 0.0 {
-                    write!(f, "{:.1}", n)
-                } else {
-                    write!(f, "{}", n)
-                }
-            }
+            // As jlox prints numbers: integral ones without a trailing `.0`.
+            Object::Number(n) => write!(f, "{}", n),
             Object::String(s) => write!(f, "{}", s),
             Object::Boolean(b) => write!(f, "{}", b),
+            Object::Native(_) => write!(f, "<native fn>"),
+            Object::List(_) | Object::Map(_) => self.fmt_nested(f, &mut vec![]),
         }
     }
 }
 
 impl Debug for Object {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
-        match self {
-            Object::Number(n) => {
-                write!(f, "{}", n)
-            }
-            _ => write!(f, "{}", self),
+        write!(f, "{}", self)
+    }
+}
+
+/// How deeply statements and expressions may nest. Parsing recurses on the
+/// Rust stack, so anything deeper is reported instead of overflowing it.
+const MAX_NESTING: usize = DEFAULT_MAX_DEPTH;
+
+/// How many operators a chain like `1 + 2 + 3` may have. The chain parses
+/// in a loop, but the tree it builds nests one level per operator, which
+/// the formatter and other tree walks recurse on. Such a level takes far
+/// less stack than a level of parentheses does to parse.
+const MAX_CHAIN: usize = MAX_NESTING * 8;
+
+/// A syntax error: the token it was found at and the catalog message.
+pub struct ParseError {
+    pub(crate) token: Token,
+    pub(crate) code: Code,
+    args: Vec<String>,
+}
+
+impl ParseError {
+    fn new(token: &Token, code: Code, args: &[&dyn Display]) -> Self {
+        ParseError {
+            token: token.clone(),
+            code,
+            args: args.iter().map(|arg| arg.to_string()).collect(),
         }
     }
 }
 
+impl Display for ParseError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let args: Vec<&dyn Display> =
+            self.args.iter().map(|arg| arg as &dyn Display).collect();
+        write!(f, "{}", message(self.code, &args))
+    }
+}
+
+type ParseResult<T> = Result<T, ParseError>;
+
 pub(crate) struct Parser<'a, 'b> {
-    tokens: &'a Vec<Token<'a>>,
+    tokens: &'a Vec<Token>,
     current: RefCell<usize>,
+    /// Statements and expressions being parsed, innermost included.
+    nesting: Cell<usize>,
+    /// Where the expressions parsed so far are allocated.
+    arena: RefCell<Arena>,
     lox: &'b Lox,
 }
 
@@ -173,20 +810,36 @@ impl<'a, 'b> Parser<'a, 'b> {
         Parser {
             tokens,
             current: RefCell::new(0),
+            nesting: Cell::new(0),
+            arena: RefCell::new(Arena::default()),
             lox,
         }
     }
 
+    fn alloc(&self, expr: Expr) -> ExprId {
+        self.arena.borrow_mut().alloc(expr)
+    }
+
     fn is_at_end(&self) -> bool {
-        if self.tokens[*self.current.borrow()].token_type == EOF {
-            return true;
+        self.peek().token_type == EOF
+    }
+
+    /// Index of the first token at or after `from` that is not a line break
+    /// or doc comment. Line breaks only exist in ASI mode and are invisible
+    /// to the grammar except where a statement may end; doc comments are
+    /// only read back by [`Parser::doc_comment`].
+    fn skip_newlines(&self, from: usize) -> usize {
+        let mut index = from.min(self.tokens.len() - 1);
+        while matches!(self.tokens[index].token_type, NEWLINE | DOC_COMMENT) {
+            index += 1;
         }
-        false
+        index
     }
 
-    fn advance(&self) -> &'a Token<'a> {
-        if !self.is_at_end() {
-            *self.current.borrow_mut() += 1;
+    fn advance(&self) -> &'a Token {
+        let next = self.skip_newlines(*self.current.borrow());
+        if self.tokens[next].token_type != EOF {
+            *self.current.borrow_mut() = next + 1;
         }
         self.previous()
     }
@@ -198,183 +851,684 @@ impl<'a, 'b> Parser<'a, 'b> {
         self.peek().token_type == token_type
     }
 
-    fn peek(&self) -> &'a Token<'a> {
-        &self.tokens[*self.current.borrow()]
+    fn peek(&self) -> &'a Token {
+        &self.tokens[self.skip_newlines(*self.current.borrow())]
+    }
+
+    fn peek_next(&self) -> &'a Token {
+        let next = self.skip_newlines(*self.current.borrow());
+        &self.tokens[self.skip_newlines(next + 1)]
     }
 
-    fn previous(&self) -> &'a Token<'a> {
+    /// Consumes a soft keyword: with extensions enabled, the current
+    /// identifier spells `keyword` and the next token is one of `followed_by`,
+    /// i.e. a position where reading the name as a plain identifier could
+    /// not parse (`const x`, but not `const = 1`).
+    fn match_soft_keyword(
+        &self,
+        keyword: SoftKeyword,
+        followed_by: &[TokenType],
+    ) -> bool {
+        if !self.lox.extensions
+            || !self.check(IDENTIFIER)
+            || self.peek().lexeme != keyword.as_str().as_bytes()
+            || !followed_by.contains(&self.peek_next().token_type)
+        {
+            return false;
+        }
+        self.advance();
+        true
+    }
+
+    fn previous(&self) -> &'a Token {
         &self.tokens[*self.current.borrow() - 1]
     }
 
-    fn consume(&self, token_type: TokenType, message: String) {
+    fn consume(
+        &self,
+        token_type: TokenType,
+        code: Code,
+        args: &[&dyn Display],
+    ) -> ParseResult<&'a Token> {
         if self.check(token_type) {
-            self.advance();
-            return;
+            return Ok(self.advance());
         }
-        self.lox.error(self.peek(), message)
+        Err(ParseError::new(self.peek(), code, args))
     }
 
-    pub(crate) fn parse(&self) -> Vec<Declaration> {
-        let mut stmts = vec![];
+    /// Ends a statement with `;`. In ASI mode a line break, a closing `}` or
+    /// the end of input also ends it, but only once the expression parser
+    /// has stopped: a next line that can continue the statement (`+ 1`,
+    /// `(x)`, `? a : b`) still does.
+    fn end_statement(&self) -> ParseResult<()> {
+        if self.match_token(&[SEMICOLON]) {
+            return Ok(());
+        }
+        // A doc comment runs to the end of its line.
+        let at_line_break = matches!(
+            self.tokens[*self.current.borrow()].token_type,
+            NEWLINE | DOC_COMMENT
+        );
+        if self.lox.asi && (at_line_break || self.check(RIGHT_BRACE) || self.is_at_end())
+        {
+            return Ok(());
+        }
+        Err(ParseError::new(self.peek(), Code::MissingSemicolon, &[]))
+    }
+
+    /// Records an error that leaves the construct around it intact, so
+    /// parsing carries on without unwinding.
+    fn report(&self, error: ParseError) {
+        let args: Vec<&dyn Display> =
+            error.args.iter().map(|arg| arg as &dyn Display).collect();
+        self.lox.error(&error.token, error.code, &args)
+    }
+
+    /// Parses the whole program. Each declaration that fails to parse is
+    /// reported and left out, and parsing resumes at the next statement, so
+    /// one run finds every syntax error.
+    pub(crate) fn parse(&self) -> Program {
+        let mut declarations = vec![];
         while !self.is_at_end() {
-            stmts.push(self.declaration());
+            declarations.extend(self.declaration());
+        }
+        Program {
+            arena: self.arena.take(),
+            declarations,
         }
-        stmts
     }
 
-    fn block(&self) -> Vec<Declaration> {
+    /// Hands each top-level declaration to `on_decl` as soon as it is
+    /// complete, with the arena holding its expressions. The arena is
+    /// emptied for the next declaration, so callers that do not need the
+    /// whole program never hold more than one declaration's AST at a time.
+    pub(crate) fn parse_streaming(
+        &self,
+        mut on_decl: impl FnMut(Declaration, &mut Arena),
+    ) {
+        while !self.is_at_end() {
+            if let Some(declaration) = self.declaration() {
+                on_decl(declaration, &mut self.arena.borrow_mut());
+            }
+            self.arena.borrow_mut().exprs.clear();
+        }
+    }
+
+    /// Skips to where the next statement probably begins: past a `;`, or
+    /// before a keyword that starts a statement. Inside a block, a `}` ends
+    /// the skipping too and is left for the block to close.
+    fn synchronize(&self) {
+        let in_block = self.nesting.get() > 0;
+        if !(in_block && self.check(RIGHT_BRACE)) {
+            self.advance();
+        }
+        while !self.is_at_end() {
+            if self.previous().token_type == SEMICOLON {
+                return;
+            }
+            match self.peek().token_type {
+                VAR | PRINT | EPRINT | IF | SWITCH | CASE | DEFAULT | FOR | DO | TRY
+                | THROW => return,
+                RIGHT_BRACE if in_block => return,
+                _ => {}
+            }
+            self.advance();
+        }
+    }
+
+    /// Runs `parse` one level deeper. Past [`MAX_NESTING`] the construct is
+    /// an error instead.
+    fn nested<T>(&self, parse: impl FnOnce() -> ParseResult<T>) -> ParseResult<T> {
+        if self.nesting.get() >= MAX_NESTING {
+            return Err(ParseError::new(self.peek(), Code::NestedTooDeeply, &[]));
+        }
+        self.nesting.set(self.nesting.get() + 1);
+        let result = parse();
+        self.nesting.set(self.nesting.get() - 1);
+        result
+    }
+
+    /// Counts one more operator of a chain, which past [`MAX_CHAIN`] is an
+    /// error.
+    fn link(&self, links: &mut usize) -> ParseResult<()> {
+        *links += 1;
+        if *links > MAX_CHAIN {
+            return Err(ParseError::new(self.previous(), Code::NestedTooDeeply, &[]));
+        }
+        Ok(())
+    }
+
+    fn block(&self) -> ParseResult<Vec<Declaration>> {
         let mut stmts = vec![];
         while !self.is_at_end() && !self.check(RIGHT_BRACE) {
-            stmts.push(self.declaration());
+            stmts.extend(self.declaration());
         }
-        self.consume(RIGHT_BRACE, "Expect '}' after block.".into());
-        stmts
+        self.consume(RIGHT_BRACE, Code::ExpectRightBraceAfterBlock, &[])?;
+        Ok(stmts)
     }
 
-    fn declaration(&self) -> Declaration {
-        if self.match_token(&[VAR]) {
-            return Declaration::VarDecl(self.vardecl());
+    /// The lines of the `///` comments directly before the next token.
+    fn doc_comment(&self) -> Option<String> {
+        let start = self.skip_newlines(*self.current.borrow());
+        let lines: Vec<&str> = self.tokens[..start]
+            .iter()
+            .rev()
+            .take_while(|token| matches!(token.token_type, NEWLINE | DOC_COMMENT))
+            .filter(|token| token.token_type == DOC_COMMENT)
+            .map(|token| token.literal.as_str())
+            .collect();
+        let lines: Vec<&str> = lines.into_iter().rev().collect();
+        (!lines.is_empty()).then(|| lines.join("\n"))
+    }
+
+    /// A declaration, or `None` if it did not parse: the error is reported
+    /// and the tokens up to the next statement are skipped.
+    fn declaration(&self) -> Option<Declaration> {
+        match self.unsynchronized_declaration() {
+            Ok(declaration) => Some(declaration),
+            Err(error) => {
+                self.report(error);
+                self.synchronize();
+                None
+            }
         }
-        return Declaration::Statement(self.statement());
     }
 
-    fn vardecl(&self) -> Expr {
-        let var_operator = self.previous();
-        let primary = self.primary();
-        return if !self.match_token(&[EQUAL]) {
-            self.consume(SEMICOLON, "Error: missing semicolon at end".into());
-            Unary {
-                operator: var_operator,
-                right: Box::new(primary),
+    fn unsynchronized_declaration(&self) -> ParseResult<Declaration> {
+        let doc = self.doc_comment();
+        if self.match_token(&[VAR]) {
+            return self.vardecl(false, doc);
+        }
+        if self.match_soft_keyword(SoftKeyword::Const, &[IDENTIFIER]) {
+            if self.peek_next().token_type != EQUAL {
+                self.report(ParseError::new(
+                    self.peek(),
+                    Code::ExpectConstInitializer,
+                    &[],
+                ));
             }
+            return self.vardecl(true, doc);
+        }
+        Ok(Declaration::Statement(self.statement()?))
+    }
+
+    /// The rest of a declaration whose `var` or `const` was just consumed.
+    fn vardecl(&self, constant: bool, doc: Option<String>) -> ParseResult<Declaration> {
+        let keyword = self.previous();
+        let target = self.binding_pattern()?;
+        let initializer = if self.match_token(&[EQUAL]) {
+            Some(self.expression()?)
         } else {
-            let operator = self.previous();
-            let expr = self.expression();
-            self.consume(SEMICOLON, "Error: missing semicolon at end".into());
-            Unary {
-                operator: var_operator,
-                right: Box::new(Binary {
-                    left: Box::new(primary),
-                    operator,
-                    right: Box::new(expr),
-                }),
+            if !matches!(self.arena.borrow()[target], Variable { .. }) {
+                self.report(ParseError::new(
+                    self.peek(),
+                    Code::ExpectPatternInitializer,
+                    &[],
+                ));
             }
+            None
+        };
+        self.end_statement()?;
+        Ok(Declaration::Var {
+            keyword: keyword.clone(),
+            target,
+            initializer,
+            constant,
+            doc,
+        })
+    }
+
+    /// The target of a `var`: a name, `[a, b]` to unpack a list by position
+    /// or `{x, y}` to unpack a map by key. Patterns are list and map
+    /// expressions whose elements are the variables to bind.
+    fn binding_pattern(&self) -> ParseResult<ExprId> {
+        let variable = |(identifier, line)| {
+            self.alloc(Variable {
+                identifier,
+                line,
+                slot: None,
+            })
         };
+        if self.match_token(&[LEFT_BRACKET]) {
+            let elements = self
+                .binding_names(RIGHT_BRACKET)?
+                .into_iter()
+                .map(variable)
+                .collect();
+            return Ok(self.alloc(List { elements }));
+        }
+        if self.match_token(&[LEFT_BRACE]) {
+            let brace = self.previous();
+            let entries = self
+                .binding_names(RIGHT_BRACE)?
+                .into_iter()
+                .map(|(identifier, line)| {
+                    let key = self.alloc(Literal {
+                        value: Object::String(identifier.text()),
+                        token: None,
+                    });
+                    (key, variable((identifier, line)))
+                })
+                .collect();
+            return Ok(self.alloc(Map {
+                brace: brace.clone(),
+                entries,
+            }));
+        }
+        let name = self.consume(IDENTIFIER, Code::ExpectVariableName, &[])?;
+        Ok(variable((
+            Symbol::intern(&String::from_utf8_lossy(&name.lexeme)),
+            name.line,
+        )))
     }
 
-    fn if_(&self) -> If {
-        self.consume(LEFT_PAREN, "Expect '(' after 'if'.".into());
-        let expr = self.expression();
-        self.consume(RIGHT_PAREN, "Expect ')' after if condition.".into());
-        let then_branch = self.statement();
+    /// Comma-separated names up to and including `closing`, with their lines.
+    fn binding_names(&self, closing: TokenType) -> ParseResult<Vec<(Symbol, usize)>> {
+        let mut names = vec![];
+        while !self.is_at_end() && !self.check(closing) {
+            let name = self.consume(IDENTIFIER, Code::ExpectBindingName, &[])?;
+            names.push((
+                Symbol::intern(&String::from_utf8_lossy(&name.lexeme)),
+                name.line,
+            ));
+            if !self.match_token(&[COMMA]) {
+                break;
+            }
+        }
+        self.consume(closing, Code::ExpectClosingPattern, &[&closing.spelling()])?;
+        Ok(names)
+    }
+
+    fn if_(&self) -> ParseResult<If> {
+        let keyword = self.previous();
+        self.consume(LEFT_PAREN, Code::ExpectLeftParenAfterIf, &[])?;
+        let expr = self.expression()?;
+        self.consume(RIGHT_PAREN, Code::ExpectRightParenAfterIf, &[])?;
+        let then_branch = self.statement()?;
         let else_branch: Option<Box<Statement>> = if self.match_token(&[ELSE]) {
-            Some(Box::new(self.statement()))
-        } else { None };
-        If {
-            condition: Box::new(expr),
+            Some(Box::new(self.statement()?))
+        } else {
+            None
+        };
+        Ok(If {
+            keyword: keyword.clone(),
+            condition: expr,
             then_branch: Box::new(then_branch),
             else_branch,
+        })
+    }
+
+    fn switch(&self) -> ParseResult<Switch> {
+        let keyword = self.previous();
+        self.consume(LEFT_PAREN, Code::ExpectLeftParenAfterSwitch, &[])?;
+        let value = self.expression()?;
+        self.consume(RIGHT_PAREN, Code::ExpectRightParenAfterSwitch, &[])?;
+        self.consume(LEFT_BRACE, Code::ExpectLeftBraceBeforeCases, &[])?;
+
+        let mut cases = vec![];
+        let mut default = None;
+        while !self.is_at_end() && !self.check(RIGHT_BRACE) {
+            let values = if self.match_token(&[CASE]) {
+                let values = self.elements(COLON)?;
+                self.consume(COLON, Code::ExpectColonAfterCase, &[])?;
+                Some(values)
+            } else if self.match_token(&[DEFAULT]) {
+                if default.is_some() {
+                    self.report(ParseError::new(
+                        self.previous(),
+                        Code::DuplicateDefault,
+                        &[],
+                    ));
+                }
+                self.consume(COLON, Code::ExpectColonAfterCase, &[])?;
+                None
+            } else {
+                return Err(ParseError::new(self.peek(), Code::ExpectCaseOrDefault, &[]));
+            };
+            let mut body = vec![];
+            while !self.is_at_end()
+                && !self.check(RIGHT_BRACE)
+                && !self.check(CASE)
+                && !self.check(DEFAULT)
+            {
+                body.extend(self.declaration());
+            }
+            match values {
+                Some(values) => cases.push((values, body)),
+                None => default = Some(body),
+            }
+        }
+        self.consume(RIGHT_BRACE, Code::ExpectRightBraceAfterCases, &[])?;
+        Ok(Switch {
+            keyword: keyword.clone(),
+            value,
+            cases,
+            default,
+        })
+    }
+
+    fn try_(&self) -> ParseResult<Try> {
+        let body = self.braced_block("try")?;
+        let catch = if self.match_token(&[CATCH]) {
+            self.consume(LEFT_PAREN, Code::ExpectLeftParenAfterCatch, &[])?;
+            let name = self.consume(IDENTIFIER, Code::ExpectCatchVariable, &[])?;
+            self.consume(RIGHT_PAREN, Code::ExpectRightParenAfterCatch, &[])?;
+            let name = Symbol::intern(&String::from_utf8_lossy(&name.lexeme));
+            Some((name, self.braced_block("catch")?))
+        } else {
+            None
+        };
+        let finally = if self.match_token(&[FINALLY]) {
+            Some(self.braced_block("finally")?)
+        } else {
+            None
+        };
+        if catch.is_none() && finally.is_none() {
+            self.report(ParseError::new(
+                self.peek(),
+                Code::ExpectCatchOrFinally,
+                &[],
+            ));
+        }
+        Ok(Try {
+            body,
+            catch,
+            finally,
+        })
+    }
+
+    /// A `{ ... }` block required after `keyword`.
+    fn braced_block(&self, keyword: &str) -> ParseResult<Vec<Declaration>> {
+        self.consume(LEFT_BRACE, Code::ExpectLeftBraceAfter, &[&keyword])?;
+        self.block()
+    }
+
+    /// `in` is not reserved: it is only recognized right after the loop
+    /// variable, where an identifier could not appear anyway.
+    fn for_in(&self) -> ParseResult<Statement> {
+        let keyword = self.previous();
+        self.consume(LEFT_PAREN, Code::ExpectLeftParenAfterFor, &[])?;
+        let variable = self.peek();
+        if !(self.check(IDENTIFIER)
+            && self.peek_next().token_type == IDENTIFIER
+            && self.peek_next().lexeme == b"in")
+        {
+            return Err(ParseError::new(variable, Code::ExpectForIn, &[]));
         }
+        self.advance();
+        self.advance();
+        let iterable = self.expression()?;
+        self.consume(RIGHT_PAREN, Code::ExpectRightParenAfterForIn, &[])?;
+        let body = self.statement()?;
+        Ok(Statement::ForIn {
+            keyword: keyword.clone(),
+            variable: Symbol::intern(&String::from_utf8_lossy(&variable.lexeme)),
+            iterable,
+            body: Box::new(body),
+        })
+    }
+
+    fn statement(&self) -> ParseResult<Statement> {
+        self.nested(|| self.unnested_statement())
     }
 
-    fn statement(&self) -> Statement {
+    fn unnested_statement(&self) -> ParseResult<Statement> {
         if self.match_token(&[PRINT]) {
-            let expr = self.expression();
-            self.consume(SEMICOLON, "Error: missing semicolon at end".into());
-            return Statement::PrintStmt(expr);
+            let expr = self.expression()?;
+            self.end_statement()?;
+            return Ok(Statement::PrintStmt(expr));
+        }
+        if self.match_token(&[EPRINT]) {
+            let expr = self.expression()?;
+            self.end_statement()?;
+            return Ok(Statement::EPrintStmt(expr));
         }
         if self.match_token(&[LEFT_BRACE]) {
-            let exprs = self.block();
-            return Statement::Block(exprs);
+            let exprs = self.block()?;
+            return Ok(Statement::Block(exprs));
         }
 
         if self.match_token(&[IF]) {
-            let expr = self.if_();
-            return Statement::IfStmt(expr);
+            let expr = self.if_()?;
+            return Ok(Statement::IfStmt(expr));
+        }
+
+        if self.match_token(&[SWITCH]) {
+            return Ok(Statement::Switch(self.switch()?));
+        }
+
+        if self.match_token(&[FOR]) {
+            return self.for_in();
+        }
+
+        if self.match_soft_keyword(SoftKeyword::Import, &[STRING]) {
+            let keyword = self.previous();
+            let path = self.advance().literal.clone();
+            self.end_statement()?;
+            return Ok(Statement::Import {
+                keyword: keyword.clone(),
+                path,
+            });
         }
 
-        let expr = self.expression();
-        self.consume(SEMICOLON, "Error: missing semicolon at end".into());
-        Statement::ExprStmt(expr)
+        if self.match_token(&[TRY]) {
+            return Ok(Statement::Try(self.try_()?));
+        }
+
+        if self.match_token(&[THROW]) {
+            let keyword = self.previous();
+            let value = self.expression()?;
+            self.end_statement()?;
+            return Ok(Statement::Throw {
+                keyword: keyword.clone(),
+                value,
+            });
+        }
+
+        if self.match_token(&[DO]) {
+            let body = self.statement()?;
+            self.consume(WHILE, Code::ExpectWhileAfterDoBody, &[])?;
+            self.consume(LEFT_PAREN, Code::ExpectLeftParenAfterWhile, &[])?;
+            let condition = self.expression()?;
+            self.consume(RIGHT_PAREN, Code::ExpectRightParenAfterCondition, &[])?;
+            self.end_statement()?;
+            return Ok(Statement::DoWhile {
+                body: Box::new(body),
+                condition,
+            });
+        }
+
+        let expr = self.expression()?;
+        self.end_statement()?;
+        Ok(Statement::ExprStmt(expr))
     }
 
-    fn expression(&self) -> Expr {
-        self.assignment()
+    fn expression(&self) -> ParseResult<ExprId> {
+        self.nested(|| self.comma())
     }
 
-    fn assignment(&self) -> Expr {
-        let expr = self.equality();
-        if self.match_token(&[EQUAL]) {
+    /// `a, b, c` evaluates every operand and yields the last one. Argument
+    /// lists must parse their elements with `assignment` instead.
+    fn comma(&self) -> ParseResult<ExprId> {
+        let mut expr = self.assignment()?;
+        let mut links = 0;
+        while self
+            .match_where(|operator| operator.precedence() == Some(Precedence::Comma))
+        {
+            self.link(&mut links)?;
+            let operator = self.previous().clone();
+            let right = self.assignment()?;
+            expr = self.alloc(Binary {
+                left: expr,
+                operator,
+                right,
+            })
+        }
+        Ok(expr)
+    }
+
+    fn assignment(&self) -> ParseResult<ExprId> {
+        let expr = self.conditional()?;
+        if self.match_where(TokenType::is_assignment_operator) {
             let equal = self.previous();
-            let value = self.assignment();
+            let value = self.assignment()?;
 
-            if let Variable { identifier } = expr {
-                return Assign {
-                    identifier,
-                    value: Box::new(value),
-                };
-            }
-            self.lox.error(equal, "Invalid assignment target.".into());
+            let target = self.arena.borrow()[expr].clone();
+            let assignment = match target {
+                Variable {
+                    identifier, line, ..
+                } => {
+                    // `x += e` desugars to `x = x + e`; the binary keeps the
+                    // `+=` token, which the interpreter evaluates as `+`.
+                    let value = if equal.token_type == EQUAL {
+                        value
+                    } else {
+                        let left = self.alloc(Variable {
+                            identifier,
+                            line,
+                            slot: None,
+                        });
+                        self.alloc(Binary {
+                            left,
+                            operator: equal.clone(),
+                            right: value,
+                        })
+                    };
+                    Assign {
+                        identifier,
+                        line,
+                        value,
+                        slot: None,
+                    }
+                }
+                Index {
+                    object,
+                    bracket,
+                    index,
+                } => IndexSet {
+                    object,
+                    bracket,
+                    index,
+                    operator: equal.clone(),
+                    value,
+                },
+                _ => {
+                    self.report(ParseError::new(
+                        equal,
+                        Code::InvalidAssignmentTarget,
+                        &[],
+                    ));
+                    return Ok(expr);
+                }
+            };
+            // The assignment takes the target's place in the arena.
+            self.arena.borrow_mut()[expr] = assignment;
         }
-        expr
+        Ok(expr)
     }
 
-    fn equality(&self) -> Expr {
-        let mut expr = self.comparison();
-        while self.match_token(&[BANG_EQUAL, EQUAL_EQUAL]) {
-            expr = Binary {
-                left: Box::new(expr),
-                operator: self.previous(),
-                right: Box::new(self.comparison()),
-            }
+    fn conditional(&self) -> ParseResult<ExprId> {
+        let expr = self.binary(0)?;
+        if self.match_token(&[QUESTION]) {
+            let then_branch = self.expression()?;
+            self.consume(COLON, Code::ExpectColonInConditional, &[])?;
+            let else_branch = self.conditional()?;
+            return Ok(self.alloc(Conditional {
+                condition: expr,
+                then_branch,
+                else_branch,
+            }));
         }
-        expr
+        Ok(expr)
     }
 
-    fn comparison(&self) -> Expr {
-        let mut expr = self.term();
-        while self.match_token(&[GREATER, GREATER_EQUAL, LESS, LESS_EQUAL]) {
-            expr = Binary {
-                left: Box::new(expr),
-                operator: self.previous(),
-                right: Box::new(self.term()),
-            }
+    /// Left-associative binary operators, one precedence level per entry of
+    /// [`Precedence::BINARY_LEVELS`]; the level past the last one is `unary`.
+    fn binary(&self, level: usize) -> ParseResult<ExprId> {
+        let Some(&precedence) = Precedence::BINARY_LEVELS.get(level) else {
+            return self.unary();
+        };
+        let mut expr = self.binary(level + 1)?;
+        let mut links = 0;
+        while self.match_where(|operator| operator.precedence() == Some(precedence)) {
+            self.link(&mut links)?;
+            let operator = self.previous().clone();
+            let right = self.binary(level + 1)?;
+            expr = self.alloc(Binary {
+                left: expr,
+                operator,
+                right,
+            })
+        }
+        Ok(expr)
+    }
+
+    fn unary(&self) -> ParseResult<ExprId> {
+        if self.match_where(TokenType::is_unary_operator) {
+            let operator = self.previous().clone();
+            let right = self.nested(|| self.unary())?;
+            return Ok(self.alloc(Unary { operator, right }));
         }
-        expr
+        self.call()
     }
 
-    fn term(&self) -> Expr {
-        let mut expr = self.factor();
-        while self.match_token(&[MINUS, PLUS]) {
-            expr = Binary {
-                left: Box::new(expr),
-                operator: self.previous(),
-                right: Box::new(self.factor()),
+    fn call(&self) -> ParseResult<ExprId> {
+        let mut expr = self.primary()?;
+        loop {
+            if self.match_token(&[LEFT_PAREN]) {
+                let arguments = self.elements(RIGHT_PAREN)?;
+                let paren =
+                    self.consume(RIGHT_PAREN, Code::ExpectRightParenAfterArguments, &[])?;
+                expr = self.alloc(Call {
+                    callee: expr,
+                    paren: paren.clone(),
+                    arguments,
+                });
+            } else if self.match_token(&[LEFT_BRACKET]) {
+                let index = self.expression()?;
+                let bracket =
+                    self.consume(RIGHT_BRACKET, Code::ExpectRightBracketAfterIndex, &[])?;
+                expr = self.alloc(Index {
+                    object: expr,
+                    bracket: bracket.clone(),
+                    index,
+                });
+            } else {
+                break;
             }
         }
-        expr
+        Ok(expr)
     }
 
-    fn factor(&self) -> Expr {
-        let mut expr = self.unary();
-        while self.match_token(&[SLASH, STAR]) {
-            expr = Binary {
-                left: Box::new(expr),
-                operator: self.previous(),
-                right: Box::new(self.unary()),
+    /// Comma-separated expressions up to, but not including, `closing`.
+    /// Elements are parsed below the comma operator, and each may be a
+    /// `...list` spread.
+    fn elements(&self, closing: TokenType) -> ParseResult<Vec<ExprId>> {
+        let mut elements = vec![];
+        if !self.check(closing) {
+            loop {
+                if self.match_token(&[ELLIPSIS]) {
+                    let ellipsis = self.previous().clone();
+                    let value = self.assignment()?;
+                    elements.push(self.alloc(Spread { ellipsis, value }));
+                } else {
+                    elements.push(self.assignment()?);
+                }
+                if !self.match_token(&[COMMA]) {
+                    break;
+                }
             }
         }
-        expr
+        Ok(elements)
     }
 
-    fn unary(&self) -> Expr {
-        if self.match_token(&[BANG, MINUS]) {
-            return Unary {
-                operator: self.previous(),
-                right: Box::new(self.unary()),
-            };
+    /// Consumes the current token if its type satisfies `predicate`.
+    fn match_where(&self, predicate: impl Fn(TokenType) -> bool) -> bool {
+        if !self.is_at_end() && predicate(self.peek().token_type) {
+            self.advance();
+            return true;
         }
-        self.primary()
+        false
     }
 
     fn match_token(&self, token_types: &[TokenType]) -> bool {
@@ -387,50 +1541,322 @@ impl<'a, 'b> Parser<'a, 'b> {
         false
     }
 
-    fn primary(&self) -> Expr {
+    fn primary(&self) -> ParseResult<ExprId> {
+        let literal = |value| {
+            self.alloc(Literal {
+                value,
+                token: Some(self.previous().clone()),
+            })
+        };
         if self.match_token(&[STRING]) {
-            return Literal {
-                value: Object::String(self.previous().literal.clone()),
-            };
+            let value = Symbol::intern(&self.previous().literal).text();
+            return Ok(literal(Object::String(value)));
         }
 
         if self.match_token(&[NUMBER]) {
-            return Literal {
-                value: Object::Number(self.previous().literal.parse::<f32>().unwrap()),
-            };
+            let value = self.previous().literal.parse::<f64>().unwrap();
+            return Ok(literal(Object::Number(value)));
         }
 
         if self.match_token(&[TRUE]) {
-            return Literal {
-                value: Object::Boolean(true),
-            };
+            return Ok(literal(Object::Boolean(true)));
         }
 
         if self.match_token(&[FALSE]) {
-            return Literal {
-                value: Object::Boolean(false),
-            };
+            return Ok(literal(Object::Boolean(false)));
         }
 
         if self.match_token(&[NIL]) {
-            return Literal { value: Object::Nil };
+            return Ok(literal(Object::Nil));
         }
 
         if self.match_token(&[IDENTIFIER]) {
-            return Variable {
-                identifier: String::from_utf8_lossy(self.previous().lexeme).into(),
-            };
+            return Ok(self.alloc(Variable {
+                identifier: Symbol::intern(&String::from_utf8_lossy(
+                    &self.previous().lexeme,
+                )),
+                line: self.previous().line,
+                slot: None,
+            }));
+        }
+
+        if self.match_token(&[LEFT_BRACKET]) {
+            let elements = self.elements(RIGHT_BRACKET)?;
+            self.consume(RIGHT_BRACKET, Code::ExpectRightBracketAfterElements, &[])?;
+            return Ok(self.alloc(List { elements }));
+        }
+
+        if self.match_token(&[LEFT_BRACE]) {
+            let brace = self.previous();
+            let mut entries = vec![];
+            if !self.check(RIGHT_BRACE) {
+                loop {
+                    // Keys stop above the conditional's own `:`.
+                    let key = self.conditional()?;
+                    self.consume(COLON, Code::ExpectColonAfterMapKey, &[])?;
+                    entries.push((key, self.assignment()?));
+                    if !self.match_token(&[COMMA]) {
+                        break;
+                    }
+                }
+            }
+            self.consume(RIGHT_BRACE, Code::ExpectRightBraceAfterMapEntries, &[])?;
+            return Ok(self.alloc(Map {
+                brace: brace.clone(),
+                entries,
+            }));
         }
 
         if self.match_token(&[LEFT_PAREN]) {
-            let expr = self.expression();
-            self.consume(RIGHT_PAREN, "Error: Unmatched parentheses.".into());
-            return Grouping {
-                expression: Box::new(expr),
-            };
+            let expr = self.expression()?;
+            self.consume(RIGHT_PAREN, Code::UnmatchedParentheses, &[])?;
+            return Ok(self.alloc(Grouping { expression: expr }));
         }
 
-        eprintln!("Unexpected error");
-        std::process::exit(65);
+        Err(ParseError::new(self.peek(), Code::ExpectExpression, &[]))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scanner::Scanner;
+
+    fn soft_keyword_matches(extensions: bool, source: &str) -> bool {
+        let mut lox = Lox::new();
+        lox.extensions = extensions;
+        let mut scanner = Scanner::new(source.as_bytes(), &lox);
+        let tokens = scanner.scan_tokens();
+        let parser = Parser::new(tokens, &lox);
+        parser.match_soft_keyword(SoftKeyword::Const, &[IDENTIFIER])
+    }
+
+    /// Each declaration of `program` as `lox parse` prints it.
+    fn printed(program: &Program) -> Vec<String> {
+        program
+            .declarations
+            .iter()
+            .map(|declaration| program.arena.show(declaration).to_string())
+            .collect()
+    }
+
+    #[test]
+    fn test_soft_keyword_only_in_keyword_position() {
+        assert!(soft_keyword_matches(true, "const x = 1;"));
+        assert!(!soft_keyword_matches(true, "const = 1;"));
+        assert!(!soft_keyword_matches(true, "constant x"));
+        assert!(!soft_keyword_matches(false, "const x = 1;"));
+    }
+
+    /// Parses `source` in ASI mode; `None` if it did not parse.
+    fn parse_asi(source: &str) -> Option<Vec<String>> {
+        let mut lox = Lox::new();
+        lox.asi = true;
+        lox.extensions = true;
+        let mut scanner = Scanner::new(source.as_bytes(), &lox);
+        let tokens = scanner.scan_tokens();
+        let statements = printed(&Parser::new(tokens, &lox).parse());
+        (!lox.has_error()).then_some(statements)
+    }
+
+    #[test]
+    fn test_asi_line_break_ends_statement() {
+        assert_eq!(parse_asi("print 1\nprint 2").unwrap().len(), 2);
+        assert_eq!(parse_asi("print 1; print 2;\n").unwrap().len(), 2);
+        assert_eq!(parse_asi("{ print 1 }\nprint 2").unwrap().len(), 2);
+        assert_eq!(parse_asi("var a = 1\n\n\na = 2").unwrap().len(), 2);
+        assert_eq!(
+            parse_asi("if (true) print 1\nelse print 2").unwrap().len(),
+            1
+        );
+        assert!(parse_asi("print 1 print 2").is_none());
+    }
+
+    #[test]
+    fn test_asi_continues_when_next_line_can_continue() {
+        let one = |source| {
+            let statements = parse_asi(source).unwrap();
+            assert_eq!(statements.len(), 1, "{:?}", source);
+        };
+        one("print 1\n+ 2");
+        one("print 1 +\n2");
+        one("print f\n(1)");
+        one("print true\n? 1\n: 2");
+        one("print 1,\n2");
+        one("var a =\n1");
+        one("print (1\n)");
+    }
+
+    #[test]
+    fn test_parse_streaming_hands_over_each_declaration_when_complete() {
+        let source = b"print 1;\nvar a = 2;\n{ print a; }";
+        let lox = Lox::new();
+        let mut scanner = Scanner::new(source, &lox);
+        let tokens = scanner.scan_tokens();
+        let parser = Parser::new(tokens, &lox);
+
+        let mut streamed = vec![];
+        parser.parse_streaming(|declaration, arena| {
+            let text = arena.show(&declaration).to_string();
+            streamed.push((text, *parser.current.borrow()));
+        });
+
+        let ends: Vec<usize> = streamed.iter().map(|(_, end)| *end).collect();
+        assert_eq!(ends, vec![3, 8, 13]);
+        let streamed: Vec<String> = streamed.into_iter().map(|(text, _)| text).collect();
+        let mut scanner = Scanner::new(source, &lox);
+        let tokens = scanner.scan_tokens();
+        let collected = printed(&Parser::new(tokens, &lox).parse());
+        assert_eq!(streamed, collected);
+    }
+
+    #[test]
+    fn test_parse_streaming_empties_the_arena_between_declarations() {
+        let lox = Lox::new();
+        let mut scanner = Scanner::new(b"print 1 + 2 * 3;\nprint -4;\nprint (5);", &lox);
+        let tokens = scanner.scan_tokens();
+        let mut sizes = vec![];
+        Parser::new(tokens, &lox).parse_streaming(|declaration, arena| {
+            sizes.push(arena.exprs.len());
+            let Declaration::Statement(Statement::PrintStmt(value)) = declaration else {
+                panic!("expected a print");
+            };
+            // Operands come first, so each declaration's root is its last node.
+            assert_eq!(value.0 as usize, arena.exprs.len() - 1);
+        });
+
+        assert_eq!(sizes, vec![5, 2, 2]);
+    }
+
+    #[test]
+    fn test_var_declaration_is_its_own_node() {
+        let mut lox = Lox::new();
+        lox.extensions = true;
+        let mut scanner = Scanner::new(
+            b"/// The answer.\nconst answer = 42;\nvar [a, b] = [1, 2];\nvar c;",
+            &lox,
+        );
+        let tokens = scanner.scan_tokens();
+        let parser = Parser::new(tokens, &lox);
+        let program = parser.parse();
+        assert!(!lox.has_error());
+
+        let arena = &program.arena;
+        let [Declaration::Var {
+            keyword,
+            target,
+            initializer: Some(initializer),
+            constant: true,
+            doc: Some(doc),
+        }, Declaration::Var {
+            target: pattern,
+            initializer: Some(list),
+            constant: false,
+            ..
+        }, Declaration::Var {
+            initializer: None,
+            doc: None,
+            ..
+        }] = &program.declarations[..]
+        else {
+            panic!("{:?}", printed(&program));
+        };
+        let (Variable { identifier, .. }, Literal { value, .. }) =
+            (&arena[*target], &arena[*initializer])
+        else {
+            panic!("{:?}", printed(&program));
+        };
+        assert!(matches!(
+            (&arena[*pattern], &arena[*list]),
+            (List { .. }, List { .. })
+        ));
+        assert_eq!(keyword.lexeme, b"const");
+        assert_eq!(identifier.to_string(), "answer");
+        assert_eq!(value.to_string(), "42");
+        assert_eq!(doc, "The answer.");
+    }
+
+    #[test]
+    fn test_parse_streaming_hands_over_each_declaration_in_order() {
+        let lox = Lox::new();
+        let mut scanner = Scanner::new(b"print 1;\nvar a = 2;\nprint +;\nprint a;", &lox);
+        let tokens = scanner.scan_tokens();
+        let mut seen = vec![];
+        Parser::new(tokens, &lox).parse_streaming(|declaration, arena| {
+            // The errors reported so far show how far parsing had got.
+            let text = arena.show(&declaration).to_string();
+            seen.push((text, lox.diagnostics.borrow().len()));
+        });
+
+        assert_eq!(
+            seen,
+            vec![
+                ("print 1.0;".to_string(), 0),
+                ("(var (= variable a 2.0));".to_string(), 0),
+                ("print variable a;".to_string(), 1),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_bad_statement_is_skipped_and_parsing_resumes() {
+        let lox = Lox::new();
+        let mut scanner =
+            Scanner::new(b"print 1 +; { print (; print 2; } print 3;", &lox);
+        let tokens = scanner.scan_tokens();
+        let statements = printed(&Parser::new(tokens, &lox).parse());
+        assert_eq!(lox.error_count(), 2);
+        assert_eq!(statements.len(), 2);
+        assert!(statements[0].contains('2'), "{:?}", statements);
+    }
+
+    /// The codes of the syntax errors found parsing `source`.
+    fn error_codes(source: &str) -> Vec<Code> {
+        let mut lox = Lox::new();
+        lox.extensions = true;
+        let mut scanner = Scanner::new(source.as_bytes(), &lox);
+        Parser::new(scanner.scan_tokens(), &lox).parse();
+        let diagnostics = lox.diagnostics.borrow();
+        diagnostics
+            .iter()
+            .filter_map(|diagnostic| diagnostic.code)
+            .collect()
+    }
+
+    #[test]
+    fn test_var_target_must_be_a_name_or_pattern() {
+        assert_eq!(error_codes("var 1 = 2;"), [Code::ExpectVariableName]);
+        assert_eq!(error_codes("var \"s\" = 3;"), [Code::ExpectVariableName]);
+        assert_eq!(error_codes("var [a, 1] = [];"), [Code::ExpectBindingName]);
+        assert!(error_codes("var a = 1; var [b] = [a];").is_empty());
+    }
+
+    #[test]
+    fn test_deep_equals_compares_contents_and_cycles() {
+        let list = |elements| Object::List(Rc::new(RefCell::new(elements)));
+        let cyclic = || {
+            let outer = list(vec![Object::Nil]);
+            if let Object::List(elements) = &outer {
+                elements.borrow_mut()[0] = outer.clone();
+            }
+            outer
+        };
+
+        assert!(
+            list(vec![Object::Number(1.0)]).deep_equals(&list(vec![Object::Number(1.0)]))
+        );
+        assert!(!list(vec![Object::Number(1.0)]).deep_equals(&list(vec![])));
+        assert!(cyclic().deep_equals(&cyclic()));
+        assert_eq!(cyclic().to_string(), "[[...]]");
+    }
+
+    #[test]
+    fn test_values_are_compact_and_share_strings() {
+        assert!(std::mem::size_of::<Object>() <= 3 * std::mem::size_of::<usize>());
+        let string = Object::String("shared".into());
+        let (Object::String(a), Object::String(b)) = (&string, &string.clone()) else {
+            unreachable!()
+        };
+        assert!(Rc::ptr_eq(a, b));
     }
 }
@@ -0,0 +1,221 @@
+//! Token-level preprocessor, run between the scanner and the parser when
+//! extensions are enabled.
+//!
+//! A directive starts with `#` and runs to the end of its line:
+//!
+//! * `#define NAME tokens...` replaces every later `NAME` with `tokens...`;
+//! * `#ifdef NAME` / `#ifndef NAME`, `#else` and `#endif` keep or drop the
+//!   lines between them depending on whether `NAME` was `#define`d or passed
+//!   on the command line as `-DNAME`.
+//!
+//! Substituted tokens take the position of the name they replace, so later
+//! errors point at the use site rather than at the definition.
+
+use std::collections::HashMap;
+
+use crate::messages::Code;
+use crate::token::Token;
+use crate::token::TokenType::{EOF, HASH, IDENTIFIER, NEWLINE};
+use crate::Lox;
+
+/// An open `#ifdef`/`#ifndef` block.
+struct Condition {
+    /// The `#` that opened it.
+    hash: Token,
+    directive: String,
+    /// Whether the branch currently being read is kept.
+    taken: bool,
+    seen_else: bool,
+}
+
+pub(crate) struct Preprocessor<'b> {
+    tokens: &'b [Token],
+    current: usize,
+    macros: HashMap<String, Vec<Token>>,
+    conditions: Vec<Condition>,
+    lox: &'b Lox,
+}
+
+impl<'b> Preprocessor<'b> {
+    pub(crate) fn new(tokens: &'b [Token], lox: &'b Lox) -> Self {
+        Preprocessor {
+            tokens,
+            current: 0,
+            macros: HashMap::new(),
+            conditions: vec![],
+            lox,
+        }
+    }
+
+    /// The token stream with directives applied and removed.
+    pub(crate) fn expand(mut self) -> Vec<Token> {
+        let mut expanded = vec![];
+        while let Some(token) = self.tokens.get(self.current) {
+            match token.token_type {
+                HASH => self.directive(),
+                EOF => {
+                    for condition in &self.conditions {
+                        self.error(
+                            &condition.hash,
+                            Code::UnterminatedConditional,
+                            &condition.directive,
+                        );
+                    }
+                    expanded.push(token.clone());
+                    break;
+                }
+                _ => {
+                    self.current += 1;
+                    if self.is_active() {
+                        expanded.extend(self.substitute(token));
+                    }
+                }
+            }
+        }
+        expanded
+    }
+
+    fn is_active(&self) -> bool {
+        self.conditions.iter().all(|condition| condition.taken)
+    }
+
+    fn is_defined(&self, name: &str) -> bool {
+        self.macros.contains_key(name) || self.lox.defines.iter().any(|d| d == name)
+    }
+
+    fn substitute(&self, token: &Token) -> Vec<Token> {
+        let body = (token.token_type == IDENTIFIER)
+            .then(|| self.macros.get(lexeme(token).as_ref()))
+            .flatten();
+        match body {
+            Some(body) => body
+                .iter()
+                .map(|substituted| Token {
+                    line: token.line,
+                    column: token.column,
+                    span: token.span.clone(),
+                    ..substituted.clone()
+                })
+                .collect(),
+            None => vec![token.clone()],
+        }
+    }
+
+    /// Consumes the `#` at `current` and the rest of its line.
+    fn directive(&mut self) {
+        let hash = &self.tokens[self.current];
+        self.current += 1;
+        let start = self.current;
+        while !matches!(self.tokens[self.current].token_type, EOF | NEWLINE)
+            && self.tokens[self.current].line == hash.line
+        {
+            self.current += 1;
+        }
+        let line = &self.tokens[start..self.current];
+
+        let Some((name, arguments)) = line.split_first() else {
+            return self.error(hash, Code::ExpectDirectiveName, &"");
+        };
+        let directive = lexeme(name);
+        match directive.as_ref() {
+            "define" | "ifdef" | "ifndef" => {
+                let Some((macro_name, body)) = arguments
+                    .split_first()
+                    .filter(|(macro_name, _)| macro_name.token_type == IDENTIFIER)
+                else {
+                    return self.error(hash, Code::ExpectMacroName, &directive);
+                };
+                let macro_name = lexeme(macro_name).into_owned();
+                if directive == "define" {
+                    if self.is_active() {
+                        // Expand the body now so macros may build on earlier ones
+                        // without any risk of recursive substitution.
+                        let body = body.iter().flat_map(|t| self.substitute(t)).collect();
+                        self.macros.insert(macro_name, body);
+                    }
+                } else {
+                    let defined = self.is_defined(&macro_name);
+                    self.conditions.push(Condition {
+                        hash: hash.clone(),
+                        taken: defined == (directive == "ifdef"),
+                        directive: directive.into_owned(),
+                        seen_else: false,
+                    });
+                }
+            }
+            "else" => match self.conditions.last_mut() {
+                Some(condition) if !condition.seen_else => {
+                    condition.taken = !condition.taken;
+                    condition.seen_else = true;
+                }
+                _ => self.error(hash, Code::UnmatchedDirective, &directive),
+            },
+            "endif" => {
+                if self.conditions.pop().is_none() {
+                    self.error(hash, Code::UnmatchedDirective, &directive);
+                }
+            }
+            _ => self.error(hash, Code::UnknownDirective, &directive),
+        }
+    }
+
+    fn error(&self, hash: &Token, code: Code, directive: &dyn std::fmt::Display) {
+        self.lox.report(
+            hash.line,
+            hash.column,
+            Some(hash.span.clone()),
+            "",
+            code,
+            &[directive],
+        );
+    }
+}
+
+fn lexeme<'t>(token: &'t Token) -> std::borrow::Cow<'t, str> {
+    String::from_utf8_lossy(&token.lexeme)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scanner::Scanner;
+
+    fn expand(lox: &Lox, source: &str) -> Vec<String> {
+        let mut scanner = Scanner::new(source.as_bytes(), lox);
+        let tokens = scanner.scan_tokens();
+        Preprocessor::new(tokens, lox)
+            .expand()
+            .iter()
+            .map(|token| lexeme(token).into_owned())
+            .collect()
+    }
+
+    #[test]
+    fn test_define_and_ifdef() {
+        let mut lox = Lox::new();
+        lox.extensions = true;
+        lox.defines = vec!["DEBUG".to_string()];
+        let source = "#define TWO 1 + 1\n#define FOUR TWO * TWO\n\
+                      #ifdef DEBUG\nprint FOUR;\n#else\nprint 0;\n#endif\n\
+                      #ifndef DEBUG\nprint 5;\n#endif";
+
+        assert_eq!(
+            expand(&lox, source),
+            vec!["print", "1", "+", "1", "*", "1", "+", "1", ";", ""]
+        );
+        assert!(!lox.has_error());
+    }
+
+    #[test]
+    fn test_unterminated_ifdef_is_reported() {
+        let mut lox = Lox::new();
+        lox.extensions = true;
+
+        expand(&lox, "print 1;\n#ifdef X\nprint 2;");
+
+        assert_eq!(
+            lox.diagnostics.borrow()[0].to_string(),
+            "[line 2] Error: Unterminated '#ifdef'; expect '#endif'."
+        );
+    }
+}
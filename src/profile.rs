@@ -0,0 +1,97 @@
+//! `--profile`: call counts and timings per source line and per native
+//! function, reported when the run ends.
+
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+#[derive(Default, Clone, Copy)]
+struct Timing {
+    calls: usize,
+    inclusive: Duration,
+    /// Inclusive time minus the time spent in nested statements.
+    exclusive: Duration,
+}
+
+#[derive(Default)]
+pub struct Profile {
+    /// Keyed by line; `None` collects statements built only from literals.
+    lines: HashMap<Option<usize>, Timing>,
+    natives: HashMap<&'static str, Timing>,
+    /// For each statement running, when it started and the time spent so
+    /// far in the statements nested in it.
+    running: Vec<(Instant, Duration)>,
+}
+
+impl Profile {
+    pub fn enter(&mut self) {
+        self.running.push((Instant::now(), Duration::ZERO));
+    }
+
+    /// Ends the statement started by the matching [`Profile::enter`].
+    pub fn exit(&mut self, line: Option<usize>) {
+        let Some((started, nested)) = self.running.pop() else {
+            return;
+        };
+        let elapsed = started.elapsed();
+        if let Some((_, parent_nested)) = self.running.last_mut() {
+            *parent_nested += elapsed;
+        }
+        let timing = self.lines.entry(line).or_default();
+        timing.calls += 1;
+        timing.inclusive += elapsed;
+        timing.exclusive += elapsed.saturating_sub(nested);
+    }
+
+    /// Natives run no statements, so all their time is exclusive.
+    pub fn native(&mut self, name: &'static str, elapsed: Duration) {
+        let timing = self.natives.entry(name).or_default();
+        timing.calls += 1;
+        timing.inclusive += elapsed;
+        timing.exclusive += elapsed;
+    }
+
+    /// The summary tables, most expensive first.
+    pub fn report(&self) -> Vec<String> {
+        let lines = self.lines.iter().map(|(line, timing)| {
+            let line = line.map_or("?".to_string(), |line| line.to_string());
+            (line, *timing)
+        });
+        let natives = self
+            .natives
+            .iter()
+            .map(|(name, timing)| (name.to_string(), *timing));
+        let mut report = vec!["Profile by line:".to_string()];
+        report.extend(table("line", lines.collect()));
+        if !self.natives.is_empty() {
+            report.push("Profile by native function:".into());
+            report.extend(table("function", natives.collect()));
+        }
+        report
+    }
+}
+
+fn table(label: &str, mut rows: Vec<(String, Timing)>) -> Vec<String> {
+    rows.sort_by(|(a_name, a), (b_name, b)| {
+        b.exclusive
+            .cmp(&a.exclusive)
+            .then_with(|| a_name.cmp(b_name))
+    });
+    let mut table = vec![format!(
+        "  {:<10} {:>8} {:>12} {:>12}",
+        label, "calls", "inclusive", "exclusive"
+    )];
+    table.extend(rows.iter().map(|(name, timing)| {
+        format!(
+            "  {:<10} {:>8} {:>12} {:>12}",
+            name,
+            timing.calls,
+            millis(timing.inclusive),
+            millis(timing.exclusive)
+        )
+    }));
+    table
+}
+
+fn millis(duration: Duration) -> String {
+    format!("{:.3}ms", duration.as_secs_f64() * 1000.0)
+}
@@ -0,0 +1,220 @@
+//! Static pass over scopes, run after parsing and before the program runs.
+//!
+//! A `var` in a block may shadow a variable of an enclosing scope, but
+//! declaring the same name twice in one block is an error. The global
+//! scope allows redeclaration, as in Lox.
+//!
+//! Every read and assignment of a local variable is given its [`Slot`], so
+//! the interpreter indexes straight into the right scope instead of
+//! searching for the name. Globals keep no slot and are found by name.
+//!
+//! The scopes mirror the ones the interpreter creates: a block, a switch
+//! case, each clause of a `try` (the catch variable shares its handler's
+//! scope) and a for-in loop, which holds the loop variable.
+
+use crate::environment::Slot;
+use crate::messages::Code;
+use crate::parser::{Arena, Declaration, Expr, ExprId, Statement};
+use crate::symbol::Symbol;
+use crate::Lox;
+
+/// Resolves the local variables of `declarations`, whose expressions are
+/// in `arena`, reporting every redeclaration to `lox`.
+pub(crate) fn resolve(declarations: &[Declaration], arena: &mut Arena, lox: &Lox) {
+    let mut resolver = Resolver {
+        lox,
+        arena,
+        scopes: vec![],
+    };
+    resolver.body(declarations);
+}
+
+struct Resolver<'l, 'a> {
+    lox: &'l Lox,
+    /// Where the slots found are written.
+    arena: &'a mut Arena,
+    /// The names declared in each enclosing block in slot order, innermost
+    /// last; empty at global scope.
+    scopes: Vec<Vec<Symbol>>,
+}
+
+impl Resolver<'_, '_> {
+    fn declare(&mut self, name: Symbol, line: usize) {
+        let Some(scope) = self.scopes.last_mut() else {
+            return;
+        };
+        if scope.contains(&name) {
+            // The interpreter reuses the slot, so the rest still lines up.
+            self.lox.report(
+                line,
+                0,
+                None,
+                &format!(" at '{}' ", name),
+                Code::VariableAlreadyDeclared,
+                &[],
+            );
+        } else {
+            scope.push(name);
+        }
+    }
+
+    /// The slot of the innermost local `name`, `None` when it is global.
+    fn slot(&self, name: Symbol) -> Option<Slot> {
+        self.scopes
+            .iter()
+            .rev()
+            .enumerate()
+            .find_map(|(depth, scope)| {
+                let index = scope.iter().position(|&declared| declared == name)?;
+                Some(Slot { depth, index })
+            })
+    }
+
+    fn scoped(&mut self, declarations: &[Declaration]) {
+        self.scopes.push(vec![]);
+        self.body(declarations);
+        self.scopes.pop();
+    }
+
+    fn body(&mut self, declarations: &[Declaration]) {
+        for declaration in declarations {
+            match declaration {
+                Declaration::Var {
+                    target,
+                    initializer,
+                    ..
+                } => {
+                    // The initializer still sees any outer variable of the
+                    // same name.
+                    if let Some(initializer) = initializer {
+                        self.expr(*initializer);
+                    }
+                    self.pattern(*target);
+                }
+                Declaration::Statement(statement) => self.statement(statement),
+            }
+        }
+    }
+
+    /// Declares the names a `var` target binds.
+    fn pattern(&mut self, target: ExprId) {
+        match &self.arena[target] {
+            &Expr::Variable {
+                identifier, line, ..
+            } => self.declare(identifier, line),
+            // A map pattern's keys are literals, which declare nothing.
+            Expr::List { .. } | Expr::Map { .. } => {
+                for name in self.arena[target].children() {
+                    self.pattern(name);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn statement(&mut self, statement: &Statement) {
+        match statement {
+            Statement::ExprStmt(value)
+            | Statement::PrintStmt(value)
+            | Statement::EPrintStmt(value)
+            | Statement::WhileStmt(value)
+            | Statement::Throw { value, .. } => self.expr(*value),
+            Statement::Block(declarations) => self.scoped(declarations),
+            Statement::IfStmt(if_) => {
+                self.expr(if_.condition);
+                self.statement(&if_.then_branch);
+                if let Some(else_branch) = &if_.else_branch {
+                    self.statement(else_branch);
+                }
+            }
+            Statement::Switch(switch) => {
+                self.expr(switch.value);
+                for (values, declarations) in &switch.cases {
+                    values.iter().for_each(|&value| self.expr(value));
+                    self.scoped(declarations);
+                }
+                if let Some(declarations) = &switch.default {
+                    self.scoped(declarations);
+                }
+            }
+            Statement::ForIn {
+                keyword,
+                variable,
+                iterable,
+                body,
+            } => {
+                self.expr(*iterable);
+                self.scopes.push(vec![]);
+                self.declare(*variable, keyword.line);
+                self.statement(body);
+                self.scopes.pop();
+            }
+            Statement::DoWhile { body, condition } => {
+                self.statement(body);
+                self.expr(*condition);
+            }
+            Statement::Try(try_) => {
+                self.scoped(&try_.body);
+                if let Some((name, declarations)) = &try_.catch {
+                    self.scopes.push(vec![*name]);
+                    self.body(declarations);
+                    self.scopes.pop();
+                }
+                if let Some(declarations) = &try_.finally {
+                    self.scoped(declarations);
+                }
+            }
+            Statement::Import { .. } => {}
+        }
+    }
+
+    fn expr(&mut self, id: ExprId) {
+        if let Expr::Variable { identifier, .. } | Expr::Assign { identifier, .. } =
+            self.arena[id]
+        {
+            let found = self.slot(identifier);
+            if let Expr::Variable { slot, .. } | Expr::Assign { slot, .. } =
+                &mut self.arena[id]
+            {
+                *slot = found;
+            }
+        }
+        for child in self.arena[id].children() {
+            self.expr(child);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::Parser;
+    use crate::scanner::Scanner;
+
+    #[test]
+    fn test_locals_get_slots_and_globals_do_not() {
+        let lox = Lox::new();
+        let mut scanner =
+            Scanner::new(b"var g; { var a; var b; { var c; a = g; } }", &lox);
+        let tokens = scanner.scan_tokens();
+        let mut program = Parser::new(tokens, &lox).parse();
+        resolve(&program.declarations, &mut program.arena, &lox);
+
+        let arena = &program.arena;
+        let Declaration::Statement(Statement::Block(outer)) = &program.declarations[1]
+        else {
+            panic!("expected a block");
+        };
+        let Declaration::Statement(Statement::Block(inner)) = &outer[2] else {
+            panic!("expected a block");
+        };
+        let Declaration::Statement(Statement::ExprStmt(assignment)) = &inner[1] else {
+            panic!("expected an expression statement");
+        };
+        let Expr::Assign { value, slot, .. } = &arena[*assignment] else {
+            panic!("expected an assignment");
+        };
+        assert_eq!(*slot, Some(Slot { depth: 1, index: 0 }));
+        assert!(matches!(arena[*value], Expr::Variable { slot: None, .. }));
+    }
+}
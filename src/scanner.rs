@@ -1,11 +1,31 @@
+use std::cell::Cell;
+
+use crate::messages::Code;
 use crate::token::TokenType::{
-    BANG, BANG_EQUAL, COMMA, DOT, EOF, EQUAL, EQUAL_EQUAL, GREATER, GREATER_EQUAL,
-    IDENTIFIER, LEFT_BRACE, LEFT_PAREN, LESS, LESS_EQUAL, MINUS, NUMBER, PLUS,
-    RIGHT_BRACE, RIGHT_PAREN, SEMICOLON, SLASH, STAR, STRING,
+    AMPERSAND, BANG, BANG_EQUAL, CARET, COLON, COMMA, DOC_COMMENT, DOT, ELLIPSIS, EOF,
+    EQUAL, EQUAL_EQUAL, GREATER, GREATER_EQUAL, GREATER_GREATER, HASH, IDENTIFIER,
+    LEFT_BRACE, LEFT_BRACKET, LEFT_PAREN, LESS, LESS_EQUAL, LESS_LESS, MINUS,
+    MINUS_EQUAL, NEWLINE, NUMBER, PIPE, PLUS, PLUS_EQUAL, QUESTION, QUESTION_QUESTION,
+    RIGHT_BRACE, RIGHT_BRACKET, RIGHT_PAREN, SEMICOLON, SLASH, SLASH_EQUAL, STAR,
+    STAR_EQUAL, STRING, TILDE,
 };
-use crate::token::{try_get_keyword, Token, TokenType};
+use crate::token::{try_get_extension_keyword, try_get_keyword, Token, TokenType};
 use crate::Lox;
 
+/// A `//` or `/* */` comment. The parser never sees these; they are kept
+/// for `fmt` to print back.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct Comment {
+    /// The line the comment starts on.
+    pub line: usize,
+    /// The comment as written, delimiters included.
+    pub text: String,
+    /// Whether code comes before the comment on its line.
+    pub trailing: bool,
+    /// How many `{` are open where the comment is.
+    pub braces: usize,
+}
+
 pub(crate) struct Scanner<'a, 'b>
 where
     'b: 'a,
@@ -13,9 +33,16 @@ where
     start: usize,
     current: usize,
     line: usize,
+    /// The `//` and `/* */` comments skipped so far.
+    comments: Vec<Comment>,
+    /// How many `{` are open so far.
+    braces: usize,
+    /// A byte and its column, which the next [`Scanner::column`] counts on
+    /// from rather than from the start of the line.
+    last_column: Cell<(usize, usize)>,
 
     source: &'a [u8],
-    tokens: Vec<Token<'a>>,
+    tokens: Vec<Token>,
     lox: &'b Lox,
 }
 
@@ -28,21 +55,51 @@ impl<'a, 'b> Scanner<'a, 'b> {
             start: 0,
             current: 0,
             line: 1,
+            comments: vec![],
+            braces: 0,
+            last_column: Cell::new((0, 1)),
         }
     }
 
+    pub fn tokens(&self) -> &Vec<Token> {
+        &self.tokens
+    }
+
+    pub fn comments(&self) -> &[Comment] {
+        &self.comments
+    }
+
+    /// Records the comment that was just skipped, from `start` on.
+    fn add_comment(&mut self, line: usize) {
+        let trailing = self
+            .tokens
+            .last()
+            .is_some_and(|token| token.line == line && token.token_type != NEWLINE);
+        self.comments.push(Comment {
+            line,
+            text: String::from_utf8_lossy(&self.source[self.start..self.current])
+                .trim_end()
+                .to_string(),
+            trailing,
+            braces: self.braces,
+        });
+    }
+
     fn is_at_end(&self) -> bool {
         self.current >= self.source.len()
     }
 
-    pub fn scan_tokens(&mut self) -> &'a Vec<Token> {
+    pub fn scan_tokens(&mut self) -> &Vec<Token> {
         while !self.is_at_end() {
             self.start = self.current;
             self.scan_token()
         }
 
-        self.tokens
-            .push(Token::new(EOF, "".as_bytes(), "null".into(), self.line));
+        self.tokens.push(Token {
+            column: self.column(self.current),
+            span: self.current..self.current,
+            ..Token::new(EOF, "".as_bytes(), "null".into(), self.line)
+        });
 
         &self.tokens
     }
@@ -59,8 +116,34 @@ impl<'a, 'b> Scanner<'a, 'b> {
 
     fn add_token_with_literal(&mut self, token_type: TokenType, literal: String) {
         let text = &self.source[self.start..self.current];
-        self.tokens
-            .push(Token::new(token_type, text, literal, self.line))
+        self.tokens.push(Token {
+            column: self.column(self.start),
+            span: self.start..self.current,
+            ..Token::new(token_type, text, literal, self.line)
+        })
+    }
+
+    /// The 1-based column of byte `at`, counting characters since the
+    /// preceding line break.
+    fn column(&self, at: usize) -> usize {
+        let (from, column) = match self.last_column.get() {
+            (from, column) if from <= at => (from, column),
+            _ => (0, 1),
+        };
+        let (from, column) = match self.source[from..at]
+            .iter()
+            .rposition(|&byte| byte == b'\n')
+        {
+            Some(index) => (from + index + 1, 1),
+            None => (from, column),
+        };
+        let column = column
+            + self.source[from..at]
+                .iter()
+                .filter(|&&byte| byte & 0xc0 != 0x80)
+                .count();
+        self.last_column.set((at, column));
+        column
     }
 
     fn next_match(&mut self, expected: u8) -> bool {
@@ -89,6 +172,27 @@ impl<'a, 'b> Scanner<'a, 'b> {
         self.source[self.current + 1]
     }
 
+    /// The UTF-8 character starting at byte `at`, and its length in bytes.
+    fn char_at(&self, at: usize) -> Option<(char, usize)> {
+        let len = match *self.source.get(at)? {
+            0..=0x7f => 1,
+            0xc0..=0xdf => 2,
+            0xe0..=0xef => 3,
+            _ => 4,
+        };
+        let bytes = self.source.get(at..at + len)?;
+        let ch = std::str::from_utf8(bytes).ok()?.chars().next()?;
+        Some((ch, len))
+    }
+
+    /// Whether `ch` may continue an identifier. Non-ASCII letters and digits
+    /// only count with extensions enabled.
+    fn is_identifier_char(&self, ch: char) -> bool {
+        ch.is_ascii_alphanumeric()
+            || ch == '_'
+            || (self.lox.extensions && !ch.is_ascii() && ch.is_alphanumeric())
+    }
+
     fn add_string(&mut self) {
         while self.peek() != b'"' && !self.is_at_end() {
             if self.peek() == b'\n' {
@@ -98,8 +202,14 @@ impl<'a, 'b> Scanner<'a, 'b> {
         }
 
         if self.is_at_end() {
-            self.lox
-                .report(self.line, "Unterminated string.", "".into());
+            self.lox.report(
+                self.line,
+                self.column(self.current),
+                Some(self.current..self.current),
+                "",
+                Code::UnterminatedString,
+                &[],
+            );
             return;
         }
 
@@ -113,6 +223,64 @@ impl<'a, 'b> Scanner<'a, 'b> {
         )
     }
 
+    /// A `"""..."""` string whose opening quotes were already consumed. The
+    /// contents are kept verbatim, newlines and lone `"` included.
+    fn add_raw_string(&mut self) {
+        let start_line = self.line;
+        while !self.source[self.current..].starts_with(b"\"\"\"") {
+            if self.is_at_end() {
+                self.lox.report(
+                    start_line,
+                    self.column(self.start),
+                    Some(self.start..self.current),
+                    "",
+                    Code::UnterminatedRawString,
+                    &[],
+                );
+                return;
+            }
+            if self.advance() == b'\n' {
+                self.line += 1;
+            }
+        }
+        self.current += 3;
+
+        self.add_token_with_literal(
+            STRING,
+            std::str::from_utf8(&self.source[self.start + 3..self.current - 3])
+                .unwrap()
+                .into(),
+        )
+    }
+
+    /// Skips a `/* ... */` comment whose opener was already consumed. Nested
+    /// comments must be closed before the outer one ends.
+    fn block_comment(&mut self) {
+        let mut depth = 1;
+        while depth > 0 {
+            if self.is_at_end() {
+                self.lox.report(
+                    self.line,
+                    self.column(self.current),
+                    Some(self.current..self.current),
+                    "",
+                    Code::UnterminatedBlockComment,
+                    &[],
+                );
+                return;
+            }
+            if self.peek() == b'/' && self.peek_next() == b'*' {
+                self.current += 2;
+                depth += 1;
+            } else if self.peek() == b'*' && self.peek_next() == b'/' {
+                self.current += 2;
+                depth -= 1;
+            } else if self.advance() == b'\n' {
+                self.line += 1;
+            }
+        }
+    }
+
     fn add_number(&mut self) {
         while self.peek().is_ascii_digit() {
             self.advance();
@@ -125,24 +293,28 @@ impl<'a, 'b> Scanner<'a, 'b> {
                 self.advance();
             }
         }
+        // Keep the spelling from the source; consumers normalize as needed.
         let str_repr =
             std::str::from_utf8(&self.source[self.start..self.current]).unwrap();
-        let double = str_repr.parse::<f32>().unwrap();
-        let double = if double.fract() == 0.0 {
-            format!("{:.1}", double)
-        } else {
-            format!("{}", double)
-        };
-        self.add_token_with_literal(NUMBER, double)
+        self.add_token_with_literal(NUMBER, str_repr.into())
     }
 
     fn add_identifier_or_reserved_words(&mut self) {
-        while self.peek().is_ascii_alphanumeric() || self.peek() == b'_' {
-            self.advance();
+        while let Some((ch, len)) = self.char_at(self.current) {
+            if !self.is_identifier_char(ch) {
+                break;
+            }
+            self.current += len;
         }
 
         let str = &std::str::from_utf8(&self.source[self.start..self.current]).unwrap();
-        match try_get_keyword(str) {
+        let keyword = try_get_keyword(str).or_else(|| {
+            self.lox
+                .extensions
+                .then(|| try_get_extension_keyword(str))
+                .flatten()
+        });
+        match keyword {
             None => self.add_token_with_literal(IDENTIFIER, String::from(*str)),
             Some(token) => self.add_token(token),
         }
@@ -152,14 +324,65 @@ impl<'a, 'b> Scanner<'a, 'b> {
         match self.advance() {
             b'(' => self.add_token(LEFT_PAREN),
             b')' => self.add_token(RIGHT_PAREN),
-            b'{' => self.add_token(LEFT_BRACE),
-            b'}' => self.add_token(RIGHT_BRACE),
+            b'{' => {
+                self.braces += 1;
+                self.add_token(LEFT_BRACE)
+            }
+            b'[' if self.lox.extensions => self.add_token(LEFT_BRACKET),
+            b']' if self.lox.extensions => self.add_token(RIGHT_BRACKET),
+            b'}' => {
+                self.braces = self.braces.saturating_sub(1);
+                self.add_token(RIGHT_BRACE)
+            }
             b',' => self.add_token(COMMA),
-            b'.' => self.add_token(DOT),
-            b'-' => self.add_token(MINUS),
-            b'+' => self.add_token(PLUS),
+            b'.' => {
+                let ellipsis = self.peek() == b'.' && self.peek_next() == b'.';
+                if self.lox.extensions && ellipsis {
+                    self.current += 2;
+                    self.add_token(ELLIPSIS)
+                } else {
+                    self.add_token(DOT)
+                }
+            }
+            b'-' => {
+                let token_type = if self.lox.extensions && self.next_match(b'=') {
+                    MINUS_EQUAL
+                } else {
+                    MINUS
+                };
+                self.add_token(token_type);
+            }
+            b'+' => {
+                let token_type = if self.lox.extensions && self.next_match(b'=') {
+                    PLUS_EQUAL
+                } else {
+                    PLUS
+                };
+                self.add_token(token_type);
+            }
             b';' => self.add_token(SEMICOLON),
-            b'*' => self.add_token(STAR),
+            b'*' => {
+                let token_type = if self.lox.extensions && self.next_match(b'=') {
+                    STAR_EQUAL
+                } else {
+                    STAR
+                };
+                self.add_token(token_type);
+            }
+            b'?' if self.lox.extensions => {
+                let token_type = if self.next_match(b'?') {
+                    QUESTION_QUESTION
+                } else {
+                    QUESTION
+                };
+                self.add_token(token_type);
+            }
+            b':' if self.lox.extensions => self.add_token(COLON),
+            b'&' if self.lox.extensions => self.add_token(AMPERSAND),
+            b'|' if self.lox.extensions => self.add_token(PIPE),
+            b'^' if self.lox.extensions => self.add_token(CARET),
+            b'~' if self.lox.extensions => self.add_token(TILDE),
+            b'#' if self.lox.extensions => self.add_token(HASH),
             b'!' => {
                 let token_type = if self.next_match(b'=') {
                     BANG_EQUAL
@@ -179,6 +402,8 @@ impl<'a, 'b> Scanner<'a, 'b> {
             b'<' => {
                 let token_type = if self.next_match(b'=') {
                     LESS_EQUAL
+                } else if self.lox.extensions && self.next_match(b'<') {
+                    LESS_LESS
                 } else {
                     LESS
                 };
@@ -187,6 +412,8 @@ impl<'a, 'b> Scanner<'a, 'b> {
             b'>' => {
                 let token_type = if self.next_match(b'=') {
                     GREATER_EQUAL
+                } else if self.lox.extensions && self.next_match(b'>') {
+                    GREATER_GREATER
                 } else {
                     GREATER
                 };
@@ -194,22 +421,109 @@ impl<'a, 'b> Scanner<'a, 'b> {
             }
             b'/' => {
                 if self.next_match(b'/') {
+                    let is_doc = self.lox.extensions && self.next_match(b'/');
                     while !self.is_at_end() && self.peek() != b'\n' {
                         self.advance();
                     }
+                    if !is_doc {
+                        self.add_comment(self.line);
+                    } else {
+                        let text = &self.source[self.start + 3..self.current];
+                        let text = String::from_utf8_lossy(text).trim().to_string();
+                        self.add_token_with_literal(DOC_COMMENT, text);
+                    }
+                } else if self.next_match(b'*') {
+                    let line = self.line;
+                    self.block_comment();
+                    self.add_comment(line);
+                } else if self.lox.extensions && self.next_match(b'=') {
+                    self.add_token(SLASH_EQUAL)
                 } else {
                     self.add_token(SLASH)
                 };
             }
             b' ' | b'\t' | b'\r' => {}
-            b'\n' => self.line += 1,
-            b'"' => self.add_string(),
+            b'\n' => {
+                if self.lox.asi {
+                    self.tokens.push(Token {
+                        column: self.column(self.start),
+                        span: self.start..self.current,
+                        ..Token::new(NEWLINE, b"", "null".into(), self.line)
+                    });
+                }
+                self.line += 1
+            }
+            b'"' => {
+                if self.peek() == b'"' && self.peek_next() == b'"' {
+                    self.current += 2;
+                    self.add_raw_string()
+                } else {
+                    self.add_string()
+                }
+            }
             b'0'..=b'9' => self.add_number(),
             b'a'..=b'z' | b'A'..=b'Z' | b'_' => self.add_identifier_or_reserved_words(),
-            ch => {
-                self.lox
-                    .report(self.line, "Unexpected character: ", (ch as char).into())
-            }
+            ch if ch.is_ascii() => self.lox.report(
+                self.line,
+                self.column(self.start),
+                Some(self.start..self.current),
+                "",
+                Code::UnexpectedCharacter,
+                &[&(ch as char)],
+            ),
+            // A multi-byte character: consume all of it, so it is reported
+            // once and as itself rather than byte by byte.
+            _ => match self.char_at(self.start) {
+                Some((ch, len)) => {
+                    self.current = self.start + len;
+                    if self.is_identifier_char(ch) && ch.is_alphabetic() {
+                        self.add_identifier_or_reserved_words()
+                    } else {
+                        self.lox.report(
+                            self.line,
+                            self.column(self.start),
+                            Some(self.start..self.current),
+                            "",
+                            Code::UnexpectedCharacter,
+                            &[&ch],
+                        )
+                    }
+                }
+                None => self.lox.report(
+                    self.line,
+                    self.column(self.start),
+                    Some(self.start..self.current),
+                    "",
+                    Code::UnexpectedCharacter,
+                    &[&char::REPLACEMENT_CHARACTER],
+                ),
+            },
+        }
+    }
+}
+
+/// Options named by `// lox: a, b` directive comments in the leading trivia
+/// of `source`, i.e. among the blank lines and line comments before the first
+/// token, with the line each one is on.
+pub(crate) fn directive_comments(source: &str) -> Vec<(usize, &str)> {
+    let mut options = vec![];
+    for (index, line) in source.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+        let Some(comment) = line.strip_prefix("//") else {
+            break;
+        };
+        if let Some(directive) = comment.trim_start().strip_prefix("lox:") {
+            options.extend(
+                directive
+                    .split(',')
+                    .map(str::trim)
+                    .filter(|option| !option.is_empty())
+                    .map(|option| (index + 1, option)),
+            );
         }
     }
+    options
 }
@@ -0,0 +1,70 @@
+//! Interned strings. Identifier names and string literals are stored once
+//! in a per-thread table and referred to by a [`Symbol`], so variables are
+//! looked up by a `u32` and equal literals share one allocation.
+//!
+//! The table only grows; a program has few distinct names and literals, and
+//! interning one again returns the symbol it already has.
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fmt::{Debug, Display, Formatter};
+use std::rc::Rc;
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Symbol(u32);
+
+#[derive(Default)]
+struct Interner {
+    symbols: HashMap<Rc<str>, Symbol>,
+    strings: Vec<Rc<str>>,
+}
+
+thread_local! {
+    static INTERNER: RefCell<Interner> = RefCell::default();
+}
+
+impl Symbol {
+    pub fn intern(text: &str) -> Symbol {
+        INTERNER.with_borrow_mut(|interner| {
+            if let Some(&symbol) = interner.symbols.get(text) {
+                return symbol;
+            }
+            let symbol = Symbol(interner.strings.len() as u32);
+            let text: Rc<str> = text.into();
+            interner.strings.push(text.clone());
+            interner.symbols.insert(text, symbol);
+            symbol
+        })
+    }
+
+    /// The interned text. Every call returns the same allocation, so `==`
+    /// on two of them is decided by comparing pointers.
+    pub fn text(self) -> Rc<str> {
+        INTERNER.with_borrow(|interner| interner.strings[self.0 as usize].clone())
+    }
+}
+
+impl Display for Symbol {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.text())
+    }
+}
+
+impl Debug for Symbol {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:?}", self.text())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_interning_the_same_text_shares_one_symbol() {
+        assert!(Symbol::intern("name") == Symbol::intern("name"));
+        assert!(Symbol::intern("name") != Symbol::intern("other"));
+        let (a, b) = (Symbol::intern("name").text(), Symbol::intern("name").text());
+        assert!(Rc::ptr_eq(&a, &b));
+    }
+}
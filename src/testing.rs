@@ -0,0 +1,46 @@
+//! Helpers shared by the unit tests.
+
+use std::env;
+use std::fs;
+use std::ops::Deref;
+use std::path::{Path, PathBuf};
+
+use crate::outcome::{RunOutcome, Stream};
+
+/// A scratch directory that is removed when dropped, so a failing test
+/// does not leave it behind.
+pub struct TempDir(PathBuf);
+
+impl Deref for TempDir {
+    type Target = Path;
+
+    fn deref(&self) -> &Path {
+        &self.0
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.0);
+    }
+}
+
+/// An empty directory for the test `name`; the process id keeps
+/// concurrent test runs apart.
+pub fn temp_dir(name: &str) -> TempDir {
+    let path = env::temp_dir().join(format!("lox-{}-{}", name, std::process::id()));
+    let _ = fs::remove_dir_all(&path);
+    fs::create_dir_all(&path).unwrap();
+    TempDir(path)
+}
+
+/// The lines `outcome` wrote to stdout.
+pub fn stdout_lines(outcome: &RunOutcome) -> Vec<&str> {
+    outcome
+        .output
+        .lines()
+        .iter()
+        .filter(|(stream, _)| *stream == Stream::Stdout)
+        .map(|(_, line)| line.as_str())
+        .collect()
+}
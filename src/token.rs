@@ -1,13 +1,18 @@
 use std::fmt::{Display, Formatter};
+use std::ops::Range;
+
+use crate::json::Json;
 
 #[derive(Debug, PartialEq, Copy, Clone)]
-#[allow(non_camel_case_types, dead_code)]
+#[allow(non_camel_case_types, dead_code, clippy::upper_case_acronyms)]
 pub enum TokenType {
     // Single-character tokens
     LEFT_PAREN,
     RIGHT_PAREN,
     LEFT_BRACE,
     RIGHT_BRACE,
+    LEFT_BRACKET,
+    RIGHT_BRACKET,
     COMMA,
     DOT,
     MINUS,
@@ -15,6 +20,14 @@ pub enum TokenType {
     SEMICOLON,
     SLASH,
     STAR,
+    QUESTION,
+    COLON,
+    /// Starts a preprocessor directive; only scanned with extensions enabled.
+    HASH,
+    AMPERSAND,
+    PIPE,
+    CARET,
+    TILDE,
 
     // One or two character tokens
     BANG,
@@ -25,6 +38,14 @@ pub enum TokenType {
     GREATER_EQUAL,
     LESS,
     LESS_EQUAL,
+    PLUS_EQUAL,
+    MINUS_EQUAL,
+    STAR_EQUAL,
+    SLASH_EQUAL,
+    QUESTION_QUESTION,
+    LESS_LESS,
+    GREATER_GREATER,
+    ELLIPSIS,
 
     // Literals
     IDENTIFIER,
@@ -49,67 +70,401 @@ pub enum TokenType {
     VAR,
     WHILE,
 
+    // Extension keywords, only reserved when extensions are enabled
+    EPRINT,
+    SWITCH,
+    CASE,
+    DEFAULT,
+    DO,
+    TRY,
+    CATCH,
+    FINALLY,
+    THROW,
+
+    // Line break, only scanned in automatic semicolon insertion mode
+    NEWLINE,
+    /// A `///` comment, only scanned with extensions enabled.
+    DOC_COMMENT,
+
     // End of file
     EOF,
 }
 
-const fn create_keywords() -> [(&'static str, TokenType); 16] {
-    [
-        ("and", TokenType::AND),
-        ("class", TokenType::CLASS),
-        ("else", TokenType::ELSE),
-        ("false", TokenType::FALSE),
-        ("for", TokenType::FOR),
-        ("fun", TokenType::FUN),
-        ("if", TokenType::IF),
-        ("nil", TokenType::NIL),
-        ("or", TokenType::OR),
-        ("print", TokenType::PRINT),
-        ("return", TokenType::RETURN),
-        ("super", TokenType::SUPER),
-        ("this", TokenType::THIS),
-        ("true", TokenType::TRUE),
-        ("var", TokenType::VAR),
-        ("while", TokenType::WHILE),
-    ]
-}
-
-const KEYWORDS: [(&str, TokenType); 16] = create_keywords();
+/// Broad classes of tokens, as listed in [`TOKENS`].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Category {
+    Punctuation,
+    Operator,
+    Assignment,
+    Literal,
+    Identifier,
+    Keyword,
+    /// Keywords reserved only when extensions are enabled.
+    ExtensionKeyword,
+    Trivia,
+}
+
+/// Binding strength of an infix operator, loosest first.
+#[derive(Debug, Clone, Copy, PartialEq, PartialOrd)]
+pub enum Precedence {
+    Comma,
+    Assignment,
+    Conditional,
+    Coalesce,
+    Equality,
+    BitwiseOr,
+    BitwiseXor,
+    BitwiseAnd,
+    Comparison,
+    Shift,
+    Term,
+    Factor,
+}
+
+impl Precedence {
+    /// The left-associative binary levels, loosest first; the operand of the
+    /// last one is a unary expression.
+    pub const BINARY_LEVELS: [Precedence; 9] = [
+        Precedence::Coalesce,
+        Precedence::Equality,
+        Precedence::BitwiseOr,
+        Precedence::BitwiseXor,
+        Precedence::BitwiseAnd,
+        Precedence::Comparison,
+        Precedence::Shift,
+        Precedence::Term,
+        Precedence::Factor,
+    ];
+
+    /// Name of the grammar rule parsing this level.
+    pub fn rule(self) -> &'static str {
+        match self {
+            Precedence::Comma => "comma",
+            Precedence::Assignment => "assignment",
+            Precedence::Conditional => "conditional",
+            Precedence::Coalesce => "coalesce",
+            Precedence::Equality => "equality",
+            Precedence::BitwiseOr => "bitOr",
+            Precedence::BitwiseXor => "bitXor",
+            Precedence::BitwiseAnd => "bitAnd",
+            Precedence::Comparison => "comparison",
+            Precedence::Shift => "shift",
+            Precedence::Term => "term",
+            Precedence::Factor => "factor",
+        }
+    }
+}
+
+use Category::*;
+use Precedence::{
+    BitwiseAnd, BitwiseOr, BitwiseXor, Coalesce, Comparison, Conditional, Equality,
+    Factor, Shift, Term,
+};
+use TokenType::*;
+
+/// Every token type in declaration order: its fixed spelling (empty when the
+/// lexeme varies), category, infix precedence and whether it is also a
+/// prefix operator. Keyword lookup, the predicates on [`TokenType`] and the
+/// `grammar` dump are all derived from this table.
+#[rustfmt::skip]
+const TOKENS: [(TokenType, &str, Category, Option<Precedence>, bool); 67] = [
+    (LEFT_PAREN, "(", Punctuation, None, false),
+    (RIGHT_PAREN, ")", Punctuation, None, false),
+    (LEFT_BRACE, "{", Punctuation, None, false),
+    (RIGHT_BRACE, "}", Punctuation, None, false),
+    (LEFT_BRACKET, "[", Punctuation, None, false),
+    (RIGHT_BRACKET, "]", Punctuation, None, false),
+    (COMMA, ",", Operator, Some(Precedence::Comma), false),
+    (DOT, ".", Punctuation, None, false),
+    (MINUS, "-", Operator, Some(Term), true),
+    (PLUS, "+", Operator, Some(Term), false),
+    (SEMICOLON, ";", Punctuation, None, false),
+    (SLASH, "/", Operator, Some(Factor), false),
+    (STAR, "*", Operator, Some(Factor), false),
+    (QUESTION, "?", Operator, Some(Conditional), false),
+    (COLON, ":", Punctuation, None, false),
+    (HASH, "#", Punctuation, None, false),
+    (AMPERSAND, "&", Operator, Some(BitwiseAnd), false),
+    (PIPE, "|", Operator, Some(BitwiseOr), false),
+    (CARET, "^", Operator, Some(BitwiseXor), false),
+    (TILDE, "~", Operator, None, true),
+    (BANG, "!", Operator, None, true),
+    (BANG_EQUAL, "!=", Operator, Some(Equality), false),
+    (EQUAL, "=", Assignment, Some(Precedence::Assignment), false),
+    (EQUAL_EQUAL, "==", Operator, Some(Equality), false),
+    (GREATER, ">", Operator, Some(Comparison), false),
+    (GREATER_EQUAL, ">=", Operator, Some(Comparison), false),
+    (LESS, "<", Operator, Some(Comparison), false),
+    (LESS_EQUAL, "<=", Operator, Some(Comparison), false),
+    (PLUS_EQUAL, "+=", Assignment, Some(Precedence::Assignment), false),
+    (MINUS_EQUAL, "-=", Assignment, Some(Precedence::Assignment), false),
+    (STAR_EQUAL, "*=", Assignment, Some(Precedence::Assignment), false),
+    (SLASH_EQUAL, "/=", Assignment, Some(Precedence::Assignment), false),
+    (QUESTION_QUESTION, "??", Operator, Some(Coalesce), false),
+    (LESS_LESS, "<<", Operator, Some(Shift), false),
+    (GREATER_GREATER, ">>", Operator, Some(Shift), false),
+    (ELLIPSIS, "...", Punctuation, None, false),
+    (IDENTIFIER, "", Identifier, None, false),
+    (STRING, "", Literal, None, false),
+    (NUMBER, "", Literal, None, false),
+    (AND, "and", Keyword, None, false),
+    (CLASS, "class", Keyword, None, false),
+    (ELSE, "else", Keyword, None, false),
+    (FALSE, "false", Keyword, None, false),
+    (FUN, "fun", Keyword, None, false),
+    (FOR, "for", Keyword, None, false),
+    (IF, "if", Keyword, None, false),
+    (NIL, "nil", Keyword, None, false),
+    (OR, "or", Keyword, None, false),
+    (PRINT, "print", Keyword, None, false),
+    (RETURN, "return", Keyword, None, false),
+    (SUPER, "super", Keyword, None, false),
+    (THIS, "this", Keyword, None, false),
+    (TRUE, "true", Keyword, None, false),
+    (VAR, "var", Keyword, None, false),
+    (WHILE, "while", Keyword, None, false),
+    (EPRINT, "eprint", ExtensionKeyword, None, false),
+    (SWITCH, "switch", ExtensionKeyword, None, false),
+    (CASE, "case", ExtensionKeyword, None, false),
+    (DEFAULT, "default", ExtensionKeyword, None, false),
+    (DO, "do", ExtensionKeyword, None, false),
+    (TRY, "try", ExtensionKeyword, None, false),
+    (CATCH, "catch", ExtensionKeyword, None, false),
+    (FINALLY, "finally", ExtensionKeyword, None, false),
+    (THROW, "throw", ExtensionKeyword, None, false),
+    (NEWLINE, "", Trivia, None, false),
+    (DOC_COMMENT, "", Trivia, None, false),
+    (EOF, "", Trivia, None, false),
+];
+
+impl TokenType {
+    fn entry(
+        self,
+    ) -> &'static (TokenType, &'static str, Category, Option<Precedence>, bool) {
+        &TOKENS[self as usize]
+    }
+
+    /// Every token type, in declaration order.
+    pub fn all() -> impl Iterator<Item = TokenType> {
+        TOKENS.iter().map(|entry| entry.0)
+    }
+
+    /// The fixed source spelling, or `""` for identifiers, literals and trivia.
+    pub fn spelling(self) -> &'static str {
+        self.entry().1
+    }
+
+    pub fn category(self) -> Category {
+        self.entry().2
+    }
+
+    /// Keywords reserved in every dialect; see [`Category::ExtensionKeyword`].
+    #[allow(dead_code)]
+    pub fn is_keyword(self) -> bool {
+        self.category() == Keyword
+    }
+
+    #[allow(dead_code)]
+    pub fn is_literal(self) -> bool {
+        self.category() == Literal
+    }
+
+    pub fn is_assignment_operator(self) -> bool {
+        self.category() == Assignment
+    }
+
+    /// Operators that build an `Expr::Binary`: the comma and the
+    /// left-associative levels in [`Precedence::BINARY_LEVELS`].
+    #[allow(dead_code)]
+    pub fn is_binary_operator(self) -> bool {
+        self.precedence().is_some_and(|precedence| {
+            precedence == Precedence::Comma
+                || Precedence::BINARY_LEVELS.contains(&precedence)
+        })
+    }
+
+    pub fn is_unary_operator(self) -> bool {
+        self.entry().4
+    }
+
+    /// Tokens the scanner only produces with extensions enabled, so the
+    /// plain grammar leaves them out.
+    pub fn requires_extensions(self) -> bool {
+        matches!(
+            self,
+            LEFT_BRACKET
+                | RIGHT_BRACKET
+                | HASH
+                | QUESTION
+                | COLON
+                | AMPERSAND
+                | PIPE
+                | CARET
+                | TILDE
+                | LESS_LESS
+                | GREATER_GREATER
+                | PLUS_EQUAL
+                | MINUS_EQUAL
+                | STAR_EQUAL
+                | SLASH_EQUAL
+                | QUESTION_QUESTION
+                | ELLIPSIS
+        )
+    }
+
+    /// Infix binding strength, `None` for tokens that are never infix.
+    pub fn precedence(self) -> Option<Precedence> {
+        self.entry().3
+    }
+
+    /// The arithmetic operator applied by a compound assignment like `+=`.
+    pub fn compound_operator(self) -> Option<TokenType> {
+        match self {
+            PLUS_EQUAL => Some(PLUS),
+            MINUS_EQUAL => Some(MINUS),
+            STAR_EQUAL => Some(STAR),
+            SLASH_EQUAL => Some(SLASH),
+            _ => None,
+        }
+    }
+}
+
+fn find_keyword(category: Category, keyword: &str) -> Option<TokenType> {
+    TOKENS
+        .iter()
+        .find(|entry| entry.2 == category && entry.1 == keyword)
+        .map(|entry| entry.0)
+}
 
 pub fn try_get_keyword(keyword: &str) -> Option<TokenType> {
-    KEYWORDS
-        .into_iter()
-        .find(|&(key, _)| key == keyword)
-        .map(|(_, token_type)| token_type)
+    find_keyword(Keyword, keyword)
+}
+
+/// Extension keywords that are never reserved by the scanner. They scan as
+/// identifiers and only act as keywords in the grammar position that
+/// introduces them, so enabling extensions cannot break a program that
+/// already uses these names.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SoftKeyword {
+    Const,
+    Import,
+}
+
+impl SoftKeyword {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            SoftKeyword::Const => "const",
+            SoftKeyword::Import => "import",
+        }
+    }
 }
 
+pub fn try_get_extension_keyword(keyword: &str) -> Option<TokenType> {
+    find_keyword(ExtensionKeyword, keyword)
+}
+
+#[derive(Clone)]
 #[allow(dead_code)]
-pub struct Token<'a> {
+pub struct Token {
     pub(crate) token_type: TokenType,
-    pub(crate) lexeme: &'a [u8],
+    pub(crate) lexeme: Vec<u8>,
     pub(crate) literal: String,
     pub(crate) line: usize,
+    /// 1-based character offset of the token within its line; 0 for tokens
+    /// that were not scanned from source.
+    pub(crate) column: usize,
+    /// Byte offsets of the lexeme in the source; empty for tokens that were
+    /// not scanned from source.
+    pub(crate) span: Range<usize>,
 }
 
-impl<'a> Token<'a> {
+impl Token {
     pub fn new(
         token_type: TokenType,
-        lexeme: &'a [u8],
+        lexeme: &[u8],
         literal: String,
         line: usize,
     ) -> Self {
         Token {
             token_type,
-            lexeme,
+            lexeme: lexeme.to_vec(),
             literal,
             line,
+            column: 0,
+            span: 0..0,
         }
     }
+
+    /// The literal as `tokenize` prints it, `None` where it prints `null`.
+    fn shown_literal(&self) -> Option<String> {
+        match self.token_type {
+            TokenType::NUMBER => Some(normalize_number(&self.literal)),
+            TokenType::STRING => Some(self.literal.clone()),
+            _ => (self.literal != "null").then(|| self.literal.clone()),
+        }
+    }
+
+    pub fn to_json(&self) -> Json {
+        Json::Object(vec![
+            ("type", Json::String(format!("{:?}", self.token_type))),
+            (
+                "lexeme",
+                Json::String(String::from_utf8_lossy(&self.lexeme).into()),
+            ),
+            (
+                "literal",
+                self.shown_literal().map_or(Json::Null, Json::String),
+            ),
+            ("line", Json::Number(self.line as f64)),
+            ("column", Json::Number(self.column as f64)),
+        ])
+    }
+
+    /// A row under [`CSV_HEADER`]; a `null` literal is an empty field.
+    pub fn to_csv_row(&self) -> String {
+        [
+            format!("{:?}", self.token_type),
+            String::from_utf8_lossy(&self.lexeme).into(),
+            self.shown_literal().unwrap_or_default(),
+            self.line.to_string(),
+            self.column.to_string(),
+        ]
+        .iter()
+        .map(|field| csv_field(field))
+        .collect::<Vec<String>>()
+        .join(",")
+    }
+}
+
+pub const CSV_HEADER: &str = "type,lexeme,literal,line,column";
+
+/// Quotes a CSV field when it holds a separator, quote or line break.
+fn csv_field(field: &str) -> String {
+    if field.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+/// Canonical spelling of a number literal: `1.50` becomes `1.5` and integral
+/// values keep a trailing `.0`.
+pub fn normalize_number(lexeme: &str) -> String {
+    let double = lexeme.parse::<f64>().unwrap();
+    if double.fract() == 0.0 {
+        format!("{:.1}", double)
+    } else {
+        format!("{}", double)
+    }
 }
 
-impl<'a> Display for Token<'a> {
+impl Display for Token {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
-        let lexeme_str = String::from_utf8_lossy(self.lexeme);
+        let lexeme_str = String::from_utf8_lossy(&self.lexeme);
+        if self.token_type == TokenType::NUMBER {
+            let literal = normalize_number(&self.literal);
+            return write!(f, "{:?} {} {}", self.token_type, lexeme_str, literal);
+        }
         write!(f, "{:?} {} {}", self.token_type, lexeme_str, self.literal)
     }
 }
@@ -118,10 +473,26 @@ impl<'a> Display for Token<'a> {
 mod tests {
     use super::*;
 
+    #[test]
+    fn test_token_table_follows_declaration_order() {
+        for (index, token_type) in TokenType::all().enumerate() {
+            assert_eq!(token_type as usize, index, "{:?}", token_type);
+        }
+        assert_eq!(TokenType::all().last(), Some(EOF));
+    }
+
     #[test]
     fn test_token() {
         let t = Token::new(TokenType::LEFT_PAREN, &[40], "null".into(), 0);
 
         println!("{}", t);
     }
+
+    #[test]
+    fn test_number_literal_keeps_source_spelling() {
+        let t = Token::new(TokenType::NUMBER, b"1.50", "1.50".into(), 1);
+
+        assert_eq!(t.literal, "1.50");
+        assert_eq!(t.to_string(), "NUMBER 1.50 1.5");
+    }
 }
@@ -0,0 +1,204 @@
+//! Finding the programs under a directory, for `fmt` and `check`.
+//!
+//! A `.loxignore` file at the top of the directory lists paths to leave
+//! out, one pattern per line, in a subset of `.gitignore` syntax:
+//!
+//! - blank lines and lines starting with `#` are skipped;
+//! - `*` matches within one path component, `?` one character of it, and
+//!   `**` any number of components;
+//! - a pattern ending in `/` only matches directories;
+//! - a pattern containing another `/` matches the path from the top of the
+//!   directory, and any other pattern matches a name at any depth.
+//!
+//! Negated `!` patterns are not supported.
+
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// Name of the file listing paths to leave out.
+pub const IGNORE_FILE: &str = ".loxignore";
+
+/// The `.lox` files under `root`, sorted by path, leaving out what its
+/// `.loxignore` matches. Symbolic links are not followed.
+pub fn lox_files(root: &Path) -> io::Result<Vec<PathBuf>> {
+    let ignore = match fs::read_to_string(root.join(IGNORE_FILE)) {
+        Ok(text) => Ignore::parse(&text),
+        Err(error) if error.kind() == io::ErrorKind::NotFound => Ignore::default(),
+        Err(error) => return Err(error),
+    };
+    let mut files = vec![];
+    visit(root, Path::new(""), &ignore, &mut files)?;
+    files.sort();
+    Ok(files)
+}
+
+fn visit(
+    root: &Path,
+    relative: &Path,
+    ignore: &Ignore,
+    files: &mut Vec<PathBuf>,
+) -> io::Result<()> {
+    for entry in fs::read_dir(root.join(relative))? {
+        let entry = entry?;
+        let path = relative.join(entry.file_name());
+        let file_type = entry.file_type()?;
+        if ignore.matches(&path, file_type.is_dir()) {
+            continue;
+        }
+        if file_type.is_dir() {
+            visit(root, &path, ignore, files)?;
+        } else if file_type.is_file() && path.extension().is_some_and(|ext| ext == "lox")
+        {
+            files.push(root.join(path));
+        }
+    }
+    Ok(())
+}
+
+/// The patterns of a `.loxignore` file.
+#[derive(Debug, Default)]
+struct Ignore {
+    patterns: Vec<Pattern>,
+}
+
+#[derive(Debug)]
+struct Pattern {
+    /// The components of the glob, split on `/`.
+    components: Vec<String>,
+    /// Matched against the whole relative path rather than the name.
+    anchored: bool,
+    directories_only: bool,
+}
+
+impl Ignore {
+    fn parse(text: &str) -> Self {
+        let patterns = text
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty() && !line.starts_with('#'))
+            .map(|line| {
+                let directories_only = line.ends_with('/');
+                let line = line.trim_end_matches('/');
+                let anchored = line.contains('/');
+                Pattern {
+                    components: line
+                        .trim_start_matches('/')
+                        .split('/')
+                        .map(String::from)
+                        .collect(),
+                    anchored,
+                    directories_only,
+                }
+            })
+            .collect();
+        Ignore { patterns }
+    }
+
+    /// Whether `path`, relative to the top of the directory, is left out.
+    fn matches(&self, path: &Path, is_dir: bool) -> bool {
+        let components: Vec<String> = path
+            .components()
+            .map(|component| component.as_os_str().to_string_lossy().into_owned())
+            .collect();
+        self.patterns.iter().any(|pattern| {
+            if pattern.directories_only && !is_dir {
+                return false;
+            }
+            if pattern.anchored {
+                matches_path(&pattern.components, &components)
+            } else {
+                components
+                    .last()
+                    .is_some_and(|name| matches_name(&pattern.components[0], name))
+            }
+        })
+    }
+}
+
+/// Whether the glob components match the path components, `**` standing
+/// for any number of them.
+fn matches_path(pattern: &[String], path: &[String]) -> bool {
+    match pattern.split_first() {
+        None => path.is_empty(),
+        Some((first, rest)) if first == "**" => {
+            (0..=path.len()).any(|skip| matches_path(rest, &path[skip..]))
+        }
+        Some((first, rest)) => path.split_first().is_some_and(|(name, path)| {
+            matches_name(first, name) && matches_path(rest, path)
+        }),
+    }
+}
+
+/// Whether a glob with `*` and `?` matches one path component.
+fn matches_name(pattern: &str, name: &str) -> bool {
+    fn matches(pattern: &[char], name: &[char]) -> bool {
+        match pattern.split_first() {
+            None => name.is_empty(),
+            Some(('*', rest)) => {
+                (0..=name.len()).any(|skip| matches(rest, &name[skip..]))
+            }
+            Some(('?', rest)) => !name.is_empty() && matches(rest, &name[1..]),
+            Some((c, rest)) => name.first() == Some(c) && matches(rest, &name[1..]),
+        }
+    }
+    let pattern: Vec<char> = pattern.chars().collect();
+    let name: Vec<char> = name.chars().collect();
+    matches(&pattern, &name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::temp_dir;
+
+    #[test]
+    fn test_ignore_patterns_match_names_paths_and_directories() {
+        let ignore =
+            Ignore::parse("# generated\n*.gen.lox\n/build/\nvendor/**/old.lox\n");
+
+        assert!(ignore.matches(Path::new("a.gen.lox"), false));
+        assert!(ignore.matches(Path::new("src/b.gen.lox"), false));
+        assert!(ignore.matches(Path::new("build"), true));
+        assert!(!ignore.matches(Path::new("build"), false));
+        assert!(!ignore.matches(Path::new("src/build"), true));
+        assert!(ignore.matches(Path::new("vendor/old.lox"), false));
+        assert!(ignore.matches(Path::new("vendor/x/y/old.lox"), false));
+        assert!(!ignore.matches(Path::new("src/old.lox"), false));
+        assert!(!ignore.matches(Path::new("a.lox"), false));
+    }
+
+    #[test]
+    fn test_lox_files_are_found_recursively_in_order() {
+        let root = temp_dir("walk");
+        for dir in ["b", "a/deep", "skip"] {
+            fs::create_dir_all(root.join(dir)).unwrap();
+        }
+        for file in [
+            "z.lox",
+            "b/x.lox",
+            "a/deep/y.lox",
+            "skip/w.lox",
+            "notes.txt",
+        ] {
+            fs::write(root.join(file), "").unwrap();
+        }
+        fs::write(root.join(IGNORE_FILE), "skip/\n").unwrap();
+
+        let files = lox_files(&root);
+
+        let files: Vec<PathBuf> = files
+            .unwrap()
+            .iter()
+            .map(|file| file.strip_prefix(&*root).unwrap().to_path_buf())
+            .collect();
+        assert_eq!(
+            files,
+            [
+                PathBuf::from("a/deep/y.lox"),
+                PathBuf::from("b/x.lox"),
+                PathBuf::from("z.lox"),
+            ]
+        );
+    }
+}